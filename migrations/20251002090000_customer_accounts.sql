@@ -0,0 +1,6 @@
+-- Customer accounts: display name on users and order ownership
+ALTER TABLE users ADD COLUMN name TEXT;
+
+ALTER TABLE orders ADD COLUMN customer_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_orders_customer ON orders(customer_id);
@@ -0,0 +1,16 @@
+-- Store money as integer minor units (cents) instead of REAL
+DROP INDEX idx_products_price;
+
+ALTER TABLE products ADD COLUMN price_cents INTEGER NOT NULL DEFAULT 0 CHECK (price_cents >= 0);
+UPDATE products SET price_cents = CAST(ROUND(price * 100) AS INTEGER);
+ALTER TABLE products DROP COLUMN price;
+
+ALTER TABLE orders ADD COLUMN total_cents INTEGER NOT NULL DEFAULT 0;
+UPDATE orders SET total_cents = CAST(ROUND(total_amount * 100) AS INTEGER);
+ALTER TABLE orders DROP COLUMN total_amount;
+
+ALTER TABLE order_items ADD COLUMN price_cents INTEGER NOT NULL DEFAULT 0;
+UPDATE order_items SET price_cents = CAST(ROUND(price * 100) AS INTEGER);
+ALTER TABLE order_items DROP COLUMN price;
+
+CREATE INDEX idx_products_price ON products(price_cents);
@@ -0,0 +1,19 @@
+-- Server-side carts, keyed by a session token for guests or by customer
+CREATE TABLE carts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token TEXT NOT NULL UNIQUE,
+    customer_id INTEGER UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE cart_items (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    cart_id INTEGER NOT NULL REFERENCES carts(id) ON DELETE CASCADE,
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    UNIQUE (cart_id, product_id)
+);
+
+CREATE INDEX idx_carts_guest_updated ON carts(updated_at) WHERE customer_id IS NULL;
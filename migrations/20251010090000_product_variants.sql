@@ -0,0 +1,55 @@
+-- Option types shared across products (e.g. Size, Color) and their values
+CREATE TABLE option_types (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE option_values (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    option_type_id INTEGER NOT NULL REFERENCES option_types(id) ON DELETE CASCADE,
+    value TEXT NOT NULL,
+    position INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (option_type_id, value)
+);
+
+-- Sellable variants of a product, each with its own SKU, stock and optional price override
+CREATE TABLE product_variants (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    sku TEXT NOT NULL UNIQUE,
+    price_cents INTEGER CHECK (price_cents IS NULL OR price_cents >= 0),
+    stock_quantity INTEGER NOT NULL DEFAULT 0 CHECK (stock_quantity >= 0),
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE variant_option_values (
+    variant_id INTEGER NOT NULL REFERENCES product_variants(id) ON DELETE CASCADE,
+    option_value_id INTEGER NOT NULL REFERENCES option_values(id) ON DELETE CASCADE,
+    PRIMARY KEY (variant_id, option_value_id)
+);
+
+CREATE INDEX idx_product_variants_product ON product_variants(product_id);
+CREATE INDEX idx_variant_option_values_value ON variant_option_values(option_value_id);
+
+-- Cart lines may now point at a variant; a product and variant pair appears once per cart
+CREATE TABLE cart_items_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    cart_id INTEGER NOT NULL REFERENCES carts(id) ON DELETE CASCADE,
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    variant_id INTEGER REFERENCES product_variants(id) ON DELETE CASCADE,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+INSERT INTO cart_items_new (id, cart_id, product_id, quantity, created_at)
+SELECT id, cart_id, product_id, quantity, created_at FROM cart_items;
+
+DROP TABLE cart_items;
+ALTER TABLE cart_items_new RENAME TO cart_items;
+
+CREATE UNIQUE INDEX idx_cart_items_line ON cart_items(cart_id, product_id, IFNULL(variant_id, 0));
+
+-- Order lines record the variant that was bought
+ALTER TABLE order_items ADD COLUMN variant_id INTEGER REFERENCES product_variants(id);
@@ -0,0 +1,38 @@
+-- Discount codes. Percentage coupons carry percent_off, fixed ones amount_off_cents;
+-- free shipping coupons carry neither.
+CREATE TABLE coupons (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    code TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    kind TEXT NOT NULL CHECK (kind IN ('percentage', 'fixed', 'free_shipping')),
+    percent_off INTEGER CHECK (percent_off BETWEEN 1 AND 100),
+    amount_off_cents INTEGER CHECK (amount_off_cents > 0),
+    min_order_cents INTEGER NOT NULL DEFAULT 0 CHECK (min_order_cents >= 0),
+    expires_at TEXT,
+    max_uses INTEGER CHECK (max_uses > 0),
+    max_uses_per_customer INTEGER CHECK (max_uses_per_customer > 0),
+    active INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    CHECK (
+        (kind = 'percentage' AND percent_off IS NOT NULL AND amount_off_cents IS NULL)
+        OR (kind = 'fixed' AND amount_off_cents IS NOT NULL AND percent_off IS NULL)
+        OR (kind = 'free_shipping' AND percent_off IS NULL AND amount_off_cents IS NULL)
+    )
+);
+
+-- One row per order that used a coupon; usage limits are counted from here
+CREATE TABLE coupon_redemptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    coupon_id INTEGER NOT NULL REFERENCES coupons(id) ON DELETE CASCADE,
+    order_id INTEGER NOT NULL UNIQUE REFERENCES orders(id) ON DELETE CASCADE,
+    customer_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    customer_email TEXT NOT NULL COLLATE NOCASE,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX idx_coupon_redemptions_coupon ON coupon_redemptions(coupon_id);
+
+ALTER TABLE carts ADD COLUMN coupon_code TEXT;
+
+ALTER TABLE orders ADD COLUMN discount_cents INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE orders ADD COLUMN coupon_code TEXT;
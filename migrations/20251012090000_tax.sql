@@ -0,0 +1,47 @@
+-- Tax classes group categories that are taxed alike; products outside any
+-- category, or in a category without a class, use Standard
+CREATE TABLE tax_classes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+INSERT INTO tax_classes (id, name) VALUES (1, 'Standard');
+
+-- Rates per class and destination. A NULL region covers the whole country;
+-- a rate for the shipping region takes precedence over it.
+-- The rate is a percentage kept as decimal text, e.g. '20' or '8.875'.
+CREATE TABLE tax_rates (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tax_class_id INTEGER NOT NULL REFERENCES tax_classes(id) ON DELETE CASCADE,
+    country TEXT NOT NULL CHECK (length(country) = 2 AND country = upper(country)),
+    region TEXT COLLATE NOCASE,
+    name TEXT NOT NULL,
+    rate TEXT NOT NULL CHECK (CAST(rate AS REAL) BETWEEN 0 AND 100),
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE UNIQUE INDEX idx_tax_rates_destination ON tax_rates(tax_class_id, country, IFNULL(region, ''));
+
+ALTER TABLE categories ADD COLUMN tax_class_id INTEGER REFERENCES tax_classes(id) ON DELETE SET NULL;
+
+-- Orders keep the destination they were taxed for and a net/tax/gross breakdown.
+-- total_cents = subtotal_cents + tax_cents; existing orders were untaxed.
+ALTER TABLE orders ADD COLUMN shipping_country TEXT;
+ALTER TABLE orders ADD COLUMN shipping_region TEXT;
+ALTER TABLE orders ADD COLUMN prices_include_tax INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE orders ADD COLUMN subtotal_cents INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE orders ADD COLUMN tax_cents INTEGER NOT NULL DEFAULT 0;
+
+UPDATE orders SET subtotal_cents = total_cents;
+
+-- Line amounts after the line's share of any discount
+ALTER TABLE order_items ADD COLUMN subtotal_cents INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE order_items ADD COLUMN tax_cents INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE order_items ADD COLUMN total_cents INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE order_items ADD COLUMN tax_rate TEXT NOT NULL DEFAULT '0';
+
+UPDATE order_items
+SET subtotal_cents = price_cents * quantity,
+    total_cents = price_cents * quantity;
@@ -0,0 +1,24 @@
+-- Product weight drives weight-based shipping; variants ship at the product's weight
+ALTER TABLE products ADD COLUMN weight_grams INTEGER NOT NULL DEFAULT 0 CHECK (weight_grams >= 0);
+
+-- Shipping methods:
+--   flat          rate_cents per order
+--   weight_based  rate_cents plus per_kg_cents for every started kilogram
+--   free_over     free, offered once the goods reach threshold_cents
+CREATE TABLE shipping_methods (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('flat', 'weight_based', 'free_over')),
+    rate_cents INTEGER NOT NULL DEFAULT 0 CHECK (rate_cents >= 0),
+    per_kg_cents INTEGER NOT NULL DEFAULT 0 CHECK (per_kg_cents >= 0),
+    threshold_cents INTEGER NOT NULL DEFAULT 0 CHECK (threshold_cents >= 0),
+    active INTEGER NOT NULL DEFAULT 1,
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+-- The method name is copied so the order still reads right if the method changes
+ALTER TABLE orders ADD COLUMN shipping_method_id INTEGER REFERENCES shipping_methods(id) ON DELETE SET NULL;
+ALTER TABLE orders ADD COLUMN shipping_method TEXT;
+ALTER TABLE orders ADD COLUMN shipping_cents INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,19 @@
+-- Payment attempts against an order. Each attempt is one intent at the
+-- provider; provider_reference is the provider's id for it.
+CREATE TABLE payments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    provider_reference TEXT NOT NULL,
+    client_secret TEXT,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'captured', 'failed', 'refunded')),
+    amount_cents INTEGER NOT NULL CHECK (amount_cents >= 0),
+    refunded_cents INTEGER NOT NULL DEFAULT 0 CHECK (refunded_cents >= 0),
+    failure_reason TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    UNIQUE (provider, provider_reference)
+);
+
+CREATE INDEX idx_payments_order ON payments(order_id);
@@ -0,0 +1,19 @@
+-- Responses to mutating requests sent with an Idempotency-Key header, so a
+-- retried request gets the original response instead of running again.
+-- response_status is NULL while the first request is still in flight.
+CREATE TABLE idempotency_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    idempotency_key TEXT NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    request_body BLOB NOT NULL,
+    response_status INTEGER,
+    response_content_type TEXT,
+    response_body BLOB,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+-- Keys are scoped to the signed-in user; guests share one namespace
+CREATE UNIQUE INDEX idx_idempotency_keys_key ON idempotency_keys(idempotency_key, IFNULL(user_id, 0));
+CREATE INDEX idx_idempotency_keys_created ON idempotency_keys(created_at);
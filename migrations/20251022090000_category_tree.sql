@@ -0,0 +1,4 @@
+-- Categories nest under a parent; top-level categories have none
+ALTER TABLE categories ADD COLUMN parent_id INTEGER REFERENCES categories(id);
+
+CREATE INDEX idx_categories_parent ON categories(parent_id);
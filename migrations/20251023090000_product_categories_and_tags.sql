@@ -0,0 +1,44 @@
+-- Products can be filed under several categories. products.category_id stays
+-- as the main one, which decides the tax class, and is always among them.
+CREATE TABLE product_categories (
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    category_id INTEGER NOT NULL REFERENCES categories(id) ON DELETE CASCADE,
+    PRIMARY KEY (product_id, category_id)
+);
+
+CREATE INDEX idx_product_categories_category ON product_categories(category_id);
+
+INSERT INTO product_categories (product_id, category_id)
+SELECT id, category_id FROM products WHERE category_id IS NOT NULL;
+
+CREATE TRIGGER product_main_category_insert AFTER INSERT ON products
+WHEN new.category_id IS NOT NULL BEGIN
+    INSERT OR IGNORE INTO product_categories (product_id, category_id)
+    VALUES (new.id, new.category_id);
+END;
+
+-- Changing the main category moves the product out of the old one
+CREATE TRIGGER product_main_category_update AFTER UPDATE OF category_id ON products
+WHEN new.category_id IS NOT old.category_id BEGIN
+    DELETE FROM product_categories
+    WHERE product_id = new.id AND category_id = old.category_id;
+    INSERT OR IGNORE INTO product_categories (product_id, category_id)
+    SELECT new.id, new.category_id WHERE new.category_id IS NOT NULL;
+END;
+
+-- Free-form tags. The slug is the lowercased, hyphenated name used in URLs;
+-- names that differ only in case or punctuation are the same tag.
+CREATE TABLE tags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE TABLE product_tags (
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    PRIMARY KEY (product_id, tag_id)
+);
+
+CREATE INDEX idx_product_tags_tag ON product_tags(tag_id);
@@ -0,0 +1,11 @@
+-- Products move through draft -> published -> archived. Only published
+-- products are shown in the storefront; archived ones stay in the table so
+-- past orders can still refer to them.
+ALTER TABLE products ADD COLUMN status TEXT NOT NULL DEFAULT 'published'
+    CHECK (status IN ('draft', 'published', 'archived'));
+ALTER TABLE products ADD COLUMN deleted_at TEXT;
+
+CREATE INDEX idx_products_status ON products(status);
+
+-- Archived categories are hidden from the storefront until restored
+ALTER TABLE categories ADD COLUMN deleted_at TEXT;
@@ -0,0 +1,12 @@
+-- Order lines keep the product details they were bought with, so renaming,
+-- re-imaging or archiving a product never changes a past order. The unit
+-- price is already kept in price_cents.
+ALTER TABLE order_items ADD COLUMN product_name TEXT NOT NULL DEFAULT '';
+ALTER TABLE order_items ADD COLUMN sku TEXT;
+ALTER TABLE order_items ADD COLUMN image_url TEXT;
+
+-- Existing lines get the details as they are now, the best still known
+UPDATE order_items SET
+    product_name = IFNULL((SELECT name FROM products WHERE id = order_items.product_id), ''),
+    sku = (SELECT sku FROM product_variants WHERE id = order_items.variant_id),
+    image_url = (SELECT image_url FROM products WHERE id = order_items.product_id);
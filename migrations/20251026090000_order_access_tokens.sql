@@ -0,0 +1,5 @@
+-- Guest orders have no owner to check, so each gets an unguessable token
+-- that must accompany requests to view it
+ALTER TABLE orders ADD COLUMN access_token TEXT;
+
+CREATE UNIQUE INDEX idx_orders_access_token ON orders(access_token);
@@ -0,0 +1,23 @@
+-- Store a SHA-256 of each request body rather than the body itself, and give
+-- guests their own key namespace per session. The table only holds responses
+-- for retries, so it is recreated rather than migrated.
+DROP TABLE idempotency_keys;
+
+CREATE TABLE idempotency_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    idempotency_key TEXT NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    -- Random token kept in a guest's session; NULL for signed-in users
+    guest_token TEXT,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    request_hash TEXT NOT NULL,
+    response_status INTEGER,
+    response_content_type TEXT,
+    response_body BLOB,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE UNIQUE INDEX idx_idempotency_keys_key
+    ON idempotency_keys(idempotency_key, IFNULL(user_id, 0), IFNULL(guest_token, ''));
+CREATE INDEX idx_idempotency_keys_created ON idempotency_keys(created_at);
@@ -0,0 +1,26 @@
+-- Cancelling or refunding an order marks its captured payments
+-- 'refund_pending' in the same transaction; the refund at the provider is
+-- made after commit and retried until it goes through. SQLite can't alter a
+-- CHECK constraint, so the table is rebuilt.
+CREATE TABLE payments_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id INTEGER NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    provider_reference TEXT NOT NULL,
+    client_secret TEXT,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'captured', 'failed', 'refund_pending', 'refunded')),
+    amount_cents INTEGER NOT NULL CHECK (amount_cents >= 0),
+    refunded_cents INTEGER NOT NULL DEFAULT 0 CHECK (refunded_cents >= 0),
+    failure_reason TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+    UNIQUE (provider, provider_reference)
+);
+
+INSERT INTO payments_new SELECT * FROM payments;
+DROP TABLE payments;
+ALTER TABLE payments_new RENAME TO payments;
+
+CREATE INDEX idx_payments_order ON payments(order_id);
+CREATE INDEX idx_payments_refund_pending ON payments(status) WHERE status = 'refund_pending';
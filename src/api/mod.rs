@@ -0,0 +1,161 @@
+use actix_multipart::form::MultipartFormConfig;
+use actix_web::web;
+use crate::{errors::AppError, handlers, images, openapi};
+
+// Malformed bodies, paths and queries get the same error format as everything else
+pub fn extractor_config(cfg: &mut web::ServiceConfig) {
+    cfg
+        .app_data(web::JsonConfig::default().error_handler(|e, _| AppError::BadRequest(e.to_string()).into()))
+        .app_data(web::PathConfig::default().error_handler(|e, _| AppError::BadRequest(e.to_string()).into()))
+        .app_data(web::QueryConfig::default().error_handler(|e, _| AppError::BadRequest(e.to_string()).into()))
+        .app_data(
+            MultipartFormConfig::default()
+                .memory_limit(images::MAX_UPLOAD_REQUEST_BYTES)
+                .error_handler(|e, _| AppError::BadRequest(e.to_string()).into())
+        );
+}
+
+// Version 1 of the API, with paths relative to its scope. main.rs mounts it
+// at /api/v1 and, for clients written before versioning, directly at /api.
+pub fn v1(cfg: &mut web::ServiceConfig) {
+    cfg
+        // Auth
+        .route("/auth/register", web::post().to(handlers::auth::register))
+        .route("/auth/login", web::post().to(handlers::auth::login))
+        .route("/auth/logout", web::post().to(handlers::auth::logout))
+        .route("/auth/me", web::get().to(handlers::auth::me))
+        // Products
+        .route("/products", web::get().to(handlers::products::get_products))
+        .route("/products", web::post().to(handlers::products::create_product))
+        .route("/products/search", web::get().to(handlers::products::search_products))
+        .route("/products/{id}", web::get().to(handlers::products::get_product))
+        .route("/products/{id}", web::put().to(handlers::products::update_product))
+        .route("/products/{id}", web::delete().to(handlers::products::delete_product))
+        .route("/products/{id}/restore", web::post().to(handlers::products::restore_product))
+        .route("/products/{id}/variants", web::get().to(handlers::variants::get_product_variants))
+        .route("/products/{id}/variants", web::post().to(handlers::variants::create_variant))
+        .route("/products/{id}/images", web::get().to(handlers::images::get_product_images))
+        .route("/products/{id}/images", web::post().to(handlers::images::upload_product_image))
+        .route("/products/{id}/images/order", web::put().to(handlers::images::reorder_product_images))
+        .route("/products/{id}/images/{image_id}", web::delete().to(handlers::images::delete_product_image))
+        .route("/products/{id}/categories", web::get().to(handlers::categories::get_product_categories))
+        .route("/products/{id}/categories", web::put().to(handlers::categories::set_product_categories))
+        .route("/products/{id}/tags", web::get().to(handlers::tags::get_product_tags))
+        .route("/products/{id}/tags", web::put().to(handlers::tags::set_product_tags))
+        // Variants
+        .route("/option-types", web::get().to(handlers::variants::get_option_types))
+        .route("/option-types", web::post().to(handlers::variants::create_option_type))
+        .route("/option-types/{id}/values", web::post().to(handlers::variants::create_option_value))
+        .route("/variants/{id}", web::put().to(handlers::variants::update_variant))
+        .route("/variants/{id}", web::delete().to(handlers::variants::delete_variant))
+        // Categories
+        .route("/categories", web::get().to(handlers::categories::get_categories))
+        .route("/categories", web::post().to(handlers::categories::create_category))
+        .route("/categories/tree", web::get().to(handlers::categories::get_category_tree))
+        .route("/categories/archived", web::get().to(handlers::categories::get_archived_categories))
+        .route("/categories/{id}", web::put().to(handlers::categories::update_category))
+        .route("/categories/{id}", web::delete().to(handlers::categories::delete_category))
+        .route("/categories/{id}/restore", web::post().to(handlers::categories::restore_category))
+        .route("/categories/{id}/products", web::get().to(handlers::categories::get_category_products))
+        // Tags
+        .route("/tags", web::get().to(handlers::tags::list_tags))
+        .route("/tags/{slug}/products", web::get().to(handlers::tags::get_tag_products))
+        // Tax (admin)
+        .route("/tax-classes", web::get().to(handlers::tax::get_tax_classes))
+        .route("/tax-classes", web::post().to(handlers::tax::create_tax_class))
+        .route("/tax-classes/{id}", web::delete().to(handlers::tax::delete_tax_class))
+        .route("/tax-rates", web::get().to(handlers::tax::get_tax_rates))
+        .route("/tax-rates", web::post().to(handlers::tax::create_tax_rate))
+        .route("/tax-rates/{id}", web::put().to(handlers::tax::update_tax_rate))
+        .route("/tax-rates/{id}", web::delete().to(handlers::tax::delete_tax_rate))
+        // Shipping (admin)
+        .route("/shipping-methods", web::get().to(handlers::shipping::get_shipping_methods))
+        .route("/shipping-methods", web::post().to(handlers::shipping::create_shipping_method))
+        .route("/shipping-methods/{id}", web::put().to(handlers::shipping::update_shipping_method))
+        .route("/shipping-methods/{id}", web::delete().to(handlers::shipping::delete_shipping_method))
+        // Coupons
+        .route("/coupons", web::get().to(handlers::coupons::get_coupons))
+        .route("/coupons", web::post().to(handlers::coupons::create_coupon))
+        .route("/coupons/{id}", web::put().to(handlers::coupons::update_coupon))
+        .route("/coupons/{id}", web::delete().to(handlers::coupons::delete_coupon))
+        // Cart
+        .route("/cart", web::get().to(handlers::cart::get_cart))
+        .route("/cart", web::post().to(handlers::cart::add_to_cart))
+        .route("/cart/clear", web::post().to(handlers::cart::clear_cart))
+        .route("/cart/coupon", web::post().to(handlers::cart::apply_coupon))
+        .route("/cart/coupon", web::delete().to(handlers::cart::remove_coupon))
+        .route("/cart/shipping", web::get().to(handlers::shipping::quote_shipping))
+        .route("/cart/{id}", web::put().to(handlers::cart::update_cart_item))
+        .route("/cart/{id}", web::delete().to(handlers::cart::remove_from_cart))
+        // Orders
+        .route("/orders", web::post().to(handlers::orders::create_order))
+        .route("/orders", web::get().to(handlers::orders::get_orders))
+        .route("/orders/{id}", web::get().to(handlers::orders::get_order))
+        .route("/orders/{id}/status", web::patch().to(handlers::orders::update_order_status))
+        .route("/orders/{id}/cancel", web::post().to(handlers::orders::cancel_order))
+        .route("/orders/{id}/pay", web::post().to(handlers::payments::pay_order))
+        .route("/orders/{id}/payments", web::get().to(handlers::payments::get_order_payments))
+        .route("/me/orders", web::get().to(handlers::orders::get_my_orders))
+        // Payments
+        .route("/payments/webhook", web::post().to(handlers::payments::payment_webhook))
+        // Address book
+        .route("/me/addresses", web::get().to(handlers::addresses::get_my_addresses))
+        .route("/me/addresses", web::post().to(handlers::addresses::create_my_address))
+        .route("/me/addresses/{id}", web::put().to(handlers::addresses::update_my_address))
+        .route("/me/addresses/{id}", web::delete().to(handlers::addresses::delete_my_address))
+        // Docs
+        .route("/openapi.json", web::get().to(openapi::openapi_json))
+        .route("/docs", web::get().to(openapi::api_docs));
+}
+
+// Version 2 registers only the routes whose representation changed; the
+// rest fall through to v1 unchanged. Routes match in registration order, so
+// the v2 ones must come first.
+pub fn v2(cfg: &mut web::ServiceConfig) {
+    cfg
+        // Lists are paginated, with links to the neighbouring pages
+        .route("/products", web::get().to(handlers::products::get_products_page))
+        .route("/categories", web::get().to(handlers::categories::get_categories_page))
+        .route("/orders", web::get().to(handlers::orders::get_orders_page))
+        .route("/me/orders", web::get().to(handlers::orders::get_my_orders_page))
+        // Docs
+        .route("/openapi.json", web::get().to(openapi::openapi_json_v2));
+    v1(cfg);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test;
+    use crate::test_support::{test_app, TestDb};
+    
+    #[actix_web::test]
+    async fn each_version_serves_its_own_representation() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        for name in ["Hats", "Scarves", "Socks"] {
+            sqlx::query("INSERT INTO categories (name) VALUES (?1)")
+                .bind(name)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        let get = |uri: &str| test::call_and_read_body_json::<_, _, serde_json::Value>(
+            &app,
+            test::TestRequest::get().uri(uri).to_request(),
+        );
+        
+        // v1 lists everything as a bare array, and /api is the same as v1
+        let v1 = get("/api/v1/categories").await;
+        assert_eq!(v1.as_array().map(Vec::len), Some(3));
+        assert_eq!(get("/api/categories").await, v1);
+        
+        // v2 pages it
+        let v2 = get("/api/v2/categories?per_page=2").await;
+        assert_eq!(v2["items"].as_array().map(Vec::len), Some(2));
+        assert_eq!(v2["total"], 3);
+        assert_eq!(v2["next"], "/api/v2/categories?per_page=2&page=2");
+        
+        // Routes v2 doesn't change fall through to v1's handlers
+        assert_eq!(get("/api/v2/categories/tree").await, get("/api/v1/categories/tree").await);
+    }
+}
@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_session::{Session, SessionExt};
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use crate::{models::{User, UserRole}, errors::{Result, AppError}, AppState};
+
+// Session key holding the id of the signed-in user
+pub const SESSION_USER_KEY: &str = "user_id";
+
+// The ADMIN_PASSWORD shipped in .env.example, never accepted for a real account
+pub const PLACEHOLDER_ADMIN_PASSWORD: &str = "change-me";
+
+// Hash of a throwaway password, checked when a login names an unknown email
+// so that the response takes as long as for a registered one
+const DUMMY_PASSWORD_HASH: &str = "$2b$12$lnDR6AfaG4FUQczmHuL/neDUCtveWEofckXKVgrd0K2uWEvcy2cES";
+
+// Hash a password with bcrypt off the async worker thread
+pub async fn hash_password(password: String) -> Result<String> {
+    web::block(move || bcrypt::hash(password, bcrypt::DEFAULT_COST))
+        .await
+        .map_err(|_| AppError::InternalError)?
+        .map_err(|_| AppError::InternalError)
+}
+
+// Check a password against a stored bcrypt hash
+pub async fn verify_password(password: String, hash: String) -> Result<bool> {
+    web::block(move || bcrypt::verify(password, &hash))
+        .await
+        .map_err(|_| AppError::InternalError)?
+        .map_err(|_| AppError::InternalError)
+}
+
+// Check a login's password against the user's hash, or against a dummy hash
+// when there is no such user. Either way bcrypt runs once.
+pub async fn verify_login(password: String, user: Option<&User>) -> Result<bool> {
+    let hash = user.map_or(DUMMY_PASSWORD_HASH, |u| u.password_hash.as_str()).to_string();
+    let valid = verify_password(password, hash).await?;
+    Ok(valid && user.is_some())
+}
+
+// Create the bootstrap admin account if it doesn't exist yet. Creating it
+// takes an explicitly chosen password; blank or placeholder ones are refused.
+pub async fn ensure_admin(db: &sqlx::SqlitePool, email: &str, password: Option<&str>) -> Result<()> {
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE email = ?1)"
+    )
+    .bind(email)
+    .fetch_one(db)
+    .await?;
+    
+    if !exists {
+        let password = password
+            .filter(|p| !p.trim().is_empty() && *p != PLACEHOLDER_ADMIN_PASSWORD)
+            .ok_or_else(|| AppError::BadRequest(format!(
+                "ADMIN_PASSWORD must be set to a real password to create the admin account {}", email
+            )))?;
+        let password_hash = hash_password(password.to_string()).await?;
+        sqlx::query(
+            "INSERT INTO users (email, password_hash, role) VALUES (?1, ?2, 'admin')"
+        )
+        .bind(email)
+        .bind(&password_hash)
+        .execute(db)
+        .await?;
+        log::info!("Created admin user {}", email);
+    }
+    
+    Ok(())
+}
+
+pub fn current_user_id(session: &Session) -> Result<Option<i64>> {
+    session.get::<i64>(SESSION_USER_KEY)
+        .map_err(|_| AppError::SessionError)
+}
+
+// Any signed-in user. Rejects the request with 401 otherwise.
+pub struct AuthenticatedUser(pub User);
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self>>>>;
+    
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session = req.get_session();
+        let state = req.app_data::<web::Data<AppState>>().cloned();
+        
+        Box::pin(async move {
+            let state = state.ok_or(AppError::InternalError)?;
+            let user_id = current_user_id(&session)?.ok_or(AppError::Unauthorized)?;
+            
+            // Reload the user so deleted accounts and role changes take effect immediately
+            let user = sqlx::query_as::<_, User>(
+                "SELECT * FROM users WHERE id = ?1"
+            )
+            .bind(user_id)
+            .fetch_optional(&state.db)
+            .await?;
+            
+            match user {
+                Some(u) => Ok(AuthenticatedUser(u)),
+                None => {
+                    session.purge();
+                    Err(AppError::Unauthorized)
+                }
+            }
+        })
+    }
+}
+
+// A signed-in user with the admin role. Rejects with 401 when signed out
+// and 403 when signed in without admin rights.
+pub struct AdminUser(pub User);
+
+impl FromRequest for AdminUser {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self>>>>;
+    
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::from_request(req, payload);
+        
+        Box::pin(async move {
+            let AuthenticatedUser(user) = user.await?;
+            if user.role == UserRole::Admin {
+                Ok(AdminUser(user))
+            } else {
+                Err(AppError::Forbidden)
+            }
+        })
+    }
+}
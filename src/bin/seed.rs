@@ -1,5 +1,7 @@
 use sqlx::SqlitePool;
 use dotenv::dotenv;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal_macros::dec;
 use std::env;
 
 #[tokio::main]
@@ -38,32 +40,92 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     // Insert products
     let products = vec![
-        ("Laptop", "High-performance laptop", 999.99, 10, electronics_id),
-        ("Smartphone", "Latest smartphone model", 699.99, 15, electronics_id),
-        ("Headphones", "Wireless noise-canceling headphones", 199.99, 20, electronics_id),
-        ("T-Shirt", "Comfortable cotton t-shirt", 29.99, 50, clothing_id),
-        ("Jeans", "Classic denim jeans", 79.99, 30, clothing_id),
-        ("Sneakers", "Comfortable running shoes", 89.99, 25, clothing_id),
-        ("Programming Book", "Learn Rust programming", 49.99, 40, books_id),
-        ("Novel", "Bestselling fiction novel", 24.99, 35, books_id),
-        ("Cookbook", "Delicious recipes from around the world", 34.99, 20, books_id),
+        ("Laptop", "High-performance laptop", dec!(999.99), 10, 2000, electronics_id),
+        ("Smartphone", "Latest smartphone model", dec!(699.99), 15, 200, electronics_id),
+        ("Headphones", "Wireless noise-canceling headphones", dec!(199.99), 20, 300, electronics_id),
+        ("T-Shirt", "Comfortable cotton t-shirt", dec!(29.99), 50, 200, clothing_id),
+        ("Jeans", "Classic denim jeans", dec!(79.99), 30, 600, clothing_id),
+        ("Sneakers", "Comfortable running shoes", dec!(89.99), 25, 900, clothing_id),
+        ("Programming Book", "Learn Rust programming", dec!(49.99), 40, 700, books_id),
+        ("Novel", "Bestselling fiction novel", dec!(24.99), 35, 400, books_id),
+        ("Cookbook", "Delicious recipes from around the world", dec!(34.99), 20, 900, books_id),
     ];
     
-    for (name, desc, price, stock, category_id) in products {
-        sqlx::query(
-            "INSERT INTO products (name, description, price, stock_quantity, category_id) VALUES (?1, ?2, ?3, ?4, ?5)"
+    let mut tshirt_id = None;
+    for (name, desc, price, stock, weight_grams, category_id) in products {
+        // Prices are stored in cents
+        let price_cents = (price * dec!(100)).to_i64().expect("price out of range");
+        
+        let product_id = sqlx::query(
+            "INSERT INTO products (name, description, price_cents, stock_quantity, weight_grams, category_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
         )
         .bind(name)
         .bind(desc)
-        .bind(price)
+        .bind(price_cents)
         .bind(stock)
+        .bind(weight_grams)
         .bind(category_id)
         .execute(&pool)
-        .await?;
+        .await?
+        .last_insert_rowid();
         
+        if name == "T-Shirt" {
+            tshirt_id = Some(product_id);
+        }
         println!("Added product: {}", name);
     }
     
+    // Sizes for the t-shirt, XL a little dearer
+    let size_id = sqlx::query("INSERT INTO option_types (name) VALUES ('Size')")
+        .execute(&pool)
+        .await?
+        .last_insert_rowid();
+    
+    let sizes = vec![("S", None, 10), ("M", None, 15), ("L", None, 15), ("XL", Some(3299), 10)];
+    for (position, (size, price_cents, stock)) in sizes.into_iter().enumerate() {
+        let value_id = sqlx::query(
+            "INSERT INTO option_values (option_type_id, value, position) VALUES (?1, ?2, ?3)"
+        )
+        .bind(size_id)
+        .bind(size)
+        .bind(position as i64)
+        .execute(&pool)
+        .await?
+        .last_insert_rowid();
+        
+        let Some(tshirt_id) = tshirt_id else { continue };
+        let variant_id = sqlx::query(
+            "INSERT INTO product_variants (product_id, sku, price_cents, stock_quantity) VALUES (?1, ?2, ?3, ?4)"
+        )
+        .bind(tshirt_id)
+        .bind(format!("TSHIRT-{}", size))
+        .bind(price_cents)
+        .bind(stock)
+        .execute(&pool)
+        .await?
+        .last_insert_rowid();
+        
+        sqlx::query("INSERT INTO variant_option_values (variant_id, option_value_id) VALUES (?1, ?2)")
+            .bind(variant_id)
+            .bind(value_id)
+            .execute(&pool)
+            .await?;
+    }
+    println!("Added t-shirt sizes");
+    
+    // Shipping methods
+    sqlx::query(
+        r#"
+        INSERT INTO shipping_methods (name, kind, rate_cents, per_kg_cents, threshold_cents, position) VALUES
+            ('Standard', 'flat', 499, 0, 0, 0),
+            ('Express', 'weight_based', 999, 200, 0, 1),
+            ('Free shipping', 'free_over', 0, 0, 5000, 2)
+        "#
+    )
+    .execute(&pool)
+    .await?;
+    println!("Added shipping methods");
+    
     println!("Database seeded successfully!");
     
     Ok(())
@@ -1,4 +1,15 @@
-use actix_web::{error::ResponseError, http::StatusCode, HttpResponse};
+use std::collections::BTreeMap;
+
+use actix_web::{error::ResponseError, http::{header, StatusCode}, HttpResponse};
+use serde::Serialize;
+use sqlx::error::ErrorKind;
+use utoipa::ToSchema;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::request_id;
+
+// Media type for error bodies (RFC 7807)
+pub const PROBLEM_JSON: &str = "application/problem+json";
 
 #[derive(Debug, thiserror::Error)]
 #[allow(dead_code)]
@@ -12,6 +23,21 @@ pub enum AppError {
     #[error("Bad request: {0}")]
     BadRequest(String),
     
+    #[error("Unauthorized")]
+    Unauthorized,
+    
+    #[error("Forbidden")]
+    Forbidden,
+    
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    
+    #[error("Payment failed: {0}")]
+    PaymentFailed(String),
+    
+    #[error("Validation failed")]
+    Validation(#[from] validator::ValidationErrors),
+    
     #[error("Internal server error")]
     InternalError,
     
@@ -19,26 +45,202 @@ pub enum AppError {
     SessionError,
 }
 
+impl AppError {
+    // Stable identifier clients can branch on; also names the problem type
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound => "not_found",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized => "unauthorized",
+            AppError::Forbidden => "forbidden",
+            AppError::Conflict(_) => "conflict",
+            AppError::PaymentFailed(_) => "payment_failed",
+            AppError::Validation(_) => "validation_failed",
+            AppError::DatabaseError(e) => match constraint_kind(e) {
+                Some(ErrorKind::UniqueViolation) => "already_exists",
+                Some(ErrorKind::ForeignKeyViolation) => "invalid_reference",
+                Some(_) => "constraint_violation",
+                None => "internal_error",
+            },
+            AppError::InternalError => "internal_error",
+            AppError::SessionError => "session_error",
+        }
+    }
+    
+    // Human-readable explanation for the client. Server errors say nothing
+    // about their cause; that only goes to the log.
+    fn detail(&self) -> String {
+        match self {
+            AppError::NotFound => "The requested resource was not found".to_string(),
+            AppError::BadRequest(message)
+            | AppError::Conflict(message)
+            | AppError::PaymentFailed(message) => message.clone(),
+            AppError::Unauthorized => "You need to sign in to do that".to_string(),
+            AppError::Forbidden => "You are not allowed to do that".to_string(),
+            AppError::Validation(_) => "One or more fields are invalid".to_string(),
+            AppError::DatabaseError(e) => match constraint_kind(e) {
+                Some(ErrorKind::UniqueViolation) => match unique_field(e) {
+                    Some(field) => format!("A record with this {} already exists", field),
+                    None => "A record with these values already exists".to_string(),
+                },
+                Some(ErrorKind::ForeignKeyViolation) => {
+                    "A referenced record does not exist or is still in use".to_string()
+                },
+                Some(_) => "A value is missing or out of range".to_string(),
+                None => "An unexpected error occurred".to_string(),
+            },
+            AppError::InternalError | AppError::SessionError => "An unexpected error occurred".to_string(),
+        }
+    }
+}
+
+// Body of every error response: `type`, `title`, `status` and `detail` from
+// RFC 7807, plus the error `code`, the `request_id` and, for validation
+// failures, the messages by field
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: String,
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<BTreeMap<String, Vec<String>>>,
+}
+
 impl ResponseError for AppError {
     fn error_response(&self) -> HttpResponse {
         let status = self.status_code();
-        let message = self.to_string();
+        let code = self.code();
+        let request_id = request_id::current();
+        
+        if status.is_server_error() {
+            log::error!("Request {} failed: {}", request_id.as_deref().unwrap_or("-"), self);
+        }
+        
+        let problem = Problem {
+            problem_type: format!("/problems/{}", code.replace('_', "-")),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: code.to_string(),
+            request_id,
+            fields: match self {
+                AppError::Validation(errors) => Some(field_errors(errors)),
+                _ => None,
+            },
+        };
         
-        HttpResponse::build(status).json(serde_json::json!({
-            "error": message,
-            "status": status.as_u16()
-        }))
+        HttpResponse::build(status)
+            .insert_header((header::CONTENT_TYPE, PROBLEM_JSON))
+            .body(serde_json::to_string(&problem).unwrap_or_default())
     }
     
     fn status_code(&self) -> StatusCode {
         match self {
             AppError::NotFound => StatusCode::NOT_FOUND,
             AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
-            AppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PaymentFailed(_) => StatusCode::PAYMENT_REQUIRED,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::DatabaseError(e) => match constraint_kind(e) {
+                Some(ErrorKind::UniqueViolation) => StatusCode::CONFLICT,
+                Some(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                None => StatusCode::INTERNAL_SERVER_ERROR,
+            },
             AppError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::SessionError => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-pub type Result<T> = std::result::Result<T, AppError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, AppError>;
+
+// The constraint a database error violated, if it was a constraint failure
+fn constraint_kind(error: &sqlx::Error) -> Option<ErrorKind> {
+    match error {
+        sqlx::Error::Database(e) => match e.kind() {
+            ErrorKind::Other => None,
+            kind => Some(kind),
+        },
+        _ => None,
+    }
+}
+
+// SQLite names the columns in unique failures, e.g.
+// "UNIQUE constraint failed: categories.name"
+fn unique_field(error: &sqlx::Error) -> Option<String> {
+    let sqlx::Error::Database(e) = error else {
+        return None;
+    };
+    let columns = e.message().strip_prefix("UNIQUE constraint failed: ")?;
+    let fields: Vec<_> = columns.split(", ")
+        .map(|column| column.rsplit('.').next().unwrap_or(column))
+        .collect();
+    Some(fields.join(" and "))
+}
+
+// Messages by field, with nested fields and list items as dotted paths
+// (`items.0.quantity`)
+fn field_errors(errors: &ValidationErrors) -> BTreeMap<String, Vec<String>> {
+    let mut fields = BTreeMap::new();
+    collect_field_errors(errors, "", &mut fields);
+    fields
+}
+
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, fields: &mut BTreeMap<String, Vec<String>>) {
+    for (field, kind) in errors.errors() {
+        let path = format!("{}{}", prefix, field);
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                let messages = errors.iter().map(|e| match &e.message {
+                    Some(message) => message.to_string(),
+                    None => format!("is invalid ({})", e.code),
+                });
+                fields.entry(path).or_default().extend(messages);
+            },
+            ValidationErrorsKind::Struct(errors) => {
+                collect_field_errors(errors, &format!("{}.", path), fields);
+            },
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(errors, &format!("{}.{}.", path, index), fields);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDb;
+    
+    #[tokio::test]
+    async fn constraint_failures_map_to_client_errors() {
+        let db = TestDb::new().await;
+        let insert = || sqlx::query("INSERT INTO categories (name) VALUES ('Books')").execute(&db.pool);
+        insert().await.unwrap();
+        
+        let duplicate = AppError::from(insert().await.unwrap_err());
+        assert_eq!(duplicate.status_code(), StatusCode::CONFLICT);
+        assert_eq!(duplicate.code(), "already_exists");
+        assert_eq!(duplicate.detail(), "A record with this name already exists");
+        
+        let dangling = sqlx::query("INSERT INTO products (name, price_cents, category_id) VALUES ('Novel', 100, 999)")
+            .execute(&db.pool)
+            .await
+            .unwrap_err();
+        let dangling = AppError::from(dangling);
+        assert_eq!(dangling.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(dangling.code(), "invalid_reference");
+        
+        let hidden = AppError::from(sqlx::Error::PoolTimedOut);
+        assert_eq!(hidden.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(hidden.detail(), "An unexpected error occurred");
+    }
+}
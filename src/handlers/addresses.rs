@@ -0,0 +1,209 @@
+use actix_web::{web, HttpResponse};
+use sqlx::{SqliteConnection, SqlitePool};
+use validator::Validate;
+use crate::{
+    models::{Address, CreateSavedAddress, SavedAddress},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AuthenticatedUser,
+};
+
+// Get the signed-in customer's address book
+#[utoipa::path(
+    get,
+    path = "/me/addresses",
+    tag = "addresses",
+    responses(
+        (status = 200, description = "Saved addresses, default first", body = Vec<SavedAddress>),
+        (status = 401, description = "Not signed in", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_my_addresses(
+    customer: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let addresses = sqlx::query_as::<_, SavedAddress>(
+        "SELECT * FROM customer_addresses WHERE customer_id = ?1 ORDER BY is_default DESC, id"
+    )
+    .bind(customer.0.id)
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(addresses))
+}
+
+// Save an address to the signed-in customer's address book
+#[utoipa::path(
+    post,
+    path = "/me/addresses",
+    tag = "addresses",
+    request_body = CreateSavedAddress,
+    responses(
+        (status = 201, description = "Address saved", body = SavedAddress),
+        (status = 422, description = "Invalid address", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_my_address(
+    customer: AuthenticatedUser,
+    state: web::Data<AppState>,
+    address: web::Json<CreateSavedAddress>,
+) -> Result<HttpResponse> {
+    let address = address.into_inner();
+    address.validate()?;
+    let fields = address.address.validated()?;
+    
+    let mut tx = state.db.begin().await?;
+    if address.is_default {
+        clear_default(&mut tx, customer.0.id).await?;
+    }
+    
+    let address_id = sqlx::query(
+        r#"
+        INSERT INTO customer_addresses (customer_id, label, name, line1, line2, city, region,
+            postal_code, country, phone, is_default)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+        "#
+    )
+    .bind(customer.0.id)
+    .bind(&address.label)
+    .bind(&fields.name)
+    .bind(&fields.line1)
+    .bind(&fields.line2)
+    .bind(&fields.city)
+    .bind(&fields.region)
+    .bind(&fields.postal_code)
+    .bind(&fields.country)
+    .bind(&fields.phone)
+    .bind(address.is_default)
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+    
+    let created = sqlx::query_as::<_, SavedAddress>("SELECT * FROM customer_addresses WHERE id = ?1")
+        .bind(address_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    
+    Ok(HttpResponse::Created().json(created))
+}
+
+// Update an address in the signed-in customer's address book
+#[utoipa::path(
+    put,
+    path = "/me/addresses/{id}",
+    tag = "addresses",
+    params(("id" = i64, Path, description = "Address id")),
+    request_body = CreateSavedAddress,
+    responses(
+        (status = 200, description = "Address updated", body = SavedAddress),
+        (status = 422, description = "Invalid address", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 404, description = "No such address", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn update_my_address(
+    customer: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    address: web::Json<CreateSavedAddress>,
+) -> Result<HttpResponse> {
+    let address_id = path.into_inner();
+    let address = address.into_inner();
+    address.validate()?;
+    let fields = address.address.validated()?;
+    
+    let mut tx = state.db.begin().await?;
+    if address.is_default {
+        clear_default(&mut tx, customer.0.id).await?;
+    }
+    
+    let updated = sqlx::query_as::<_, SavedAddress>(
+        r#"
+        UPDATE customer_addresses
+        SET label = ?1, name = ?2, line1 = ?3, line2 = ?4, city = ?5, region = ?6,
+            postal_code = ?7, country = ?8, phone = ?9, is_default = ?10,
+            updated_at = datetime('now')
+        WHERE id = ?11 AND customer_id = ?12
+        RETURNING *
+        "#
+    )
+    .bind(&address.label)
+    .bind(&fields.name)
+    .bind(&fields.line1)
+    .bind(&fields.line2)
+    .bind(&fields.city)
+    .bind(&fields.region)
+    .bind(&fields.postal_code)
+    .bind(&fields.country)
+    .bind(&fields.phone)
+    .bind(address.is_default)
+    .bind(address_id)
+    .bind(customer.0.id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    tx.commit().await?;
+    
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+// Remove an address from the signed-in customer's address book
+#[utoipa::path(
+    delete,
+    path = "/me/addresses/{id}",
+    tag = "addresses",
+    params(("id" = i64, Path, description = "Address id")),
+    responses(
+        (status = 204, description = "Address deleted"),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 404, description = "No such address", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn delete_my_address(
+    customer: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let address_id = path.into_inner();
+    
+    let result = sqlx::query("DELETE FROM customer_addresses WHERE id = ?1 AND customer_id = ?2")
+        .bind(address_id)
+        .bind(customer.0.id)
+        .execute(&state.db)
+        .await?;
+    
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+// Look up one of a customer's saved addresses for checkout
+pub async fn find_saved_address(db: &SqlitePool, customer_id: i64, address_id: i64) -> Result<Address> {
+    let saved = sqlx::query_as::<_, SavedAddress>(
+        "SELECT * FROM customer_addresses WHERE id = ?1 AND customer_id = ?2"
+    )
+    .bind(address_id)
+    .bind(customer_id)
+    .fetch_optional(db)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    
+    Ok(saved.address)
+}
+
+async fn clear_default(conn: &mut SqliteConnection, customer_id: i64) -> Result<()> {
+    sqlx::query("UPDATE customer_addresses SET is_default = 0 WHERE customer_id = ?1 AND is_default = 1")
+        .bind(customer_id)
+        .execute(conn)
+        .await?;
+    
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use actix_session::Session;
+use actix_web::{web, HttpResponse};
+use validator::Validate;
+use crate::{
+    models::{new_password, not_blank, password_length, User},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::{hash_password, verify_login, AuthenticatedUser, SESSION_USER_KEY},
+    handlers::cart::merge_guest_cart,
+};
+
+#[derive(serde::Deserialize, Validate, utoipa::ToSchema)]
+pub struct RegisterRequest {
+    #[validate(custom(function = "not_blank"), length(max = 200, message = "must be at most 200 characters"))]
+    pub name: String,
+    #[validate(email(message = "must be a valid email address"))]
+    pub email: String,
+    #[validate(custom(function = "new_password"))]
+    pub password: String,
+}
+
+#[derive(serde::Deserialize, Validate, utoipa::ToSchema)]
+pub struct LoginRequest {
+    #[validate(email(message = "must be a valid email address"))]
+    pub email: String,
+    #[validate(custom(function = "password_length"))]
+    pub password: String,
+}
+
+// Register a customer account and sign it in
+#[utoipa::path(
+    post,
+    path = "/auth/register",
+    tag = "auth",
+    request_body = RegisterRequest,
+    responses(
+        (status = 201, description = "Account created and signed in", body = User),
+        (status = 400, description = "Email already registered", body = Problem),
+        (status = 422, description = "Invalid name, email or password", body = Problem),
+    ),
+)]
+pub async fn register(
+    session: Session,
+    state: web::Data<AppState>,
+    registration: web::Json<RegisterRequest>,
+) -> Result<HttpResponse> {
+    let mut registration = registration.into_inner();
+    registration.name = registration.name.trim().to_string();
+    registration.email = registration.email.trim().to_string();
+    registration.validate()?;
+    let RegisterRequest { name, email, password } = registration;
+    
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE email = ?1)"
+    )
+    .bind(&email)
+    .fetch_one(&state.db)
+    .await?;
+    
+    if exists {
+        return Err(AppError::BadRequest("Email is already registered".to_string()));
+    }
+    
+    let password_hash = hash_password(password).await?;
+    
+    let mut tx = state.db.begin().await?;
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        INSERT INTO users (email, password_hash, role, name)
+        VALUES (?1, ?2, 'customer', ?3)
+        RETURNING *
+        "#
+    )
+    .bind(&email)
+    .bind(&password_hash)
+    .bind(&name)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    
+    session.renew();
+    session.insert(SESSION_USER_KEY, user.id)
+        .map_err(|_| AppError::SessionError)?;
+    merge_guest_cart(&state.db, &session, user.id).await?;
+    
+    Ok(HttpResponse::Created().json(user))
+}
+
+// Log in
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Signed in", body = User),
+        (status = 401, description = "Wrong email or password", body = Problem),
+        (status = 422, description = "Malformed email or password", body = Problem),
+    ),
+)]
+pub async fn login(
+    session: Session,
+    state: web::Data<AppState>,
+    credentials: web::Json<LoginRequest>,
+) -> Result<HttpResponse> {
+    let mut credentials = credentials.into_inner();
+    credentials.email = credentials.email.trim().to_string();
+    credentials.validate()?;
+    
+    let user = sqlx::query_as::<_, User>(
+        "SELECT * FROM users WHERE email = ?1"
+    )
+    .bind(&credentials.email)
+    .fetch_optional(&state.db)
+    .await?;
+    
+    // Unknown emails still go through bcrypt, so timing doesn't tell them apart
+    let valid = verify_login(credentials.password, user.as_ref()).await?;
+    let user = user.filter(|_| valid).ok_or(AppError::Unauthorized)?;
+    
+    // Issue a fresh session on privilege change
+    session.renew();
+    session.insert(SESSION_USER_KEY, user.id)
+        .map_err(|_| AppError::SessionError)?;
+    merge_guest_cart(&state.db, &session, user.id).await?;
+    
+    Ok(HttpResponse::Ok().json(user))
+}
+
+// Log out
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    responses(
+        (status = 204, description = "Signed out"),
+    ),
+)]
+pub async fn logout(session: Session) -> Result<HttpResponse> {
+    session.purge();
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Get the signed-in user
+#[utoipa::path(
+    get,
+    path = "/auth/me",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The signed-in user", body = User),
+        (status = 401, description = "Not signed in", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn me(user: AuthenticatedUser) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(user.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use crate::{auth::ensure_admin, test_support::{sign_in, session_cookie, test_app, TestDb}};
+    
+    #[tokio::test]
+    async fn register_login_and_roles() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        
+        let response = test::call_service(&app, test::TestRequest::post()
+            .uri("/api/v1/auth/register")
+            .set_json(serde_json::json!({ "name": "Ann", "email": "ann@example.com", "password": "correct horse" }))
+            .to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let customer = session_cookie(&response);
+        
+        let response = test::call_service(&app, test::TestRequest::post()
+            .uri("/api/v1/auth/register")
+            .set_json(serde_json::json!({ "name": " ", "email": "ann@", "password": "short" }))
+            .to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["fields"], serde_json::json!({
+            "name": ["must not be blank"],
+            "email": ["must be a valid email address"],
+            "password": ["must be at least 8 characters"],
+        }));
+        
+        let me: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get()
+            .uri("/api/v1/auth/me")
+            .cookie(customer.clone())
+            .to_request()).await;
+        assert_eq!((me["email"].as_str(), me["role"].as_str()), (Some("ann@example.com"), Some("customer")));
+        
+        // Wrong password and unknown email fail the same way
+        for (email, password) in [("ann@example.com", "wrong password"), ("bob@example.com", "correct horse")] {
+            let response = test::call_service(&app, test::TestRequest::post()
+                .uri("/api/v1/auth/login")
+                .set_json(serde_json::json!({ "email": email, "password": password }))
+                .to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        sign_in(&app, "ann@example.com", "correct horse").await;
+        
+        // Admin endpoints: 401 signed out, 403 for customers, allowed for admins
+        assert!(ensure_admin(&db.pool, "admin@example.com", Some("change-me")).await.is_err());
+        ensure_admin(&db.pool, "admin@example.com", Some("admin password")).await.unwrap();
+        let admin = sign_in(&app, "admin@example.com", "admin password").await;
+        let create = |cookie: Option<actix_web::cookie::Cookie<'static>>| {
+            let mut request = test::TestRequest::post()
+                .uri("/api/v1/categories")
+                .set_json(serde_json::json!({ "name": "Hats" }));
+            if let Some(cookie) = cookie {
+                request = request.cookie(cookie);
+            }
+            request.to_request()
+        };
+        assert_eq!(test::call_service(&app, create(None)).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(test::call_service(&app, create(Some(customer))).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(test::call_service(&app, create(Some(admin))).await.status(), StatusCode::CREATED);
+    }
+}
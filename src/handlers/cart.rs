@@ -1,142 +1,711 @@
 use actix_session::Session;
 use actix_web::{web, HttpResponse};
-use crate::{models::{Cart, Product}, errors::{Result, AppError}, AppState};
+use rust_decimal::Decimal;
+use sqlx::{SqliteConnection, SqlitePool};
+use validator::Validate;
+use crate::{
+    models::{
+        line_quantity, new_line_quantity, Cart, CartItem, CouponKind, Product, ProductStatus, ProductVariant,
+        MAX_LINE_QUANTITY,
+    },
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::{current_user_id, AuthenticatedUser},
+    handlers::{coupons::check_coupon, variants::find_variant, Message},
+};
 
-// Get cart
+// Session key holding the token of a guest's cart
+const SESSION_CART_KEY: &str = "cart_token";
+
+// Guest carts untouched for this long are deleted
+pub const GUEST_CART_TTL_DAYS: i64 = 30;
+
+// Get cart with totals
+#[utoipa::path(
+    get,
+    path = "/cart",
+    tag = "cart",
+    responses(
+        (status = 200, description = "The cart with its totals", body = CartSummary),
+    ),
+)]
 pub async fn get_cart(
     session: Session,
     state: web::Data<AppState>,
+    customer: Option<AuthenticatedUser>,
 ) -> Result<HttpResponse> {
-    let mut cart = get_cart_from_session(&session)?;
+    let cart = load_cart(&state.db, &session).await?;
+    let summary = summarize_cart(&state.db, cart, customer.as_ref()).await?;
     
-    // Load product details for each cart item
-    for item in &mut cart.items {
-        if let Some(product) = sqlx::query_as::<_, Product>(
-            "SELECT * FROM products WHERE id = ?1"
-        )
-        .bind(item.product_id)
-        .fetch_optional(&state.db)
-        .await? {
-            item.product = Some(product);
-        }
-    }
-    
-    Ok(HttpResponse::Ok().json(cart))
+    Ok(HttpResponse::Ok().json(summary))
 }
 
 // Add item to cart
+#[utoipa::path(
+    post,
+    path = "/cart",
+    tag = "cart",
+    request_body = AddCartItem,
+    responses(
+        (status = 200, description = "Item added", body = AddedToCart),
+        (status = 409, description = "Not enough stock", body = Problem),
+        (status = 404, description = "No such product or variant", body = Problem),
+        (status = 422, description = "Invalid quantity", body = Problem),
+    ),
+)]
 pub async fn add_to_cart(
     session: Session,
     state: web::Data<AppState>,
     item: web::Json<AddCartItem>,
 ) -> Result<HttpResponse> {
     let item = item.into_inner();
+    item.validate()?;
     
-    // Verify product exists and has stock
-    let product = sqlx::query_as::<_, Product>(
-        "SELECT * FROM products WHERE id = ?1"
-    )
-    .bind(item.product_id)
-    .fetch_optional(&state.db)
-    .await?;
+    // Verify product (and variant) exists and has stock for the whole line
+    let line = resolve_line(&mut *state.db.acquire().await?, item.product_id, item.variant_id).await?;
+    let mut cart = load_cart(&state.db, &session).await?;
+    let in_cart = cart.quantity_of(item.product_id, item.variant_id);
+    line.check_stock(in_cart.saturating_add(item.quantity).min(MAX_LINE_QUANTITY))?;
     
-    match product {
-        Some(p) if p.stock_quantity >= item.quantity => {
-            let mut cart = get_cart_from_session(&session)?;
-            cart.add_item(item.product_id, item.quantity);
-            save_cart_to_session(&session, &cart)?;
-            
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "message": "Item added to cart",
-                "cart": cart
-            })))
-        },
-        Some(_) => Err(AppError::BadRequest("Insufficient stock".to_string())),
-        _ => Err(AppError::NotFound),
-    }
+    cart.add_item(item.product_id, item.variant_id, item.quantity);
+    save_cart(&state.db, &session, &cart).await?;
+    
+    Ok(HttpResponse::Ok().json(AddedToCart {
+        message: "Item added to cart".to_string(),
+        cart,
+    }))
 }
 
 // Update cart item quantity
+#[utoipa::path(
+    put,
+    path = "/cart/{id}",
+    tag = "cart",
+    params(("id" = i32, Path, description = "Product id"), CartLineQuery),
+    request_body = UpdateCartItem,
+    responses(
+        (status = 200, description = "The updated cart", body = Cart),
+        (status = 409, description = "Not enough stock", body = Problem),
+        (status = 422, description = "Invalid quantity", body = Problem),
+    ),
+)]
 pub async fn update_cart_item(
     session: Session,
     state: web::Data<AppState>,
     path: web::Path<i32>,
+    line: web::Query<CartLineQuery>,
     update: web::Json<UpdateCartItem>,
 ) -> Result<HttpResponse> {
     let product_id = path.into_inner();
-    let new_quantity = update.into_inner().quantity;
+    let variant_id = line.variant_id;
+    let update = update.into_inner();
+    update.validate()?;
+    let new_quantity = update.quantity;
     
     if new_quantity > 0 {
         // Verify stock
-        let product = sqlx::query_as::<_, Product>(
-            "SELECT * FROM products WHERE id = ?1"
-        )
-        .bind(product_id)
-        .fetch_optional(&state.db)
-        .await?;
+        let line = resolve_line(&mut *state.db.acquire().await?, product_id, variant_id).await?;
+        line.check_stock(new_quantity)?;
         
-        match product {
-            Some(p) if p.stock_quantity >= new_quantity => {
-                let mut cart = get_cart_from_session(&session)?;
-                cart.update_quantity(product_id, new_quantity);
-                save_cart_to_session(&session, &cart)?;
-                Ok(HttpResponse::Ok().json(cart))
-            },
-            Some(_) => Err(AppError::BadRequest("Insufficient stock".to_string())),
-            _ => Err(AppError::NotFound),
-        }
+        let mut cart = load_cart(&state.db, &session).await?;
+        cart.update_quantity(product_id, variant_id, new_quantity);
+        save_cart(&state.db, &session, &cart).await?;
+        Ok(HttpResponse::Ok().json(cart))
     } else {
-        // Remove item if quantity is 0 or less
-        let mut cart = get_cart_from_session(&session)?;
-        cart.remove_item(product_id);
-        save_cart_to_session(&session, &cart)?;
+        // Remove item if quantity is 0
+        let mut cart = load_cart(&state.db, &session).await?;
+        cart.remove_item(product_id, variant_id);
+        save_cart(&state.db, &session, &cart).await?;
         Ok(HttpResponse::Ok().json(cart))
     }
 }
 
 // Remove item from cart
+#[utoipa::path(
+    delete,
+    path = "/cart/{id}",
+    tag = "cart",
+    params(("id" = i32, Path, description = "Product id"), CartLineQuery),
+    responses(
+        (status = 200, description = "The updated cart", body = Cart),
+    ),
+)]
 pub async fn remove_from_cart(
     session: Session,
+    state: web::Data<AppState>,
     path: web::Path<i32>,
+    line: web::Query<CartLineQuery>,
 ) -> Result<HttpResponse> {
     let product_id = path.into_inner();
     
-    let mut cart = get_cart_from_session(&session)?;
-    cart.remove_item(product_id);
-    save_cart_to_session(&session, &cart)?;
+    let mut cart = load_cart(&state.db, &session).await?;
+    cart.remove_item(product_id, line.variant_id);
+    save_cart(&state.db, &session, &cart).await?;
     
     Ok(HttpResponse::Ok().json(cart))
 }
 
+// Attach a discount code to the cart
+#[utoipa::path(
+    post,
+    path = "/cart/coupon",
+    tag = "cart",
+    request_body = ApplyCoupon,
+    responses(
+        (status = 200, description = "Coupon applied", body = CartSummary),
+        (status = 400, description = "The code can't be used on this cart", body = Problem),
+    ),
+)]
+pub async fn apply_coupon(
+    session: Session,
+    state: web::Data<AppState>,
+    customer: Option<AuthenticatedUser>,
+    body: web::Json<ApplyCoupon>,
+) -> Result<HttpResponse> {
+    let mut cart = load_cart(&state.db, &session).await?;
+    load_cart_details(&state.db, &mut cart).await?;
+    
+    let mut conn = state.db.acquire().await?;
+    let coupon = check_coupon(
+        &mut conn,
+        &body.code,
+        cart.total_with_products(),
+        customer.as_ref().map(|c| c.0.id),
+        customer.as_ref().map(|c| c.0.email.as_str()),
+    ).await?;
+    drop(conn);
+    
+    cart.coupon_code = Some(coupon.code);
+    save_cart(&state.db, &session, &cart).await?;
+    
+    let summary = summarize_cart(&state.db, cart, customer.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+// Detach the discount code from the cart
+#[utoipa::path(
+    delete,
+    path = "/cart/coupon",
+    tag = "cart",
+    responses(
+        (status = 200, description = "Coupon removed", body = CartSummary),
+    ),
+)]
+pub async fn remove_coupon(
+    session: Session,
+    state: web::Data<AppState>,
+    customer: Option<AuthenticatedUser>,
+) -> Result<HttpResponse> {
+    let mut cart = load_cart(&state.db, &session).await?;
+    cart.coupon_code = None;
+    save_cart(&state.db, &session, &cart).await?;
+    
+    let summary = summarize_cart(&state.db, cart, customer.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
 // Clear cart
-pub async fn clear_cart(session: Session) -> Result<HttpResponse> {
+#[utoipa::path(
+    post,
+    path = "/cart/clear",
+    tag = "cart",
+    responses(
+        (status = 200, description = "Cart emptied", body = Message),
+    ),
+)]
+pub async fn clear_cart(
+    session: Session,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
     let cart = Cart::new();
-    save_cart_to_session(&session, &cart)?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "Cart cleared"
-    })))
+    save_cart(&state.db, &session, &cart).await?;
+    Ok(HttpResponse::Ok().json(Message::new("Cart cleared")))
 }
 
 // Helper functions
-pub fn get_cart_from_session(session: &Session) -> Result<Cart> {
-    Ok(session.get::<Cart>("cart")
-        .map_err(|_| AppError::SessionError)?
-        .unwrap_or_else(Cart::new))
+
+// Load the cart for this session: the customer's cart when signed in,
+// otherwise the guest cart named by the session token
+pub async fn load_cart(db: &SqlitePool, session: &Session) -> Result<Cart> {
+    let mut conn = db.acquire().await?;
+    let Some(cart_id) = find_cart_id(&mut conn, session).await? else {
+        return Ok(Cart::new());
+    };
+    
+    let coupon_code: Option<String> = sqlx::query_scalar("SELECT coupon_code FROM carts WHERE id = ?1")
+        .bind(cart_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    
+    let items = sqlx::query_as::<_, (i32, Option<i64>, i32)>(
+        "SELECT product_id, variant_id, quantity FROM cart_items WHERE cart_id = ?1 ORDER BY id"
+    )
+    .bind(cart_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    
+    Ok(Cart {
+        items: items.into_iter()
+            .map(|(product_id, variant_id, quantity)| CartItem {
+                product_id,
+                variant_id,
+                quantity,
+                product: None,
+                variant: None,
+            })
+            .collect(),
+        coupon_code,
+    })
 }
 
-pub fn save_cart_to_session(session: &Session, cart: &Cart) -> Result<()> {
-    session.insert("cart", cart)
-        .map_err(|_| AppError::SessionError)?;
+// Persist the cart for this session, creating it on first write
+pub async fn save_cart(db: &SqlitePool, session: &Session, cart: &Cart) -> Result<()> {
+    let mut tx = db.begin().await?;
+    let cart_id = find_or_create_cart_id(&mut tx, session).await?;
+    
+    sqlx::query("DELETE FROM cart_items WHERE cart_id = ?1")
+        .bind(cart_id)
+        .execute(&mut *tx)
+        .await?;
+    
+    for item in &cart.items {
+        sqlx::query(
+            "INSERT INTO cart_items (cart_id, product_id, variant_id, quantity) VALUES (?1, ?2, ?3, ?4)"
+        )
+        .bind(cart_id)
+        .bind(item.product_id)
+        .bind(item.variant_id)
+        .bind(item.quantity)
+        .execute(&mut *tx)
+        .await?;
+    }
+    
+    sqlx::query("UPDATE carts SET coupon_code = ?1, updated_at = datetime('now') WHERE id = ?2")
+        .bind(&cart.coupon_code)
+        .bind(cart_id)
+        .execute(&mut *tx)
+        .await?;
+    
+    tx.commit().await?;
+    Ok(())
+}
+
+// Fold the session's guest cart into the customer's cart after sign-in
+pub async fn merge_guest_cart(db: &SqlitePool, session: &Session, customer_id: i64) -> Result<()> {
+    let Some(token) = session.get::<String>(SESSION_CART_KEY)
+        .map_err(|_| AppError::SessionError)? else {
+        return Ok(());
+    };
+    
+    let mut tx = db.begin().await?;
+    
+    let guest_cart_id: Option<i64> = sqlx::query_scalar(
+        "SELECT id FROM carts WHERE token = ?1 AND customer_id IS NULL"
+    )
+    .bind(&token)
+    .fetch_optional(&mut *tx)
+    .await?;
+    
+    if let Some(guest_cart_id) = guest_cart_id {
+        let customer_cart_id = customer_cart_id(&mut tx, customer_id).await?;
+        
+        sqlx::query(
+            r#"
+            INSERT INTO cart_items (cart_id, product_id, variant_id, quantity)
+            SELECT ?1, product_id, variant_id, quantity FROM cart_items WHERE cart_id = ?2 ORDER BY id
+            ON CONFLICT (cart_id, product_id, IFNULL(variant_id, 0))
+            DO UPDATE SET quantity = MIN(quantity + excluded.quantity, ?3)
+            "#
+        )
+        .bind(customer_cart_id)
+        .bind(guest_cart_id)
+        .bind(MAX_LINE_QUANTITY)
+        .execute(&mut *tx)
+        .await?;
+        
+        // A code entered as a guest carries over unless the customer already has one
+        sqlx::query(
+            r#"
+            UPDATE carts
+            SET coupon_code = COALESCE(coupon_code, (SELECT coupon_code FROM carts WHERE id = ?2))
+            WHERE id = ?1
+            "#
+        )
+        .bind(customer_cart_id)
+        .bind(guest_cart_id)
+        .execute(&mut *tx)
+        .await?;
+        
+        sqlx::query("DELETE FROM carts WHERE id = ?1")
+            .bind(guest_cart_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    
+    tx.commit().await?;
+    session.remove(SESSION_CART_KEY);
     Ok(())
 }
 
-#[derive(serde::Deserialize)]
+// Load product and variant details for each cart item
+pub async fn load_cart_details(db: &SqlitePool, cart: &mut Cart) -> Result<()> {
+    let mut conn = db.acquire().await?;
+    for item in &mut cart.items {
+        item.product = sqlx::query_as::<_, Product>(
+            "SELECT * FROM products WHERE id = ?1"
+        )
+        .bind(item.product_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some(variant_id) = item.variant_id {
+            item.variant = find_variant(&mut conn, variant_id).await?;
+        }
+    }
+    Ok(())
+}
+
+// Price the cart. A code that no longer applies (expired, below the minimum
+// since items were removed, ...) stays attached but is reported instead of
+// failing the whole request.
+pub async fn summarize_cart(
+    db: &SqlitePool,
+    mut cart: Cart,
+    customer: Option<&AuthenticatedUser>,
+) -> Result<CartSummary> {
+    load_cart_details(db, &mut cart).await?;
+    let subtotal = cart.total_with_products();
+    
+    let mut discount = Decimal::ZERO;
+    let mut free_shipping = false;
+    let mut coupon_error = None;
+    if let Some(code) = &cart.coupon_code {
+        let mut conn = db.acquire().await?;
+        match check_coupon(
+            &mut conn,
+            code,
+            subtotal,
+            customer.map(|c| c.0.id),
+            customer.map(|c| c.0.email.as_str()),
+        ).await {
+            Ok(coupon) => {
+                discount = coupon.discount_for(subtotal);
+                free_shipping = coupon.kind == CouponKind::FreeShipping;
+            },
+            Err(AppError::BadRequest(message)) => coupon_error = Some(message),
+            Err(e) => return Err(e),
+        }
+    }
+    
+    Ok(CartSummary {
+        cart,
+        subtotal,
+        discount,
+        free_shipping,
+        total: subtotal - discount,
+        coupon_error,
+    })
+}
+
+// A cart line checked against the catalogue
+pub struct ResolvedLine {
+    pub product: Product,
+    pub variant: Option<ProductVariant>,
+}
+
+impl ResolvedLine {
+    pub fn unit_price(&self) -> Decimal {
+        self.variant.as_ref().and_then(|v| v.price).unwrap_or(self.product.price)
+    }
+    
+    // Stock is tracked per variant for products that have them
+    pub fn available(&self) -> i32 {
+        self.variant.as_ref().map_or(self.product.stock_quantity, |v| v.stock_quantity)
+    }
+    
+    pub fn label(&self) -> String {
+        match &self.variant {
+            Some(v) => format!("{} ({})", self.product.name, v.sku),
+            None => self.product.name.clone(),
+        }
+    }
+    
+    // The error for a line that can't be had in the quantity asked for,
+    // the same in the cart as at checkout
+    pub fn out_of_stock(&self) -> AppError {
+        AppError::Conflict(format!("{} is out of stock", self.label()))
+    }
+    
+    pub fn check_stock(&self, quantity: i32) -> Result<()> {
+        if self.available() < quantity {
+            return Err(self.out_of_stock());
+        }
+        Ok(())
+    }
+}
+
+// Look up the product and variant a cart line refers to. Only published
+// products can be bought, and those with variants only through one of them.
+pub async fn resolve_line(conn: &mut SqliteConnection, product_id: i32, variant_id: Option<i64>) -> Result<ResolvedLine> {
+    let product = sqlx::query_as::<_, Product>(
+        "SELECT * FROM products WHERE id = ?1"
+    )
+    .bind(product_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    if product.status != ProductStatus::Published {
+        return Err(AppError::BadRequest(
+            format!("{} is not available", product.name)
+        ));
+    }
+    
+    let variant = match variant_id {
+        Some(variant_id) => {
+            let variant = find_variant(&mut *conn, variant_id).await?
+                .filter(|v| v.product_id == product_id)
+                .ok_or(AppError::NotFound)?;
+            Some(variant)
+        },
+        None => {
+            let has_variants: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM product_variants WHERE product_id = ?1)"
+            )
+            .bind(product_id)
+            .fetch_one(&mut *conn)
+            .await?;
+            if has_variants {
+                return Err(AppError::BadRequest(
+                    format!("Choose a variant of {}", product.name)
+                ));
+            }
+            None
+        },
+    };
+    
+    Ok(ResolvedLine { product, variant })
+}
+
+// Delete guest carts that haven't been touched within the TTL
+pub async fn expire_guest_carts(db: &SqlitePool) -> Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM carts WHERE customer_id IS NULL AND updated_at < datetime('now', ?1)"
+    )
+    .bind(format!("-{} days", GUEST_CART_TTL_DAYS))
+    .execute(db)
+    .await?;
+    
+    Ok(result.rows_affected())
+}
+
+async fn find_cart_id(conn: &mut SqliteConnection, session: &Session) -> Result<Option<i64>> {
+    if let Some(customer_id) = current_user_id(session)? {
+        let cart_id = sqlx::query_scalar("SELECT id FROM carts WHERE customer_id = ?1")
+            .bind(customer_id)
+            .fetch_optional(conn)
+            .await?;
+        return Ok(cart_id);
+    }
+    
+    let token = session.get::<String>(SESSION_CART_KEY)
+        .map_err(|_| AppError::SessionError)?;
+    match token {
+        Some(token) => {
+            let cart_id = sqlx::query_scalar(
+                "SELECT id FROM carts WHERE token = ?1 AND customer_id IS NULL"
+            )
+            .bind(token)
+            .fetch_optional(conn)
+            .await?;
+            Ok(cart_id)
+        },
+        None => Ok(None),
+    }
+}
+
+async fn find_or_create_cart_id(conn: &mut SqliteConnection, session: &Session) -> Result<i64> {
+    if let Some(customer_id) = current_user_id(session)? {
+        return customer_cart_id(conn, customer_id).await;
+    }
+    if let Some(cart_id) = find_cart_id(conn, session).await? {
+        return Ok(cart_id);
+    }
+    
+    let token = uuid::Uuid::new_v4().to_string();
+    let cart_id = sqlx::query_scalar("INSERT INTO carts (token) VALUES (?1) RETURNING id")
+        .bind(&token)
+        .fetch_one(conn)
+        .await?;
+    session.insert(SESSION_CART_KEY, token)
+        .map_err(|_| AppError::SessionError)?;
+    
+    Ok(cart_id)
+}
+
+async fn customer_cart_id(conn: &mut SqliteConnection, customer_id: i64) -> Result<i64> {
+    let cart_id = sqlx::query_scalar(
+        r#"
+        INSERT INTO carts (token, customer_id) VALUES (?1, ?2)
+        ON CONFLICT (customer_id) DO UPDATE SET updated_at = datetime('now')
+        RETURNING id
+        "#
+    )
+    .bind(uuid::Uuid::new_v4().to_string())
+    .bind(customer_id)
+    .fetch_one(conn)
+    .await?;
+    
+    Ok(cart_id)
+}
+
+#[derive(serde::Deserialize, Validate, utoipa::ToSchema)]
 pub struct AddCartItem {
     pub product_id: i32,
+    pub variant_id: Option<i64>,
+    #[validate(custom(function = "line_quantity"))]
     pub quantity: i32,
 }
 
-#[derive(serde::Deserialize)]
+// Picks out a variant line for the `/api/cart/{product_id}` routes
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CartLineQuery {
+    pub variant_id: Option<i64>,
+}
+
+// A quantity of 0 removes the line
+#[derive(serde::Deserialize, Validate, utoipa::ToSchema)]
 pub struct UpdateCartItem {
+    #[validate(custom(function = "new_line_quantity"))]
     pub quantity: i32,
-}
\ No newline at end of file
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct ApplyCoupon {
+    pub code: String,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct AddedToCart {
+    pub message: String,
+    pub cart: Cart,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CartSummary {
+    #[serde(flatten)]
+    pub cart: Cart,
+    pub subtotal: Decimal,
+    pub discount: Decimal,
+    pub free_shipping: bool,
+    pub total: Decimal,
+    // Why the attached coupon isn't being applied right now
+    pub coupon_error: Option<String>,
+}
+#[cfg(test)]
+mod tests {
+    use actix_web::{cookie::Cookie, http::StatusCode, test};
+    use super::*;
+    use crate::test_support::{add_to_cart, insert_product, register, session_cookie, test_app, TestDb};
+    
+    #[tokio::test]
+    async fn signing_in_merges_the_guest_cart() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let hat = insert_product(&db.pool, "Hat", 10).await;
+        let scarf = insert_product(&db.pool, "Scarf", 10).await;
+        
+        let ann = register(&app, "Ann", "ann@example.com").await;
+        let add = |cookie: Option<Cookie<'static>>, product_id: i32, quantity: i32| {
+            add_to_cart(&app, cookie, serde_json::json!({ "product_id": product_id, "quantity": quantity }))
+        };
+        
+        // Two hats are already in the customer cart; the guest cart adds three more and a scarf
+        add(Some(ann), hat, 2).await;
+        let guest = add(None, hat, 3).await;
+        let guest = add(Some(guest), scarf, 1).await;
+        
+        let login = test::TestRequest::post()
+            .uri("/api/v1/auth/login")
+            .cookie(guest)
+            .set_json(serde_json::json!({ "email": "ann@example.com", "password": "long enough" }))
+            .to_request();
+        let response = test::call_service(&app, login).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let ann = session_cookie(&response);
+        
+        let cart: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get()
+            .uri("/api/v1/cart")
+            .cookie(ann)
+            .to_request()).await;
+        let lines: Vec<_> = cart["items"].as_array().unwrap().iter()
+            .map(|item| (item["product_id"].as_i64().unwrap() as i32, item["quantity"].as_i64().unwrap()))
+            .collect();
+        assert_eq!(lines, [(hat, 5), (scarf, 1)]);
+        
+        let guest_carts: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM carts WHERE customer_id IS NULL")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(guest_carts, 0);
+    }
+    
+    #[tokio::test]
+    async fn cart_lines_never_exceed_the_stock() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let widget = insert_product(&db.pool, "Widget", 5).await;
+        let guest = add_to_cart(&app, None, serde_json::json!({ "product_id": widget, "quantity": 3 })).await;
+        
+        // Three more would make six in the line, with only five left
+        let add = test::TestRequest::post()
+            .uri("/api/v1/cart")
+            .cookie(guest.clone())
+            .set_json(serde_json::json!({ "product_id": widget, "quantity": 3 }))
+            .to_request();
+        let response = test::call_service(&app, add).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["detail"], "Widget is out of stock");
+        
+        let update = |quantity: i32| test::TestRequest::put()
+            .uri(&format!("/api/v1/cart/{}", widget))
+            .cookie(guest.clone())
+            .set_json(serde_json::json!({ "quantity": quantity }))
+            .to_request();
+        assert_eq!(test::call_service(&app, update(6)).await.status(), StatusCode::CONFLICT);
+        assert_eq!(test::call_service(&app, update(5)).await.status(), StatusCode::OK);
+        
+        let cart: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get()
+            .uri("/api/v1/cart")
+            .cookie(guest)
+            .to_request()).await;
+        assert_eq!(cart["items"][0]["quantity"], 5);
+    }
+    
+    #[tokio::test]
+    async fn only_stale_guest_carts_expire() {
+        let db = TestDb::new().await;
+        sqlx::query("INSERT INTO users (email, password_hash, role, name) VALUES ('ann@example.com', 'x', 'customer', 'Ann')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO carts (token, customer_id, updated_at) VALUES
+                ('stale', NULL, datetime('now', '-31 days')),
+                ('fresh', NULL, datetime('now', '-29 days')),
+                ('customer', (SELECT id FROM users), datetime('now', '-90 days'))
+            "#
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        
+        assert_eq!(expire_guest_carts(&db.pool).await.unwrap(), 1);
+        let tokens: Vec<String> = sqlx::query_scalar("SELECT token FROM carts ORDER BY token")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(tokens, ["customer", "fresh"]);
+    }
+}
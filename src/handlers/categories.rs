@@ -1,12 +1,55 @@
-use actix_web::{web, HttpResponse};
-use crate::{models::{Category, CreateCategory, Product}, errors::Result, AppState};
+use std::collections::{HashMap, HashSet};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{SqliteConnection, SqlitePool};
+use validator::{Validate, ValidationError, ValidationErrors};
+use crate::{
+    models::{Category, CreateCategory, Page, Product, SetProductCategories},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AdminUser,
+    handlers::{page_offset, page_params, paginate, PageQuery},
+};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CategoryProducts {
+    pub category: Category,
+    // From the top-level category down to this one
+    pub breadcrumbs: Vec<Category>,
+    pub products: Vec<Product>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CategoryProductsQuery {
+    // Also list products filed under subcategories, at any depth
+    #[serde(default)]
+    pub include_descendants: bool,
+}
+
+// A category and its subcategories
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CategoryNode {
+    #[serde(flatten)]
+    pub category: Category,
+    #[schema(no_recursion)]
+    pub children: Vec<CategoryNode>,
+}
 
 // Get all categories
+#[utoipa::path(
+    get,
+    path = "/categories",
+    tag = "categories",
+    responses(
+        (status = 200, description = "All categories", body = Vec<Category>),
+    ),
+)]
 pub async fn get_categories(
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
     let categories = sqlx::query_as::<_, Category>(
-        "SELECT * FROM categories ORDER BY name"
+        "SELECT * FROM categories WHERE deleted_at IS NULL ORDER BY name"
     )
     .fetch_all(&state.db)
     .await?;
@@ -14,106 +57,631 @@ pub async fn get_categories(
     Ok(HttpResponse::Ok().json(categories))
 }
 
-// Get category with products
+// The whole hierarchy: top-level categories with their subcategories nested inside
+#[utoipa::path(
+    get,
+    path = "/categories/tree",
+    tag = "categories",
+    responses(
+        (status = 200, description = "Top-level categories, each with its subcategories", body = Vec<CategoryNode>),
+    ),
+)]
+pub async fn get_category_tree(
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let categories = sqlx::query_as::<_, Category>(
+        "SELECT * FROM categories WHERE deleted_at IS NULL ORDER BY name"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    
+    let mut by_parent: HashMap<Option<i32>, Vec<Category>> = HashMap::new();
+    for category in categories {
+        by_parent.entry(category.parent_id).or_default().push(category);
+    }
+    
+    Ok(HttpResponse::Ok().json(build_tree(&mut by_parent, None)))
+}
+
+// Categories a page at a time (v2)
+#[utoipa::path(
+    get,
+    path = "/categories",
+    tag = "categories",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of categories", body = Page<Category>),
+    ),
+)]
+pub async fn get_categories_page(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse> {
+    let (page, per_page) = page_params(query.page, query.per_page);
+    
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM categories WHERE deleted_at IS NULL")
+        .fetch_one(&state.db)
+        .await?;
+    let categories = sqlx::query_as::<_, Category>(
+        "SELECT * FROM categories WHERE deleted_at IS NULL ORDER BY name LIMIT ?1 OFFSET ?2"
+    )
+    .bind(per_page)
+    .bind(page_offset(page, per_page))
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(paginate(&req, categories, page, per_page, total)))
+}
+
+// Archived categories, most recently archived first (admin)
+#[utoipa::path(
+    get,
+    path = "/categories/archived",
+    tag = "categories",
+    responses(
+        (status = 200, description = "Archived categories", body = Vec<Category>),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_archived_categories(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let categories = sqlx::query_as::<_, Category>(
+        "SELECT * FROM categories WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, name"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(categories))
+}
+
+// Get category with its breadcrumbs and published products
+#[utoipa::path(
+    get,
+    path = "/categories/{id}/products",
+    tag = "categories",
+    params(("id" = i32, Path, description = "Category id"), CategoryProductsQuery),
+    responses(
+        (status = 200, description = "The category, its breadcrumbs and its products", body = CategoryProducts),
+        (status = 404, description = "No such category", body = Problem),
+    ),
+)]
 pub async fn get_category_products(
     state: web::Data<AppState>,
     path: web::Path<i32>,
+    query: web::Query<CategoryProductsQuery>,
 ) -> Result<HttpResponse> {
     let category_id = path.into_inner();
     
-    // Get category
-    let category = sqlx::query_as::<_, Category>(
-        "SELECT * FROM categories WHERE id = ?1"
-    )
-    .bind(category_id)
-    .fetch_optional(&state.db)
-    .await?;
+    let breadcrumbs = load_breadcrumbs(&state.db, category_id).await?;
+    let category = breadcrumbs.last()
+        .filter(|c| c.deleted_at.is_none())
+        .cloned()
+        .ok_or(AppError::NotFound)?;
     
-    match category {
-        Some(cat) => {
-            // Get products in this category
-            let products = sqlx::query_as::<_, Product>(
-                "SELECT * FROM products WHERE category_id = ?1 ORDER BY name"
+    let products = if query.include_descendants {
+        sqlx::query_as::<_, Product>(
+            r#"
+            WITH RECURSIVE subtree(id) AS (
+                SELECT ?1
+                UNION
+                SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+                WHERE c.deleted_at IS NULL
+            )
+            SELECT * FROM products
+            WHERE status = 'published' AND id IN (
+                SELECT pc.product_id FROM product_categories pc
+                JOIN subtree s ON s.id = pc.category_id
             )
+            ORDER BY name
+            "#
+        )
+        .bind(category_id)
+        .fetch_all(&state.db)
+        .await?
+    } else {
+        sqlx::query_as::<_, Product>(
+            r#"
+            SELECT * FROM products
+            WHERE status = 'published'
+                AND id IN (SELECT product_id FROM product_categories WHERE category_id = ?1)
+            ORDER BY name
+            "#
+        )
+        .bind(category_id)
+        .fetch_all(&state.db)
+        .await?
+    };
+    
+    Ok(HttpResponse::Ok().json(CategoryProducts { category, breadcrumbs, products }))
+}
+
+// Categories a product is filed under, its main category first
+#[utoipa::path(
+    get,
+    path = "/products/{id}/categories",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "The product's categories", body = Vec<Category>),
+    ),
+)]
+pub async fn get_product_categories(
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let categories = load_product_categories(&state.db, path.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(categories))
+}
+
+// Replace the categories a product is filed under (admin). The first id
+// becomes the product's main `category_id`, which decides its tax class.
+// An empty list unfiles the product: it loses its main category and is taxed
+// under the standard class again.
+#[utoipa::path(
+    put,
+    path = "/products/{id}/categories",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    request_body = SetProductCategories,
+    responses(
+        (status = 200, description = "The product's categories, main category first", body = Vec<Category>),
+        (status = 400, description = "A category is listed twice", body = Problem),
+        (status = 404, description = "No such product", body = Problem),
+        (status = 422, description = "A category doesn't exist or is archived", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn set_product_categories(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+    body: web::Json<SetProductCategories>,
+) -> Result<HttpResponse> {
+    let product_id = path.into_inner();
+    let category_ids = body.into_inner().category_ids;
+    
+    let distinct: HashSet<i32> = category_ids.iter().copied().collect();
+    if distinct.len() != category_ids.len() {
+        return Err(AppError::BadRequest("category_ids must not repeat a category".to_string()));
+    }
+    
+    let mut tx = state.db.begin().await?;
+    
+    let result = sqlx::query("UPDATE products SET category_id = ?1, updated_at = datetime('now') WHERE id = ?2")
+        .bind(category_ids.first())
+        .bind(product_id)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    
+    // Archived categories are hidden from the storefront, products included
+    let mut errors = ValidationErrors::new();
+    for category_id in &category_ids {
+        let archived: Option<bool> = sqlx::query_scalar("SELECT deleted_at IS NOT NULL FROM categories WHERE id = ?1")
+            .bind(category_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if archived == Some(true) {
+            let message = format!("category {} is archived", category_id);
+            errors.add("category_ids", ValidationError::new("archived").with_message(message.into()));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+    
+    sqlx::query("DELETE FROM product_categories WHERE product_id = ?1")
+        .bind(product_id)
+        .execute(&mut *tx)
+        .await?;
+    for category_id in &category_ids {
+        sqlx::query("INSERT OR IGNORE INTO product_categories (product_id, category_id) VALUES (?1, ?2)")
+            .bind(product_id)
             .bind(category_id)
-            .fetch_all(&state.db)
+            .execute(&mut *tx)
             .await?;
-            
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "category": cat,
-                "products": products
-            })))
-        },
-        None => Err(crate::errors::AppError::NotFound),
     }
+    
+    tx.commit().await?;
+    
+    let categories = load_product_categories(&state.db, product_id).await?;
+    Ok(HttpResponse::Ok().json(categories))
 }
 
 // Create category (admin)
+#[utoipa::path(
+    post,
+    path = "/categories",
+    tag = "categories",
+    request_body = CreateCategory,
+    responses(
+        (status = 201, description = "Category created", body = Category),
+        (status = 400, description = "The parent category is archived", body = Problem),
+        (status = 409, description = "Name already taken", body = Problem),
+        (status = 422, description = "Invalid category", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
 pub async fn create_category(
+    _admin: AdminUser,
     state: web::Data<AppState>,
     category: web::Json<CreateCategory>,
 ) -> Result<HttpResponse> {
     let category = category.into_inner();
+    category.validate()?;
+    
+    let mut conn = state.db.acquire().await?;
+    if let Some(parent_id) = category.parent_id {
+        ensure_not_archived(&mut conn, parent_id).await?;
+    }
     
     let result = sqlx::query_as::<_, Category>(
         r#"
-        INSERT INTO categories (name, description)
-        VALUES (?1, ?2)
+        INSERT INTO categories (name, description, tax_class_id, parent_id)
+        VALUES (?1, ?2, ?3, ?4)
         RETURNING *
         "#
     )
     .bind(&category.name)
     .bind(&category.description)
-    .fetch_one(&state.db)
+    .bind(category.tax_class_id)
+    .bind(category.parent_id)
+    .fetch_one(&mut *conn)
     .await?;
     
     Ok(HttpResponse::Created().json(result))
 }
 
 // Update category (admin)
+#[utoipa::path(
+    put,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i32, Path, description = "Category id")),
+    request_body = CreateCategory,
+    responses(
+        (status = 200, description = "Category updated", body = Category),
+        (status = 400, description = "The parent is archived, or the category itself or one of its subcategories", body = Problem),
+        (status = 404, description = "No such category", body = Problem),
+        (status = 422, description = "Invalid category", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
 pub async fn update_category(
+    _admin: AdminUser,
     state: web::Data<AppState>,
     path: web::Path<i32>,
     category: web::Json<CreateCategory>,
 ) -> Result<HttpResponse> {
     let category_id = path.into_inner();
     let category = category.into_inner();
+    category.validate()?;
+    
+    let mut tx = state.db.begin().await?;
+    
+    if let Some(parent_id) = category.parent_id {
+        if is_within(&mut tx, parent_id, category_id).await? {
+            return Err(AppError::BadRequest(
+                "A category can't be moved under itself or one of its subcategories".to_string()
+            ));
+        }
+        ensure_not_archived(&mut tx, parent_id).await?;
+    }
     
     let result = sqlx::query_as::<_, Category>(
         r#"
         UPDATE categories 
-        SET name = ?1, description = ?2, updated_at = datetime('now')
-        WHERE id = ?3
+        SET name = ?1, description = ?2, tax_class_id = ?3, parent_id = ?4, updated_at = datetime('now')
+        WHERE id = ?5
         RETURNING *
         "#
     )
     .bind(&category.name)
     .bind(&category.description)
+    .bind(category.tax_class_id)
+    .bind(category.parent_id)
     .bind(category_id)
-    .fetch_optional(&state.db)
+    .fetch_optional(&mut *tx)
     .await?;
     
+    tx.commit().await?;
+    
     match result {
         Some(c) => Ok(HttpResponse::Ok().json(c)),
-        None => Err(crate::errors::AppError::NotFound),
+        None => Err(AppError::NotFound),
     }
 }
 
-// Delete category (admin)
+// Archive category (admin). It's hidden from the storefront until restored;
+// its products stay filed under it.
+#[utoipa::path(
+    delete,
+    path = "/categories/{id}",
+    tag = "categories",
+    params(("id" = i32, Path, description = "Category id")),
+    responses(
+        (status = 204, description = "Category archived"),
+        (status = 404, description = "No such category, or already archived", body = Problem),
+        (status = 409, description = "Category has subcategories that aren't archived", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
 pub async fn delete_category(
+    _admin: AdminUser,
     state: web::Data<AppState>,
     path: web::Path<i32>,
 ) -> Result<HttpResponse> {
     let category_id = path.into_inner();
     
-    let result = sqlx::query("DELETE FROM categories WHERE id = ?1")
-        .bind(category_id)
-        .execute(&state.db)
-        .await?;
+    let has_children: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM categories WHERE parent_id = ?1 AND deleted_at IS NULL)"
+    )
+    .bind(category_id)
+    .fetch_one(&state.db)
+    .await?;
+    if has_children {
+        return Err(AppError::Conflict(
+            "Category has subcategories; move or archive them first".to_string()
+        ));
+    }
+    
+    let result = sqlx::query(
+        r#"
+        UPDATE categories SET deleted_at = datetime('now'), updated_at = datetime('now')
+        WHERE id = ?1 AND deleted_at IS NULL
+        "#
+    )
+    .bind(category_id)
+    .execute(&state.db)
+    .await?;
     
     if result.rows_affected() > 0 {
         Ok(HttpResponse::NoContent().finish())
     } else {
-        Err(crate::errors::AppError::NotFound)
+        Err(AppError::NotFound)
+    }
+}
+
+// Bring an archived category back (admin)
+#[utoipa::path(
+    post,
+    path = "/categories/{id}/restore",
+    tag = "categories",
+    params(("id" = i32, Path, description = "Category id")),
+    responses(
+        (status = 200, description = "Category restored", body = Category),
+        (status = 404, description = "No such archived category", body = Problem),
+        (status = 409, description = "Its parent is archived; restore that first", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn restore_category(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let category_id = path.into_inner();
+    
+    let parent_archived: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM categories c JOIN categories parent ON parent.id = c.parent_id
+            WHERE c.id = ?1 AND parent.deleted_at IS NOT NULL
+        )
+        "#
+    )
+    .bind(category_id)
+    .fetch_one(&state.db)
+    .await?;
+    if parent_archived {
+        return Err(AppError::Conflict(
+            "The parent category is archived; restore it first".to_string()
+        ));
+    }
+    
+    let restored = sqlx::query_as::<_, Category>(
+        r#"
+        UPDATE categories SET deleted_at = NULL, updated_at = datetime('now')
+        WHERE id = ?1 AND deleted_at IS NOT NULL
+        RETURNING *
+        "#
+    )
+    .bind(category_id)
+    .fetch_optional(&state.db)
+    .await?;
+    
+    match restored {
+        Some(c) => Ok(HttpResponse::Ok().json(c)),
+        None => Err(AppError::NotFound),
     }
-}
\ No newline at end of file
+}
+
+async fn load_product_categories(db: &SqlitePool, product_id: i32) -> Result<Vec<Category>> {
+    let categories = sqlx::query_as::<_, Category>(
+        r#"
+        SELECT c.* FROM product_categories pc
+        JOIN categories c ON c.id = pc.category_id
+        JOIN products p ON p.id = pc.product_id
+        WHERE pc.product_id = ?1
+        ORDER BY c.id IS NOT p.category_id, c.name
+        "#
+    )
+    .bind(product_id)
+    .fetch_all(db)
+    .await?;
+    
+    Ok(categories)
+}
+
+// Categories can't be filed under an archived parent, where the storefront
+// would never show them
+async fn ensure_not_archived(conn: &mut SqliteConnection, category_id: i32) -> Result<()> {
+    let archived: Option<bool> = sqlx::query_scalar(
+        "SELECT deleted_at IS NOT NULL FROM categories WHERE id = ?1"
+    )
+    .bind(category_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if archived == Some(true) {
+        return Err(AppError::BadRequest(
+            "The parent category is archived".to_string()
+        ));
+    }
+    Ok(())
+}
+
+// The category and its ancestors, top-level first; empty if it doesn't exist
+pub async fn load_breadcrumbs(db: &SqlitePool, category_id: i32) -> Result<Vec<Category>> {
+    let trail = sqlx::query_as::<_, Category>(
+        r#"
+        WITH RECURSIVE trail(id, depth) AS (
+            SELECT ?1, 0
+            UNION
+            SELECT c.parent_id, t.depth + 1
+            FROM categories c JOIN trail t ON c.id = t.id
+            WHERE c.parent_id IS NOT NULL
+        )
+        SELECT c.* FROM trail t JOIN categories c ON c.id = t.id
+        ORDER BY t.depth DESC
+        "#
+    )
+    .bind(category_id)
+    .fetch_all(db)
+    .await?;
+    
+    Ok(trail)
+}
+
+// Whether `category_id` is `ancestor_id` or lies somewhere beneath it.
+// Filing a category under a parent that is within it would form a loop.
+async fn is_within(conn: &mut SqliteConnection, category_id: i32, ancestor_id: i32) -> Result<bool> {
+    let within: bool = sqlx::query_scalar(
+        r#"
+        WITH RECURSIVE ancestors(id) AS (
+            SELECT ?1
+            UNION
+            SELECT c.parent_id FROM categories c JOIN ancestors a ON c.id = a.id
+            WHERE c.parent_id IS NOT NULL
+        )
+        SELECT EXISTS(SELECT 1 FROM ancestors WHERE id = ?2)
+        "#
+    )
+    .bind(category_id)
+    .bind(ancestor_id)
+    .fetch_one(conn)
+    .await?;
+    
+    Ok(within)
+}
+
+fn build_tree(by_parent: &mut HashMap<Option<i32>, Vec<Category>>, parent_id: Option<i32>) -> Vec<CategoryNode> {
+    by_parent.remove(&parent_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|category| {
+            let children = build_tree(by_parent, Some(category.id));
+            CategoryNode { category, children }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test::{call_service, read_body_json, TestRequest}};
+    use super::*;
+    use crate::{
+        tax::{tax_class_for_product, STANDARD_TAX_CLASS_ID},
+        test_support::{admin_cookie, insert_product, test_app, TestDb},
+    };
+    
+    #[tokio::test]
+    async fn categories_cannot_be_filed_under_their_own_subtree() {
+        let db = TestDb::new().await;
+        let insert = |name: &'static str, parent_id: Option<i32>| {
+            sqlx::query_scalar::<_, i32>("INSERT INTO categories (name, parent_id) VALUES (?1, ?2) RETURNING id")
+                .bind(name)
+                .bind(parent_id)
+                .fetch_one(&db.pool)
+        };
+        let clothing = insert("Clothing", None).await.unwrap();
+        let men = insert("Men", Some(clothing)).await.unwrap();
+        let shoes = insert("Shoes", Some(men)).await.unwrap();
+        let books = insert("Books", None).await.unwrap();
+        
+        let mut conn = db.pool.acquire().await.unwrap();
+        assert!(is_within(&mut conn, clothing, clothing).await.unwrap());
+        assert!(is_within(&mut conn, shoes, clothing).await.unwrap());
+        assert!(!is_within(&mut conn, clothing, shoes).await.unwrap());
+        assert!(!is_within(&mut conn, books, clothing).await.unwrap());
+        
+        let names: Vec<_> = load_breadcrumbs(&db.pool, shoes).await.unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["Clothing", "Men", "Shoes"]);
+        assert!(load_breadcrumbs(&db.pool, 999).await.unwrap().is_empty());
+    }
+    
+    #[actix_web::test]
+    async fn products_are_filed_only_under_live_categories() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let admin = admin_cookie(&app, &db).await;
+        let product_id = insert_product(&db.pool, "Novel", 10).await;
+        let reduced: i64 = sqlx::query_scalar("INSERT INTO tax_classes (name) VALUES ('Reduced') RETURNING id")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let insert = |name: &'static str, archived: bool| {
+            sqlx::query_scalar::<_, i32>(
+                "INSERT INTO categories (name, tax_class_id, deleted_at) VALUES (?1, ?2, CASE WHEN ?3 THEN datetime('now') END) RETURNING id"
+            )
+            .bind(name)
+            .bind(reduced)
+            .bind(archived)
+            .fetch_one(&db.pool)
+        };
+        let books = insert("Books", false).await.unwrap();
+        let old = insert("Old stock", true).await.unwrap();
+        
+        let set = |category_ids: Vec<i32>| TestRequest::put()
+            .uri(&format!("/api/v1/products/{}/categories", product_id))
+            .cookie(admin.clone())
+            .set_json(serde_json::json!({ "category_ids": category_ids }))
+            .to_request();
+        let tax_class = || async {
+            tax_class_for_product(&mut db.pool.acquire().await.unwrap(), product_id).await.unwrap()
+        };
+        
+        let response = call_service(&app, set(vec![books, old])).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let problem: serde_json::Value = read_body_json(response).await;
+        assert_eq!(problem["fields"]["category_ids"], serde_json::json!([format!("category {} is archived", old)]));
+        assert_eq!(tax_class().await, STANDARD_TAX_CLASS_ID);
+        
+        assert_eq!(call_service(&app, set(vec![books])).await.status(), StatusCode::OK);
+        assert_eq!(tax_class().await, reduced);
+        
+        // No categories at all unfiles the product, back to the standard class
+        assert_eq!(call_service(&app, set(Vec::new())).await.status(), StatusCode::OK);
+        assert_eq!(tax_class().await, STANDARD_TAX_CLASS_ID);
+    }
+}
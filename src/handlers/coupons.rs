@@ -0,0 +1,353 @@
+use actix_web::{web, HttpResponse};
+use rust_decimal::Decimal;
+use sqlx::SqliteConnection;
+use validator::{Validate, ValidationError};
+use crate::{
+    models::{Cents, Coupon, CouponKind, CreateCoupon},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AdminUser,
+};
+
+// Coupons with their redemption count
+const COUPON_SELECT: &str = r#"
+    SELECT coupons.*,
+        (SELECT COUNT(*) FROM coupon_redemptions r WHERE r.coupon_id = coupons.id) AS times_used
+    FROM coupons
+"#;
+
+// Get all coupons (admin)
+#[utoipa::path(
+    get,
+    path = "/coupons",
+    tag = "coupons",
+    responses(
+        (status = 200, description = "All coupons", body = Vec<Coupon>),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_coupons(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let coupons = sqlx::query_as::<_, Coupon>(&format!("{} ORDER BY created_at DESC, id DESC", COUPON_SELECT))
+        .fetch_all(&state.db)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(coupons))
+}
+
+// Create coupon (admin)
+#[utoipa::path(
+    post,
+    path = "/coupons",
+    tag = "coupons",
+    request_body = CreateCoupon,
+    responses(
+        (status = 201, description = "Coupon created", body = Coupon),
+        (status = 422, description = "Invalid coupon", body = Problem),
+        (status = 409, description = "Code already exists", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_coupon(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    coupon: web::Json<CreateCoupon>,
+) -> Result<HttpResponse> {
+    let coupon = coupon.into_inner();
+    validate_coupon(&coupon)?;
+    
+    let mut conn = state.db.acquire().await?;
+    
+    ensure_code_free(&mut conn, &coupon.code, None).await?;
+    
+    let coupon_id = sqlx::query(
+        r#"
+        INSERT INTO coupons (code, kind, percent_off, amount_off_cents, min_order_cents,
+            expires_at, max_uses, max_uses_per_customer, active)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        "#
+    )
+    .bind(coupon.code.trim())
+    .bind(coupon.kind)
+    .bind(coupon.percent_off)
+    .bind(coupon.amount_off.map(Cents::try_from).transpose()?)
+    .bind(Cents::try_from(coupon.min_order.unwrap_or(Decimal::ZERO))?)
+    .bind(expiry(&coupon))
+    .bind(coupon.max_uses)
+    .bind(coupon.max_uses_per_customer)
+    .bind(coupon.active)
+    .execute(&mut *conn)
+    .await?
+    .last_insert_rowid();
+    
+    let created = find_coupon_by_id(&mut conn, coupon_id).await?
+        .ok_or(AppError::NotFound)?;
+    
+    Ok(HttpResponse::Created().json(created))
+}
+
+// Update coupon (admin)
+#[utoipa::path(
+    put,
+    path = "/coupons/{id}",
+    tag = "coupons",
+    params(("id" = i64, Path, description = "Coupon id")),
+    request_body = CreateCoupon,
+    responses(
+        (status = 200, description = "Coupon updated", body = Coupon),
+        (status = 404, description = "No such coupon", body = Problem),
+        (status = 409, description = "Code already exists", body = Problem),
+        (status = 422, description = "Invalid coupon", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn update_coupon(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    coupon: web::Json<CreateCoupon>,
+) -> Result<HttpResponse> {
+    let coupon_id = path.into_inner();
+    let coupon = coupon.into_inner();
+    validate_coupon(&coupon)?;
+    
+    let mut conn = state.db.acquire().await?;
+    ensure_code_free(&mut conn, &coupon.code, Some(coupon_id)).await?;
+    
+    let result = sqlx::query(
+        r#"
+        UPDATE coupons
+        SET code = ?1, kind = ?2, percent_off = ?3, amount_off_cents = ?4, min_order_cents = ?5,
+            expires_at = ?6, max_uses = ?7, max_uses_per_customer = ?8, active = ?9,
+            updated_at = datetime('now')
+        WHERE id = ?10
+        "#
+    )
+    .bind(coupon.code.trim())
+    .bind(coupon.kind)
+    .bind(coupon.percent_off)
+    .bind(coupon.amount_off.map(Cents::try_from).transpose()?)
+    .bind(Cents::try_from(coupon.min_order.unwrap_or(Decimal::ZERO))?)
+    .bind(expiry(&coupon))
+    .bind(coupon.max_uses)
+    .bind(coupon.max_uses_per_customer)
+    .bind(coupon.active)
+    .bind(coupon_id)
+    .execute(&mut *conn)
+    .await?;
+    
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    
+    let updated = find_coupon_by_id(&mut conn, coupon_id).await?
+        .ok_or(AppError::NotFound)?;
+    
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+// Delete coupon (admin). Orders keep the code and discount they were placed with.
+#[utoipa::path(
+    delete,
+    path = "/coupons/{id}",
+    tag = "coupons",
+    params(("id" = i64, Path, description = "Coupon id")),
+    responses(
+        (status = 204, description = "Coupon deleted"),
+        (status = 404, description = "No such coupon", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn delete_coupon(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let coupon_id = path.into_inner();
+    
+    let result = sqlx::query("DELETE FROM coupons WHERE id = ?1")
+        .bind(coupon_id)
+        .execute(&state.db)
+        .await?;
+    
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+// Check that a code can be used on a cart with this subtotal. Per-customer
+// limits are only checked once we know who is buying: the signed-in
+// customer, or the email given at checkout.
+pub async fn check_coupon(
+    conn: &mut SqliteConnection,
+    code: &str,
+    subtotal: Decimal,
+    customer_id: Option<i64>,
+    customer_email: Option<&str>,
+) -> Result<Coupon> {
+    let invalid = || AppError::BadRequest(format!("Coupon code {} is not valid", code));
+    
+    let coupon = sqlx::query_as::<_, Coupon>(&format!("{} WHERE code = ?1", COUPON_SELECT))
+        .bind(code.trim())
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(invalid)?;
+    
+    if !coupon.active {
+        return Err(invalid());
+    }
+    
+    let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    if coupon.expires_at.as_deref().is_some_and(|at| at <= now.as_str()) {
+        return Err(AppError::BadRequest(format!("Coupon code {} has expired", coupon.code)));
+    }
+    
+    if subtotal < coupon.min_order {
+        return Err(AppError::BadRequest(format!(
+            "Coupon code {} needs a minimum order of {}", coupon.code, coupon.min_order
+        )));
+    }
+    
+    if coupon.max_uses.is_some_and(|max| coupon.times_used >= max) {
+        return Err(AppError::BadRequest(format!("Coupon code {} has been used up", coupon.code)));
+    }
+    
+    let customer_known = customer_id.is_some() || customer_email.is_some();
+    if let Some(max) = coupon.max_uses_per_customer.filter(|_| customer_known) {
+        let used: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM coupon_redemptions
+            WHERE coupon_id = ?1 AND (customer_id = ?2 OR customer_email = ?3)
+            "#
+        )
+        .bind(coupon.id)
+        .bind(customer_id)
+        .bind(customer_email)
+        .fetch_one(&mut *conn)
+        .await?;
+        
+        if used >= max {
+            return Err(AppError::BadRequest(format!(
+                "You have already used coupon code {}", coupon.code
+            )));
+        }
+    }
+    
+    Ok(coupon)
+}
+
+async fn find_coupon_by_id(conn: &mut SqliteConnection, coupon_id: i64) -> Result<Option<Coupon>> {
+    let coupon = sqlx::query_as::<_, Coupon>(&format!("{} WHERE id = ?1", COUPON_SELECT))
+        .bind(coupon_id)
+        .fetch_optional(conn)
+        .await?;
+    
+    Ok(coupon)
+}
+
+// Codes are unique regardless of case
+async fn ensure_code_free(conn: &mut SqliteConnection, code: &str, except_id: Option<i64>) -> Result<()> {
+    let taken: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM coupons WHERE code = ?1 AND id IS NOT ?2)"
+    )
+    .bind(code.trim())
+    .bind(except_id)
+    .fetch_one(conn)
+    .await?;
+    
+    if taken {
+        return Err(AppError::Conflict(format!("Coupon code {} already exists", code.trim())));
+    }
+    Ok(())
+}
+
+// Each kind carries exactly the amount it needs
+fn validate_coupon(coupon: &CreateCoupon) -> Result<()> {
+    let mut errors = coupon.validate().err().unwrap_or_default();
+    let mut require = |field: &'static str, ok: bool, message: &'static str| {
+        if !ok {
+            errors.add(field, ValidationError::new("kind").with_message(message.into()));
+        }
+    };
+    
+    let (percent_off, amount_off) = (coupon.percent_off.is_some(), coupon.amount_off.is_some());
+    match coupon.kind {
+        CouponKind::Percentage => {
+            require("percent_off", percent_off, "is required for percentage coupons");
+            require("amount_off", !amount_off, "only applies to fixed coupons");
+        },
+        CouponKind::Fixed => {
+            require("amount_off", amount_off, "is required for fixed coupons");
+            require("amount_off", coupon.amount_off.is_none_or(|a| a > Decimal::ZERO), "must be positive");
+            require("percent_off", !percent_off, "only applies to percentage coupons");
+        },
+        CouponKind::FreeShipping => {
+            require("percent_off", !percent_off, "only applies to percentage coupons");
+            require("amount_off", !amount_off, "only applies to fixed coupons");
+        },
+    }
+    
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+// Stored in the same format as SQLite's datetime('now')
+fn expiry(coupon: &CreateCoupon) -> Option<String> {
+    coupon.expires_at.map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use crate::test_support::{admin_cookie, test_app, TestDb};
+    
+    #[actix_web::test]
+    async fn invalid_coupons_are_reported_by_field() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let admin = admin_cookie(&app, &db).await;
+        let create = |coupon: serde_json::Value| test::TestRequest::post()
+            .uri("/api/v1/coupons")
+            .cookie(admin.clone())
+            .set_json(coupon)
+            .to_request();
+        
+        let response = test::call_service(&app, create(serde_json::json!({
+            "code": " ",
+            "kind": "fixed",
+            "percent_off": 10,
+            "min_order": "-5",
+            "max_uses": 0,
+        }))).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["fields"], serde_json::json!({
+            "amount_off": ["is required for fixed coupons"],
+            "code": ["must not be blank"],
+            "max_uses": ["must be at least 1"],
+            "min_order": ["must not be negative"],
+            "percent_off": ["only applies to percentage coupons"],
+        }));
+        
+        let response = test::call_service(&app, create(serde_json::json!({
+            "code": "SAVE10",
+            "kind": "percentage",
+            "percent_off": 10,
+        }))).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
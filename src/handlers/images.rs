@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+
+use actix_multipart::form::{bytes::Bytes, text::Text, MultipartForm};
+use actix_web::{web, HttpResponse};
+use sqlx::{SqliteConnection, SqlitePool};
+use crate::{
+    models::{ImageUrls, ProductImage, ProductStatus, ReorderImages},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AdminUser,
+    images::{process_image, ProcessedImage, MAX_IMAGE_BYTES, RENDITIONS},
+    storage::Storage,
+};
+
+#[derive(MultipartForm, utoipa::ToSchema)]
+pub struct UploadImage {
+    // JPEG, PNG or WebP, up to MAX_IMAGE_BYTES. The form as a whole is
+    // capped by the multipart config in api::extractor_config.
+    #[schema(value_type = String, format = Binary)]
+    pub file: Bytes,
+    #[schema(value_type = Option<String>)]
+    pub alt_text: Option<Text<String>>,
+}
+
+// Images of a product, in display order
+#[utoipa::path(
+    get,
+    path = "/products/{id}/images",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "The product's images, first to last", body = Vec<ProductImage>),
+    ),
+)]
+pub async fn get_product_images(
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let images = load_images(&state.db, state.storage.as_ref(), path.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(images))
+}
+
+// Upload an image (admin). It goes after the product's existing images;
+// the first image also becomes the product's `image_url`.
+#[utoipa::path(
+    post,
+    path = "/products/{id}/images",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    request_body(content = UploadImage, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "Image stored with its renditions", body = ProductImage),
+        (status = 400, description = "Not a readable JPEG, PNG or WebP image, or too large", body = Problem),
+        (status = 404, description = "No such product", body = Problem),
+        (status = 409, description = "Product is archived", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn upload_product_image(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+    form: MultipartForm<UploadImage>,
+) -> Result<HttpResponse> {
+    let product_id = path.into_inner();
+    let UploadImage { file, alt_text } = form.into_inner();
+    let alt_text = alt_text.map(|t| t.into_inner().trim().to_string()).filter(|t| !t.is_empty());
+    
+    if file.data.len() > MAX_IMAGE_BYTES {
+        let limit = MAX_IMAGE_BYTES / (1024 * 1024);
+        return Err(AppError::BadRequest(format!("Images must be at most {} MiB", limit)));
+    }
+    
+    let status: ProductStatus = sqlx::query_scalar("SELECT status FROM products WHERE id = ?1")
+        .bind(product_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if status == ProductStatus::Archived {
+        return Err(AppError::Conflict("Archived products can't take new images".to_string()));
+    }
+    
+    let original = file.data.to_vec();
+    let (processed, original) = web::block(move || process_image(&original).map(|image| (image, original)))
+        .await
+        .map_err(|_| AppError::InternalError)??;
+    
+    let storage = state.storage.as_ref();
+    let storage_key = format!("products/{}/{}", product_id, uuid::Uuid::new_v4().simple());
+    store_files(storage, &storage_key, &processed, original).await?;
+    
+    let saved = save_image(&state.db, storage, product_id, &storage_key, &processed, alt_text.as_deref()).await;
+    match saved {
+        Ok(image) => Ok(HttpResponse::Created().json(with_urls(storage, image))),
+        Err(e) => {
+            remove_files(storage, &storage_key, processed.extension()).await;
+            Err(e)
+        },
+    }
+}
+
+// Put a product's images in a new order (admin)
+#[utoipa::path(
+    put,
+    path = "/products/{id}/images/order",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    request_body = ReorderImages,
+    responses(
+        (status = 200, description = "The images in their new order", body = Vec<ProductImage>),
+        (status = 400, description = "The ids aren't exactly the product's images", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn reorder_product_images(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+    order: web::Json<ReorderImages>,
+) -> Result<HttpResponse> {
+    let product_id = path.into_inner();
+    let image_ids = order.into_inner().image_ids;
+    let storage = state.storage.as_ref();
+    
+    let mut tx = state.db.begin().await?;
+    
+    let current: HashSet<i64> = sqlx::query_scalar("SELECT id FROM product_images WHERE product_id = ?1")
+        .bind(product_id)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+    let requested: HashSet<i64> = image_ids.iter().copied().collect();
+    if requested.len() != image_ids.len() || requested != current {
+        return Err(AppError::BadRequest(
+            "image_ids must list each of the product's images exactly once".to_string()
+        ));
+    }
+    
+    for (position, image_id) in image_ids.iter().enumerate() {
+        sqlx::query("UPDATE product_images SET position = ?1 WHERE id = ?2")
+            .bind(position as i64)
+            .bind(image_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sync_image_url(&mut tx, storage, product_id, None).await?;
+    
+    tx.commit().await?;
+    
+    let images = load_images(&state.db, storage, product_id).await?;
+    Ok(HttpResponse::Ok().json(images))
+}
+
+// Delete an image and its files (admin)
+#[utoipa::path(
+    delete,
+    path = "/products/{id}/images/{image_id}",
+    tag = "products",
+    params(
+        ("id" = i32, Path, description = "Product id"),
+        ("image_id" = i64, Path, description = "Image id"),
+    ),
+    responses(
+        (status = 204, description = "Image deleted"),
+        (status = 404, description = "No such image on this product", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn delete_product_image(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<(i32, i64)>,
+) -> Result<HttpResponse> {
+    let (product_id, image_id) = path.into_inner();
+    let storage = state.storage.as_ref();
+    
+    let mut tx = state.db.begin().await?;
+    
+    let image = sqlx::query_as::<_, ProductImage>(
+        "DELETE FROM product_images WHERE id = ?1 AND product_id = ?2 RETURNING *"
+    )
+    .bind(image_id)
+    .bind(product_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    sync_image_url(&mut tx, storage, product_id, Some(&image)).await?;
+    
+    // Past orders show the image they were bought with, so its files stay
+    // as long as an order line still points at any of them
+    let urls = with_urls(storage, image.clone()).urls;
+    let ordered: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM order_items WHERE image_url IN (?1, ?2, ?3, ?4))"
+    )
+    .bind(&urls.original)
+    .bind(&urls.thumbnail)
+    .bind(&urls.medium)
+    .bind(&urls.large)
+    .fetch_one(&mut *tx)
+    .await?;
+    
+    tx.commit().await?;
+    
+    if !ordered {
+        remove_files(storage, &image.storage_key, &image.extension).await;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn load_images(db: &SqlitePool, storage: &dyn Storage, product_id: i32) -> Result<Vec<ProductImage>> {
+    let images = sqlx::query_as::<_, ProductImage>(
+        "SELECT * FROM product_images WHERE product_id = ?1 ORDER BY position, id"
+    )
+    .bind(product_id)
+    .fetch_all(db)
+    .await?;
+    
+    Ok(images.into_iter().map(|image| with_urls(storage, image)).collect())
+}
+
+// Delete the stored files of images whose rows are already gone. Failures
+// are logged rather than returned, since the images no longer exist as far
+// as the shop is concerned.
+pub async fn remove_image_files(storage: &dyn Storage, images: &[ProductImage]) {
+    for image in images {
+        remove_files(storage, &image.storage_key, &image.extension).await;
+    }
+}
+
+fn with_urls(storage: &dyn Storage, mut image: ProductImage) -> ProductImage {
+    let url = |name: &str| storage.url(&file_key(&image.storage_key, name, &image.extension));
+    image.urls = ImageUrls {
+        original: url("original"),
+        thumbnail: url("thumbnail"),
+        medium: url("medium"),
+        large: url("large"),
+    };
+    image
+}
+
+fn file_key(storage_key: &str, name: &str, extension: &str) -> String {
+    format!("{}/{}.{}", storage_key, name, extension)
+}
+
+// Write the upload and its renditions; on failure, whatever was written is removed again
+async fn store_files(storage: &dyn Storage, storage_key: &str, image: &ProcessedImage, original: Vec<u8>) -> Result<()> {
+    let files = std::iter::once(("original", original))
+        .chain(image.renditions.iter().map(|(name, bytes)| (*name, bytes.clone())));
+    for (name, bytes) in files {
+        let key = file_key(storage_key, name, image.extension());
+        if let Err(e) = storage.put(&key, bytes, image.content_type()).await {
+            remove_files(storage, storage_key, image.extension()).await;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+async fn remove_files(storage: &dyn Storage, storage_key: &str, extension: &str) {
+    let names = std::iter::once("original").chain(RENDITIONS.iter().map(|(name, _)| *name));
+    for name in names {
+        let key = file_key(storage_key, name, extension);
+        if let Err(e) = storage.delete(&key).await {
+            log::error!("Failed to delete stored file {}: {}", key, e);
+        }
+    }
+}
+
+async fn save_image(
+    db: &SqlitePool,
+    storage: &dyn Storage,
+    product_id: i32,
+    storage_key: &str,
+    image: &ProcessedImage,
+    alt_text: Option<&str>,
+) -> Result<ProductImage> {
+    let mut tx = db.begin().await?;
+    
+    let saved = sqlx::query_as::<_, ProductImage>(
+        r#"
+        INSERT INTO product_images (product_id, position, storage_key, extension, content_type, width, height, alt_text)
+        VALUES (
+            ?1,
+            (SELECT IFNULL(MAX(position) + 1, 0) FROM product_images WHERE product_id = ?1),
+            ?2, ?3, ?4, ?5, ?6, ?7
+        )
+        RETURNING *
+        "#
+    )
+    .bind(product_id)
+    .bind(storage_key)
+    .bind(image.extension())
+    .bind(image.content_type())
+    .bind(image.width)
+    .bind(image.height)
+    .bind(alt_text)
+    .fetch_one(&mut *tx)
+    .await?;
+    sync_image_url(&mut tx, storage, product_id, None).await?;
+    
+    tx.commit().await?;
+    Ok(saved)
+}
+
+// Point the product's `image_url` at the medium rendition of its first image,
+// so clients that only know the single URL keep working. When the last image
+// is removed, a URL that pointed at it is cleared; one typed in by hand is
+// left alone.
+async fn sync_image_url(
+    conn: &mut SqliteConnection,
+    storage: &dyn Storage,
+    product_id: i32,
+    removed: Option<&ProductImage>,
+) -> Result<()> {
+    let first = sqlx::query_as::<_, ProductImage>(
+        "SELECT * FROM product_images WHERE product_id = ?1 ORDER BY position, id LIMIT 1"
+    )
+    .bind(product_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    
+    match (first, removed) {
+        (Some(first), _) => {
+            sqlx::query("UPDATE products SET image_url = ?1, updated_at = datetime('now') WHERE id = ?2")
+                .bind(with_urls(storage, first).urls.medium)
+                .bind(product_id)
+                .execute(&mut *conn)
+                .await?;
+        },
+        (None, Some(removed)) => {
+            sqlx::query("UPDATE products SET image_url = NULL, updated_at = datetime('now') WHERE id = ?1 AND image_url = ?2")
+                .bind(product_id)
+                .bind(with_urls(storage, removed.clone()).urls.medium)
+                .execute(&mut *conn)
+                .await?;
+        },
+        (None, None) => {},
+    }
+    
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test};
+    use super::*;
+    use crate::test_support::{admin_cookie, image_upload, insert_product, png, test_app, TestDb};
+    
+    #[actix_web::test]
+    async fn uploads_are_capped_and_refused_for_archived_products() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let admin = admin_cookie(&app, &db).await;
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        
+        let too_large = vec![0; MAX_IMAGE_BYTES + 1];
+        let response = test::call_service(&app, image_upload(product_id, &admin, &too_large)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["detail"], "Images must be at most 10 MiB");
+        
+        let upload = || image_upload(product_id, &admin, &png(40, 30));
+        assert_eq!(test::call_service(&app, upload()).await.status(), StatusCode::CREATED);
+        
+        let archive = test::TestRequest::delete()
+            .uri(&format!("/api/v1/products/{}", product_id))
+            .cookie(admin.clone())
+            .to_request();
+        assert_eq!(test::call_service(&app, archive).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(test::call_service(&app, upload()).await.status(), StatusCode::CONFLICT);
+    }
+}
@@ -2,8 +2,92 @@ pub mod products;
 pub mod categories;
 pub mod cart;
 pub mod orders;
+pub mod auth;
+pub mod variants;
+pub mod coupons;
+pub mod tax;
+pub mod shipping;
+pub mod addresses;
+pub mod payments;
+pub mod images;
+pub mod tags;
 
-use actix_web::{HttpResponse, Result};
+use actix_web::{http::header, HttpRequest, HttpResponse, Result};
+use crate::{auth::AdminUser, models::Page};
+
+pub const DEFAULT_PER_PAGE: i64 = 20;
+pub const MAX_PER_PAGE: i64 = 100;
+
+// Plain acknowledgement for actions with nothing else to return
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Message {
+    pub message: String,
+}
+
+impl Message {
+    pub fn new(message: &str) -> Self {
+        Message { message: message.to_string() }
+    }
+}
+
+// Paging parameters for listings without filters of their own
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+// Parse an optional query parameter from its text. Needed for numbers and
+// booleans in structs flattened into a query, which serde only ever sees as
+// strings.
+pub fn from_query_str<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    value.map(|v| v.parse().map_err(serde::de::Error::custom)).transpose()
+}
+
+// Clamp client supplied paging parameters to sane values. The page is capped
+// so that its offset always fits in an i64.
+pub fn page_params(page: Option<i64>, per_page: Option<i64>) -> (i64, i64) {
+    let page = page.unwrap_or(1).clamp(1, i64::MAX / MAX_PER_PAGE);
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    (page, per_page)
+}
+
+// Rows to skip for a page returned by page_params
+pub fn page_offset(page: i64, per_page: i64) -> i64 {
+    (page - 1).saturating_mul(per_page)
+}
+
+// Wrap a page of results, linking to the neighbours with the request's other
+// query parameters preserved
+pub fn paginate<T>(req: &HttpRequest, items: Vec<T>, page: i64, per_page: i64, total: i64) -> Page<T> {
+    let total_pages = (total + per_page - 1) / per_page;
+    let link = |target: i64| {
+        let mut params: Vec<&str> = req.query_string()
+            .split('&')
+            .filter(|p| !p.is_empty() && !p.starts_with("page="))
+            .collect();
+        let page_param = format!("page={}", target);
+        params.push(&page_param);
+        format!("{}?{}", req.path(), params.join("&"))
+    };
+    
+    Page {
+        next: (page < total_pages).then(|| link(page + 1)),
+        prev: (page > 1).then(|| link((page - 1).min(total_pages.max(1)))),
+        items,
+        page,
+        per_page,
+        total,
+        total_pages,
+    }
+}
 
 pub async fn index() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().body(include_str!("../../templates/index.html")))
@@ -13,14 +97,28 @@ pub async fn store_page() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().body(include_str!("../../templates/store.html")))
 }
 
-pub async fn admin_page() -> Result<HttpResponse> {
+pub async fn admin_page(admin: Option<AdminUser>) -> Result<HttpResponse> {
+    if admin.is_none() {
+        return Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, "/login?next=/admin"))
+            .finish());
+    }
     Ok(HttpResponse::Ok().body(include_str!("../../templates/admin.html")))
 }
 
+pub async fn login_page() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().body(include_str!("../../templates/login.html")))
+}
+
 pub async fn cart_page() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().body(include_str!("../../templates/cart.html")))
 }
 
+// Fallback for unknown routes, in the same format as other errors
+pub async fn not_found() -> crate::errors::Result<HttpResponse> {
+    Err(crate::errors::AppError::NotFound)
+}
+
 // pub async fn index() -> Result<HttpResponse> {
 //     Ok(HttpResponse::Ok().body(r#"
 //         <!DOCTYPE html>
@@ -51,4 +149,24 @@ pub async fn cart_page() -> Result<HttpResponse> {
 //         </body>
 //         </html>
 //     "#))
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn page_params_are_clamped_and_offsets_never_overflow() {
+        assert_eq!(page_params(None, None), (1, DEFAULT_PER_PAGE));
+        assert_eq!(page_params(Some(0), Some(0)), (1, 1));
+        assert_eq!(page_params(Some(-5), Some(1000)), (1, MAX_PER_PAGE));
+        assert_eq!(page_params(Some(3), Some(10)), (3, 10));
+        
+        let (page, per_page) = page_params(Some(i64::MAX), Some(i64::MAX));
+        assert_eq!((page, per_page), (i64::MAX / MAX_PER_PAGE, MAX_PER_PAGE));
+        assert!(page_offset(page, per_page) > 0);
+        
+        assert_eq!(page_offset(1, 20), 0);
+        assert_eq!(page_offset(3, 10), 20);
+        assert_eq!(page_offset(i64::MAX, 2), i64::MAX);
+    }
+}
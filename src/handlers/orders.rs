@@ -1,110 +1,429 @@
 use actix_session::Session;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
+use rust_decimal::Decimal;
+use sqlx::{SqliteConnection, SqlitePool};
+use validator::{Validate, ValidationError, ValidationErrors};
 use crate::{
-    models::Order, 
-    errors::{Result, AppError}, 
+    models::{
+        Address, AddressKind, Cart, Cents, CouponKind, DecimalText, Order, OrderAddress, OrderStatus,
+        OrderStatusChange, Page, Payment, User, UserRole, not_blank,
+    },
+    errors::{Result, AppError, Problem}, 
     AppState,
-    handlers::cart::{get_cart_from_session, save_cart_to_session}
+    auth::{AdminUser, AuthenticatedUser},
+    handlers::{
+        addresses::find_saved_address,
+        cart::{load_cart, resolve_line, save_cart},
+        coupons::check_coupon,
+        page_offset, page_params, paginate, PageQuery,
+        payments::{finish_refunds, open_payment, request_refunds},
+        shipping::quote_methods,
+    },
+    tax::{allocate, apply_rate, rates_for_destination, tax_class_for_product, PricingMode},
 };
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Validate, utoipa::ToSchema)]
 pub struct CreateOrder {
+    #[validate(custom(function = "not_blank"), length(max = 200, message = "must be at most 200 characters"))]
     pub customer_name: String,
+    #[validate(email(message = "must be a valid email address"))]
     pub customer_email: String,
-    pub shipping_address: String,
+    // Each address is given in full or, for signed-in customers, as the id
+    // of a saved one. Billing defaults to the shipping address.
+    #[serde(default)]
+    pub shipping_address: Option<Address>,
+    #[serde(default)]
+    pub shipping_address_id: Option<i64>,
+    #[serde(default)]
+    pub billing_address: Option<Address>,
+    #[serde(default)]
+    pub billing_address_id: Option<i64>,
+    #[serde(default)]
+    pub shipping_method_id: Option<i64>,
 }
 
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct OrderCreated {
+    pub message: String,
+    pub order_id: i64,
+    pub total: Decimal,
+    // Missing if the payment provider couldn't be reached
+    pub payment: Option<Payment>,
+    // Guest orders only: pass as `?token=` to view the order
+    pub access_token: Option<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OrderAccessQuery {
+    // The access token a guest order was created with
+    pub token: Option<String>,
+}
+
+#[derive(sqlx::FromRow, serde::Serialize, utoipa::ToSchema)]
+pub struct OrderItem {
+    pub id: i64,
+    pub order_id: i64,
+    pub product_id: i64,
+    pub variant_id: Option<i64>,
+    pub quantity: i32,
+    #[sqlx(rename = "price_cents", try_from = "Cents")]
+    pub price: Decimal,
+    #[sqlx(rename = "subtotal_cents", try_from = "Cents")]
+    pub subtotal: Decimal,
+    #[sqlx(rename = "tax_cents", try_from = "Cents")]
+    pub tax: Decimal,
+    #[sqlx(rename = "total_cents", try_from = "Cents")]
+    pub total: Decimal,
+    #[sqlx(try_from = "DecimalText")]
+    pub tax_rate: Decimal,
+    pub created_at: String,
+    // Product details as they were when the order was placed
+    pub product_name: String,
+    pub sku: Option<String>,
+    pub image_url: Option<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct OrderDetails {
+    pub order: Order,
+    pub items: Vec<OrderItem>,
+    pub addresses: Vec<OrderAddress>,
+    pub history: Vec<OrderStatusChange>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct UpdateOrderStatus {
+    pub status: OrderStatus,
+    pub note: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/orders",
+    tag = "orders",
+    request_body = CreateOrder,
+    responses(
+        (status = 200, description = "Order placed and awaiting payment", body = OrderCreated),
+        (status = 400, description = "Empty cart or unavailable shipping method", body = Problem),
+        (status = 409, description = "An item ran out of stock", body = Problem),
+        (status = 422, description = "Invalid customer details or address", body = Problem),
+    ),
+)]
 pub async fn create_order(
     session: Session,
     state: web::Data<AppState>,
+    customer: Option<AuthenticatedUser>,
     order_data: web::Json<CreateOrder>,
 ) -> Result<HttpResponse> {
-    let order_data = order_data.into_inner();
-    let mut cart = get_cart_from_session(&session)?;
-    
-    // Load product details for cart
-    let mut total_amount = 0.0;
-    for item in &mut cart.items {
-        let product = sqlx::query_as::<_, crate::models::Product>(
-            "SELECT * FROM products WHERE id = ?1"
-        )
-        .bind(item.product_id)
-        .fetch_optional(&state.db)
-        .await?
-        .ok_or(AppError::NotFound)?;
-        
-        // Check stock
-        if product.stock_quantity < item.quantity {
-            return Err(AppError::BadRequest(
-                format!("Insufficient stock for {}", product.name)
-            ));
+    let mut order_data = order_data.into_inner();
+    order_data.validate()?;
+    // Signed-in shoppers own their orders; guests check out anonymously
+    let customer_id = customer.map(|c| c.0.id);
+    
+    // Fill in addresses picked from the address book
+    let saved = [
+        (order_data.shipping_address_id, &mut order_data.shipping_address),
+        (order_data.billing_address_id, &mut order_data.billing_address),
+    ];
+    for (address_id, address) in saved {
+        if let Some(address_id) = address_id {
+            let customer_id = customer_id.ok_or(AppError::Unauthorized)?;
+            *address = Some(find_saved_address(&state.db, customer_id, address_id).await?);
         }
-        
-        total_amount += product.price * item.quantity as f64;
-        item.product = Some(product);
     }
     
-    // Begin transaction
-    let mut tx = state.db.begin().await?;
+    let mut cart = load_cart(&state.db, &session).await?;
+    
+    let (order_id, total_amount) = place_order(&state.db, &cart, &order_data, customer_id, state.pricing).await?;
     
-    // Create order
+    // Clear cart
+    cart.clear();
+    save_cart(&state.db, &session, &cart).await?;
+    
+    // The order stays pending until the payment is captured. If the provider
+    // can't be reached now, paying the order later opens a fresh intent.
+    let payment = match open_payment(&state.db, state.payments.as_ref(), order_id, total_amount).await {
+        Ok(payment) => Some(payment),
+        Err(e) => {
+            log::error!("Failed to open payment for order {}: {}", order_id, e);
+            None
+        },
+    };
+    
+    let access_token: Option<String> = sqlx::query_scalar("SELECT access_token FROM orders WHERE id = ?1")
+        .bind(order_id)
+        .fetch_one(&state.db)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(OrderCreated {
+        message: "Order created successfully".to_string(),
+        order_id,
+        total: total_amount,
+        payment,
+        access_token,
+    }))
+}
+
+// Turn a cart into an order. Stock is checked and reserved by conditional
+// updates inside the order transaction, so concurrent checkouts can never
+// oversell; the loser gets a 409 naming the product. Any discount is spread
+// over the lines before each is taxed at the rate for its class at the
+// shipping destination; shipping is charged on top, untaxed.
+pub async fn place_order(
+    db: &SqlitePool,
+    cart: &Cart,
+    order_data: &CreateOrder,
+    customer_id: Option<i64>,
+    pricing: PricingMode,
+) -> Result<(i64, Decimal)> {
+    if cart.items.is_empty() {
+        return Err(AppError::BadRequest("Cart is empty".to_string()));
+    }
+    
+    // Both addresses are checked before either is rejected, so the response
+    // names every bad field
+    let mut errors = ValidationErrors::new();
+    let shipping_address = checked_address(&mut errors, "shipping_address", order_data.shipping_address.clone());
+    let billing_address = checked_address(&mut errors, "billing_address", order_data.billing_address.clone());
+    if order_data.shipping_address.is_none() {
+        errors.add("shipping_address", ValidationError::new("required").with_message("is required".into()));
+    }
+    let Some(shipping_address) = shipping_address.filter(|_| errors.is_empty()) else {
+        return Err(AppError::Validation(errors));
+    };
+    let billing_address = billing_address.unwrap_or_else(|| shipping_address.clone());
+    
+    // Begin transaction. The order insert comes first so the transaction
+    // takes SQLite's write lock before reading any prices or stock levels.
+    let mut tx = db.begin().await?;
+    
+    // Create order; amounts are filled in once the lines are taxed
     let order_id: i64 = sqlx::query_scalar(
         r#"
-        INSERT INTO orders (total_amount, customer_name, customer_email, shipping_address, status)
-        VALUES (?1, ?2, ?3, ?4, 'pending')
+        INSERT INTO orders (total_cents, customer_name, customer_email, shipping_address, status, customer_id,
+            shipping_country, shipping_region, prices_include_tax, access_token)
+        VALUES (0, ?1, ?2, ?3, 'pending', ?4, ?5, ?6, ?7, ?8)
         RETURNING id
         "#
     )
-    .bind(total_amount)
     .bind(&order_data.customer_name)
     .bind(&order_data.customer_email)
-    .bind(&order_data.shipping_address)
+    .bind(shipping_address.formatted())
+    .bind(customer_id)
+    .bind(&shipping_address.country)
+    .bind(&shipping_address.region)
+    .bind(pricing.includes_tax())
+    .bind(customer_id.is_none().then(|| uuid::Uuid::new_v4().simple().to_string()))
     .fetch_one(&mut *tx)
     .await?;
     
-    // Create order items and update stock
+    for (kind, address) in [(AddressKind::Shipping, &shipping_address), (AddressKind::Billing, &billing_address)] {
+        sqlx::query(
+            r#"
+            INSERT INTO order_addresses (order_id, kind, name, line1, line2, city, region, postal_code, country, phone)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            "#
+        )
+        .bind(order_id)
+        .bind(kind)
+        .bind(&address.name)
+        .bind(&address.line1)
+        .bind(&address.line2)
+        .bind(&address.city)
+        .bind(&address.region)
+        .bind(&address.postal_code)
+        .bind(&address.country)
+        .bind(&address.phone)
+        .execute(&mut *tx)
+        .await?;
+    }
+    
+    record_status_change(&mut tx, order_id, None, OrderStatus::Pending, customer_id, None).await?;
+    
+    // Load product and variant details for cart. Under the write lock, the
+    // prices charged and snapshotted can't change before the commit.
+    let mut lines = Vec::with_capacity(cart.items.len());
+    let mut line_amounts = Vec::with_capacity(cart.items.len());
     for item in &cart.items {
-        let product = item.product.as_ref().unwrap();
+        let line = resolve_line(&mut tx, item.product_id, item.variant_id).await?;
+        line_amounts.push(line.unit_price() * Decimal::from(item.quantity));
+        lines.push(line);
+    }
+    let items_amount: Decimal = line_amounts.iter().sum();
+    
+    // Redeem the cart's coupon. Checking under the write lock means two
+    // checkouts can't both take the last use of a code.
+    let mut discount = Decimal::ZERO;
+    let mut coupon_code = None;
+    let mut free_shipping = false;
+    if let Some(code) = &cart.coupon_code {
+        let coupon = check_coupon(
+            &mut tx,
+            code,
+            items_amount,
+            customer_id,
+            Some(&order_data.customer_email),
+        ).await?;
+        discount = coupon.discount_for(items_amount);
         
-        // Insert order item
         sqlx::query(
             r#"
-            INSERT INTO order_items (order_id, product_id, quantity, price)
+            INSERT INTO coupon_redemptions (coupon_id, order_id, customer_id, customer_email)
             VALUES (?1, ?2, ?3, ?4)
             "#
         )
+        .bind(coupon.id)
         .bind(order_id)
-        .bind(item.product_id)
-        .bind(item.quantity)
-        .bind(product.price)
+        .bind(customer_id)
+        .bind(&order_data.customer_email)
         .execute(&mut *tx)
         .await?;
+        free_shipping = coupon.kind == CouponKind::FreeShipping;
+        coupon_code = Some(coupon.code);
+    }
+    
+    // Price the chosen shipping method for the discounted goods. Shipping
+    // is only optional while the shop has no methods set up.
+    let weight_grams: i64 = cart.items.iter().zip(&lines)
+        .map(|(item, line)| i64::from(line.product.weight_grams) * i64::from(item.quantity))
+        .sum();
+    let quotes = quote_methods(&mut tx, items_amount - discount, weight_grams, free_shipping).await?;
+    let shipping = match order_data.shipping_method_id {
+        Some(method_id) => Some(
+            quotes.into_iter()
+                .find(|q| q.method_id == method_id)
+                .ok_or_else(|| AppError::BadRequest(
+                    "That shipping method isn't available for this order".to_string()
+                ))?
+        ),
+        None => {
+            let has_methods: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM shipping_methods WHERE active = 1)"
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if has_methods {
+                return Err(AppError::BadRequest("Choose a shipping method".to_string()));
+            }
+            None
+        },
+    };
+    let shipping_cost = shipping.as_ref().map_or(Decimal::ZERO, |s| s.cost);
+    
+    let rates = rates_for_destination(
+        &mut tx,
+        Some(&shipping_address.country),
+        shipping_address.region.as_deref(),
+    ).await?;
+    let discount_shares = allocate(discount, &line_amounts);
+    
+    // Reserve stock and create order items. Variants carry their own stock.
+    let mut subtotal = Decimal::ZERO;
+    let mut tax = Decimal::ZERO;
+    let mut total_amount = Decimal::ZERO;
+    for (i, (item, line)) in cart.items.iter().zip(&lines).enumerate() {
+        let reserved = match &line.variant {
+            Some(variant) => sqlx::query(
+                r#"
+                UPDATE product_variants
+                SET stock_quantity = stock_quantity - ?1, updated_at = datetime('now')
+                WHERE id = ?2 AND stock_quantity >= ?1
+                "#
+            )
+            .bind(item.quantity)
+            .bind(variant.id)
+            .execute(&mut *tx)
+            .await?,
+            None => sqlx::query(
+                r#"
+                UPDATE products
+                SET stock_quantity = stock_quantity - ?1, updated_at = datetime('now')
+                WHERE id = ?2 AND stock_quantity >= ?1
+                "#
+            )
+            .bind(item.quantity)
+            .bind(item.product_id)
+            .execute(&mut *tx)
+            .await?,
+        };
+        
+        // Dropping the transaction rolls back anything already reserved
+        if reserved.rows_affected() == 0 {
+            return Err(line.out_of_stock());
+        }
+        
+        let tax_class_id = tax_class_for_product(&mut tx, item.product_id).await?;
+        let rate = rates.get(&tax_class_id).map_or(Decimal::ZERO, |r| r.rate);
+        let taxed = apply_rate(line_amounts[i] - discount_shares[i], rate, pricing);
+        subtotal += taxed.net;
+        tax += taxed.tax;
+        total_amount += taxed.gross;
         
-        // Update stock
         sqlx::query(
-            "UPDATE products SET stock_quantity = stock_quantity - ?1 WHERE id = ?2"
+            r#"
+            INSERT INTO order_items (order_id, product_id, variant_id, quantity, price_cents,
+                subtotal_cents, tax_cents, total_cents, tax_rate, product_name, sku, image_url)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            "#
         )
-        .bind(item.quantity)
+        .bind(order_id)
         .bind(item.product_id)
+        .bind(item.variant_id)
+        .bind(item.quantity)
+        .bind(Cents::try_from(line.unit_price())?)
+        .bind(Cents::try_from(taxed.net)?)
+        .bind(Cents::try_from(taxed.tax)?)
+        .bind(Cents::try_from(taxed.gross)?)
+        .bind(DecimalText::from(rate))
+        .bind(&line.product.name)
+        .bind(line.variant.as_ref().map(|v| &v.sku))
+        .bind(&line.product.image_url)
         .execute(&mut *tx)
         .await?;
     }
     
+    total_amount += shipping_cost;
+    
+    sqlx::query(
+        r#"
+        UPDATE orders
+        SET subtotal_cents = ?1, tax_cents = ?2, total_cents = ?3, discount_cents = ?4, coupon_code = ?5,
+            shipping_method_id = ?6, shipping_method = ?7, shipping_cents = ?8
+        WHERE id = ?9
+        "#
+    )
+    .bind(Cents::try_from(subtotal)?)
+    .bind(Cents::try_from(tax)?)
+    .bind(Cents::try_from(total_amount)?)
+    .bind(Cents::try_from(discount)?)
+    .bind(&coupon_code)
+    .bind(shipping.as_ref().map(|s| s.method_id))
+    .bind(shipping.as_ref().map(|s| &s.name))
+    .bind(Cents::try_from(shipping_cost)?)
+    .bind(order_id)
+    .execute(&mut *tx)
+    .await?;
+    
     // Commit transaction
     tx.commit().await?;
     
-    // Clear cart
-    cart.clear();
-    save_cart_to_session(&session, &cart)?;
-    
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "Order created successfully",
-        "order_id": order_id,
-        "total": total_amount
-    })))
+    Ok((order_id, total_amount))
 }
 
+#[utoipa::path(
+    get,
+    path = "/orders",
+    tag = "orders",
+    responses(
+        (status = 200, description = "All orders, newest first", body = Vec<Order>),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
 pub async fn get_orders(
+    _admin: AdminUser,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
     let orders = sqlx::query_as::<_, Order>(
@@ -116,65 +435,1206 @@ pub async fn get_orders(
     Ok(HttpResponse::Ok().json(orders))
 }
 
+// All orders a page at a time, newest first (admin, v2)
+#[utoipa::path(
+    get,
+    path = "/orders",
+    tag = "orders",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of orders, newest first", body = Page<Order>),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_orders_page(
+    _admin: AdminUser,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse> {
+    let (page, per_page) = page_params(query.page, query.per_page);
+    
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM orders")
+        .fetch_one(&state.db)
+        .await?;
+    let orders = sqlx::query_as::<_, Order>(
+        "SELECT * FROM orders ORDER BY created_at DESC, id DESC LIMIT ?1 OFFSET ?2"
+    )
+    .bind(per_page)
+    .bind(page_offset(page, per_page))
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(paginate(&req, orders, page, per_page, total)))
+}
+
+// Orders placed by the signed-in customer
+#[utoipa::path(
+    get,
+    path = "/me/orders",
+    tag = "orders",
+    responses(
+        (status = 200, description = "The signed-in customer's orders", body = Vec<Order>),
+        (status = 401, description = "Not signed in", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_my_orders(
+    state: web::Data<AppState>,
+    customer: AuthenticatedUser,
+) -> Result<HttpResponse> {
+    let orders = sqlx::query_as::<_, Order>(
+        "SELECT * FROM orders WHERE customer_id = ?1 ORDER BY created_at DESC"
+    )
+    .bind(customer.0.id)
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(orders))
+}
+
+// The signed-in customer's orders a page at a time (v2)
+#[utoipa::path(
+    get,
+    path = "/me/orders",
+    tag = "orders",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the signed-in customer's orders", body = Page<Order>),
+        (status = 401, description = "Not signed in", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_my_orders_page(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    customer: AuthenticatedUser,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse> {
+    let (page, per_page) = page_params(query.page, query.per_page);
+    
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM orders WHERE customer_id = ?1")
+        .bind(customer.0.id)
+        .fetch_one(&state.db)
+        .await?;
+    let orders = sqlx::query_as::<_, Order>(
+        r#"
+        SELECT * FROM orders WHERE customer_id = ?1
+        ORDER BY created_at DESC, id DESC
+        LIMIT ?2 OFFSET ?3
+        "#
+    )
+    .bind(customer.0.id)
+    .bind(per_page)
+    .bind(page_offset(page, per_page))
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(paginate(&req, orders, page, per_page, total)))
+}
+
+// An order, for an admin, the customer who placed it, or a guest holding
+// its access token. Anyone else gets a 404, as if it didn't exist.
+#[utoipa::path(
+    get,
+    path = "/orders/{id}",
+    tag = "orders",
+    params(("id" = i64, Path, description = "Order id"), OrderAccessQuery),
+    responses(
+        (status = 200, description = "The order with its items, addresses and history", body = OrderDetails),
+        (status = 404, description = "No such order, or not yours", body = Problem),
+    ),
+)]
 pub async fn get_order(
+    user: Option<AuthenticatedUser>,
     state: web::Data<AppState>,
     path: web::Path<i64>,
+    query: web::Query<OrderAccessQuery>,
 ) -> Result<HttpResponse> {
     let order_id = path.into_inner();
+    let order = find_visible_order(&state.db, order_id, user.map(|u| u.0).as_ref(), query.token.as_deref()).await?;
     
-    // Get order
-    let order = sqlx::query_as!(
-        Order,
+    // Get order items, as snapshotted at checkout
+    let items = sqlx::query_as::<_, OrderItem>(
+        "SELECT * FROM order_items WHERE order_id = ?1 ORDER BY id"
+    )
+    .bind(order_id)
+    .fetch_all(&state.db)
+    .await?;
+    
+    let history = sqlx::query_as::<_, OrderStatusChange>(
+        "SELECT * FROM order_status_history WHERE order_id = ?1 ORDER BY id"
+    )
+    .bind(order_id)
+    .fetch_all(&state.db)
+    .await?;
+    
+    let addresses = sqlx::query_as::<_, OrderAddress>(
+        "SELECT * FROM order_addresses WHERE order_id = ?1 ORDER BY kind DESC"
+    )
+    .bind(order_id)
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(OrderDetails {
+        order,
+        items,
+        addresses,
+        history,
+    }))
+}
+
+// An order, if the user (or, for guest orders, the holder of the access
+// token) may see it; a 404 otherwise, as if it didn't exist
+pub async fn find_visible_order(
+    db: &SqlitePool,
+    order_id: i64,
+    user: Option<&User>,
+    token: Option<&str>,
+) -> Result<Order> {
+    sqlx::query_as::<_, Order>(
         r#"
-        SELECT 
-            id as "id: i64",
-            total_amount,
-            status,
-            customer_name,
-            customer_email,
-            shipping_address,
-            created_at,
-            updated_at
-        FROM orders 
-        WHERE id = ?1
-        "#,
-        order_id
+        SELECT * FROM orders
+        WHERE id = ?1 AND (
+            ?2 OR customer_id = ?3
+            OR (customer_id IS NULL AND access_token = ?4)
+        )
+        "#
+    )
+    .bind(order_id)
+    .bind(user.is_some_and(|u| u.role == UserRole::Admin))
+    .bind(user.map(|u| u.id))
+    .bind(token)
+    .fetch_optional(db)
+    .await?
+    .ok_or(AppError::NotFound)
+}
+
+// Change order status (admin)
+#[utoipa::path(
+    patch,
+    path = "/orders/{id}/status",
+    tag = "orders",
+    params(("id" = i64, Path, description = "Order id")),
+    request_body = UpdateOrderStatus,
+    responses(
+        (status = 200, description = "Status changed", body = Order),
+        (status = 404, description = "No such order", body = Problem),
+        (status = 409, description = "The order can't move to that status, or the status is `paid`", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn update_order_status(
+    admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    update: web::Json<UpdateOrderStatus>,
+) -> Result<HttpResponse> {
+    let order_id = path.into_inner();
+    let update = update.into_inner();
+    // Orders are only paid by capturing a payment for them
+    if update.status == OrderStatus::Paid {
+        return Err(AppError::Conflict("Orders are marked paid when their payment is captured".to_string()));
+    }
+    
+    let mut tx = state.db.begin().await?;
+    let order = transition_order_status(
+        &mut tx,
+        order_id,
+        update.status,
+        Some(admin.0.id),
+        update.note.as_deref(),
+    )
+    .await?;
+    if order.status == OrderStatus::Cancelled {
+        restock_order_items(&mut tx, order_id).await?;
+        release_coupon(&mut tx, order_id).await?;
+    }
+    let refund = matches!(order.status, OrderStatus::Cancelled | OrderStatus::Refunded);
+    if refund {
+        request_refunds(&mut tx, state.payments.as_ref(), order_id).await?;
+    }
+    tx.commit().await?;
+    
+    if refund {
+        refund_committed(&state, order_id).await;
+    }
+    
+    Ok(HttpResponse::Ok().json(order))
+}
+
+// Cancel an order and put its items back in stock. Customers may cancel their
+// own orders while pending; admins may cancel any order before it ships.
+#[utoipa::path(
+    post,
+    path = "/orders/{id}/cancel",
+    tag = "orders",
+    params(("id" = i64, Path, description = "Order id")),
+    responses(
+        (status = 200, description = "Order cancelled", body = Order),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not your order", body = Problem),
+        (status = 404, description = "No such order", body = Problem),
+        (status = 409, description = "Only pending orders can be cancelled", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn cancel_order(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let order_id = path.into_inner();
+    let user = user.0;
+    
+    let order = sqlx::query_as::<_, Order>(
+        "SELECT * FROM orders WHERE id = ?1"
     )
+    .bind(order_id)
     .fetch_optional(&state.db)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    
+    if user.role != UserRole::Admin {
+        if order.customer_id != Some(user.id) {
+            return Err(AppError::Forbidden);
+        }
+        if order.status != OrderStatus::Pending {
+            return Err(AppError::Conflict(
+                "Only pending orders can be cancelled".to_string()
+            ));
+        }
+    }
+    
+    let mut tx = state.db.begin().await?;
+    let order = transition_order_status(
+        &mut tx,
+        order_id,
+        OrderStatus::Cancelled,
+        Some(user.id),
+        None,
+    )
+    .await?;
+    restock_order_items(&mut tx, order_id).await?;
+    release_coupon(&mut tx, order_id).await?;
+    request_refunds(&mut tx, state.payments.as_ref(), order_id).await?;
+    
+    tx.commit().await?;
+    
+    refund_committed(&state, order_id).await;
+    
+    Ok(HttpResponse::Ok().json(order))
+}
+
+// Make the refunds requested by a committed cancel or refund. The order's
+// change stands either way; a refund the provider turns down stays pending
+// and the cleanup task in main.rs tries it again.
+async fn refund_committed(state: &AppState, order_id: i64) {
+    if let Err(e) = finish_refunds(&state.db, state.payments.as_ref(), Some(order_id)).await {
+        log::error!("Refund for order {} failed; it will be retried: {}", order_id, e);
+    }
+}
+
+// The validated address, or None with its problems added under `field`
+fn checked_address(errors: &mut ValidationErrors, field: &'static str, address: Option<Address>) -> Option<Address> {
+    match address?.validated() {
+        Ok(address) => Some(address),
+        Err(address_errors) => {
+            errors.merge_self(field, Err(address_errors));
+            None
+        },
+    }
+}
+
+// Give a cancelled order's coupon use back; the order keeps its discount on record
+async fn release_coupon(conn: &mut SqliteConnection, order_id: i64) -> Result<()> {
+    sqlx::query("DELETE FROM coupon_redemptions WHERE order_id = ?1")
+        .bind(order_id)
+        .execute(conn)
+        .await?;
+    
+    Ok(())
+}
+
+// Return every line of an order to stock, to the variant when one was bought
+async fn restock_order_items(conn: &mut SqliteConnection, order_id: i64) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE products
+        SET stock_quantity = stock_quantity + (
+                SELECT SUM(oi.quantity) FROM order_items oi
+                WHERE oi.order_id = ?1 AND oi.product_id = products.id AND oi.variant_id IS NULL
+            ),
+            updated_at = datetime('now')
+        WHERE id IN (
+            SELECT product_id FROM order_items WHERE order_id = ?1 AND variant_id IS NULL
+        )
+        "#
+    )
+    .bind(order_id)
+    .execute(&mut *conn)
+    .await?;
+    
+    sqlx::query(
+        r#"
+        UPDATE product_variants
+        SET stock_quantity = stock_quantity + (
+                SELECT SUM(oi.quantity) FROM order_items oi
+                WHERE oi.order_id = ?1 AND oi.variant_id = product_variants.id
+            ),
+            updated_at = datetime('now')
+        WHERE id IN (
+            SELECT variant_id FROM order_items WHERE order_id = ?1 AND variant_id IS NOT NULL
+        )
+        "#
+    )
+    .bind(order_id)
+    .execute(&mut *conn)
     .await?;
     
-    match order {
-        Some(o) => {
-            // Get order items
-            #[derive(sqlx::FromRow, serde::Serialize)]
-            struct OrderItem {
-                id: i64,
-                order_id: i64,
-                product_id: i64,
-                quantity: i32,
-                price: f64,
-                created_at: String,
-                #[sqlx(rename = "name")]
-                product_name: String
+    Ok(())
+}
+
+// Move an order to a new status if the lifecycle allows it, recording the change
+pub async fn transition_order_status(
+    conn: &mut SqliteConnection,
+    order_id: i64,
+    to: OrderStatus,
+    changed_by: Option<i64>,
+    note: Option<&str>,
+) -> Result<Order> {
+    let from = check_transition(conn, order_id, to).await?;
+    
+    // Guard on the status we read so a concurrent change can't be overwritten
+    let order = sqlx::query_as::<_, Order>(
+        r#"
+        UPDATE orders
+        SET status = ?1, updated_at = datetime('now')
+        WHERE id = ?2 AND status = ?3
+        RETURNING *
+        "#
+    )
+    .bind(to)
+    .bind(order_id)
+    .bind(from)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| AppError::Conflict("Order status changed concurrently".to_string()))?;
+    
+    record_status_change(conn, order_id, Some(from), to, changed_by, note).await?;
+    
+    Ok(order)
+}
+
+// The order's current status, when it may move to `to`
+async fn check_transition(conn: &mut SqliteConnection, order_id: i64, to: OrderStatus) -> Result<OrderStatus> {
+    let from: OrderStatus = sqlx::query_scalar(
+        "SELECT status FROM orders WHERE id = ?1"
+    )
+    .bind(order_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    
+    if !from.can_transition_to(to) {
+        return Err(AppError::Conflict(
+            format!("Cannot change order status from {} to {}", from, to)
+        ));
+    }
+    Ok(from)
+}
+
+async fn record_status_change(
+    conn: &mut SqliteConnection,
+    order_id: i64,
+    from: Option<OrderStatus>,
+    to: OrderStatus,
+    changed_by: Option<i64>,
+    note: Option<&str>,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO order_status_history (order_id, from_status, to_status, changed_by, note)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        "#
+    )
+    .bind(order_id)
+    .bind(from)
+    .bind(to)
+    .bind(changed_by)
+    .bind(note)
+    .execute(conn)
+    .await?;
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc};
+    use actix_web::{cookie::Cookie, http::StatusCode, test};
+    use super::*;
+    use crate::{
+        handlers::payments::collect_payment,
+        models::{PaymentStatus, MAX_LINE_QUANTITY},
+        payments::{BoxFuture, CaptureOutcome, FakeProvider, PaymentIntent, PaymentProvider, WebhookEvent},
+        test_support::{
+            add_to_cart, admin_cookie, image_upload, insert_product, png, register, session_cookie, test_app,
+            test_app_with_payments, TestDb,
+        },
+    };
+    
+    fn order_data() -> CreateOrder {
+        CreateOrder {
+            customer_name: "Test Customer".to_string(),
+            customer_email: "test@example.com".to_string(),
+            shipping_address: Some(Address {
+                name: "Test Customer".to_string(),
+                line1: "1 Test Street".to_string(),
+                line2: None,
+                city: "Testville".to_string(),
+                region: None,
+                postal_code: Some("12345".to_string()),
+                country: "US".to_string(),
+                phone: None,
+            }),
+            shipping_address_id: None,
+            billing_address: None,
+            billing_address_id: None,
+            shipping_method_id: None,
+        }
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_checkouts_never_oversell() {
+        let db = TestDb::new().await;
+        let product_id = insert_product(&db.pool, "Widget", 5).await;
+        
+        let handles: Vec<_> = (0..20)
+            .map(|_| {
+                let pool = db.pool.clone();
+                tokio::spawn(async move {
+                    let mut cart = Cart::new();
+                    cart.add_item(product_id, None, 1);
+                    place_order(&pool, &cart, &order_data(), None, PricingMode::Exclusive).await
+                })
+            })
+            .collect();
+        
+        let mut placed = 0;
+        let mut out_of_stock = 0;
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok(_) => placed += 1,
+                Err(AppError::Conflict(message)) => {
+                    assert_eq!(message, "Widget is out of stock");
+                    out_of_stock += 1;
+                }
+                Err(e) => panic!("unexpected checkout error: {e}"),
             }
-            
-            let items = sqlx::query_as::<_, OrderItem>(
-                r#"
-                SELECT oi.*, p.name
-                FROM order_items oi
-                JOIN products p ON oi.product_id = p.id
-                WHERE oi.order_id = ?1
-                "#
+        }
+        
+        assert_eq!(placed, 5);
+        assert_eq!(out_of_stock, 15);
+        
+        let stock: i32 = sqlx::query_scalar("SELECT stock_quantity FROM products WHERE id = ?1")
+            .bind(product_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stock, 0);
+        
+        let orders: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM orders")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(orders, 5);
+    }
+    
+    #[tokio::test]
+    async fn failed_reservation_rolls_back_whole_order() {
+        let db = TestDb::new().await;
+        let plenty = insert_product(&db.pool, "Plenty", 10).await;
+        let scarce = insert_product(&db.pool, "Scarce", 1).await;
+        
+        let mut cart = Cart::new();
+        cart.add_item(plenty, None, 3);
+        cart.add_item(scarce, None, 2);
+        
+        let result = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive).await;
+        assert!(matches!(result, Err(AppError::Conflict(ref m)) if m == "Scarce is out of stock"));
+        
+        let stock: i32 = sqlx::query_scalar("SELECT stock_quantity FROM products WHERE id = ?1")
+            .bind(plenty)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stock, 10);
+        
+        let orders: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM orders")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(orders, 0);
+    }
+    
+    #[tokio::test]
+    async fn archived_products_cannot_be_ordered() {
+        let db = TestDb::new().await;
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 1);
+        
+        place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive).await.unwrap();
+        
+        sqlx::query("UPDATE products SET status = 'archived', deleted_at = datetime('now') WHERE id = ?1")
+            .bind(product_id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let result = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive).await;
+        assert!(matches!(result, Err(AppError::BadRequest(ref m)) if m == "Widget is not available"));
+    }
+    
+    #[actix_web::test]
+    async fn order_lines_keep_the_product_details_they_were_bought_with() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let admin = admin_cookie(&app, &db).await;
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        
+        let upload = || image_upload(product_id, &admin, &png(800, 600));
+        let first: serde_json::Value = test::call_and_read_body_json(&app, upload()).await;
+        // Pictured by the full-size original rather than the default medium rendition
+        let image_url = first["urls"]["original"].as_str().unwrap().to_string();
+        sqlx::query("UPDATE products SET image_url = ?1 WHERE id = ?2")
+            .bind(&image_url)
+            .bind(product_id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 2);
+        let (order_id, _) = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive)
+            .await
+            .unwrap();
+        
+        // The product is renamed, repriced and its pictured image replaced
+        sqlx::query("UPDATE products SET name = 'Gadget', price_cents = 5000 WHERE id = ?1")
+            .bind(product_id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        test::call_service(&app, upload()).await;
+        let delete = test::TestRequest::delete()
+            .uri(&format!("/api/v1/products/{}/images/{}", product_id, first["id"]))
+            .cookie(admin.clone())
+            .to_request();
+        assert_eq!(test::call_service(&app, delete).await.status(), StatusCode::NO_CONTENT);
+        
+        let request = test::TestRequest::get()
+            .uri(&format!("/api/v1/orders/{}", order_id))
+            .cookie(admin)
+            .to_request();
+        let order: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        let items = order["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["product_name"], "Widget");
+        assert_eq!(items[0]["price"], "10.00");
+        assert_eq!(items[0]["image_url"], image_url.as_str());
+        let image = test::call_service(&app, test::TestRequest::get().uri(&image_url).to_request()).await;
+        assert_eq!(image.status(), StatusCode::OK);
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_checkouts_respect_coupon_limit() {
+        let db = TestDb::new().await;
+        let product_id = insert_product(&db.pool, "Widget", 100).await;
+        sqlx::query("INSERT INTO coupons (code, kind, percent_off, max_uses) VALUES ('SAVE10', 'percentage', 10, 3)")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let pool = db.pool.clone();
+                tokio::spawn(async move {
+                    let mut cart = Cart::new();
+                    cart.add_item(product_id, None, 2);
+                    cart.coupon_code = Some("save10".to_string());
+                    place_order(&pool, &cart, &order_data(), None, PricingMode::Exclusive).await
+                })
+            })
+            .collect();
+        
+        let mut discounted = 0;
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok((_, total)) => {
+                    assert_eq!(total, Decimal::new(1800, 2));
+                    discounted += 1;
+                }
+                Err(AppError::BadRequest(message)) => {
+                    assert_eq!(message, "Coupon code SAVE10 has been used up");
+                }
+                Err(e) => panic!("unexpected checkout error: {e}"),
+            }
+        }
+        assert_eq!(discounted, 3);
+        
+        let (orders, discount_cents): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), SUM(discount_cents) FROM orders WHERE coupon_code = 'SAVE10'"
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!((orders, discount_cents), (3, 600));
+    }
+    
+    #[tokio::test]
+    async fn billing_address_defaults_to_the_shipping_address() {
+        let db = TestDb::new().await;
+        let product_id = insert_product(&db.pool, "Widget", 5).await;
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 1);
+        
+        let (order_id, _) = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive)
+            .await
+            .unwrap();
+        let addresses: Vec<(String, String, String, String)> = sqlx::query_as(
+            "SELECT kind, name, line1, country FROM order_addresses WHERE order_id = ?1 ORDER BY kind"
+        )
+        .bind(order_id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        let address = |kind: &str| (kind.to_string(), "Test Customer".to_string(), "1 Test Street".to_string(), "US".to_string());
+        assert_eq!(addresses, [address("billing"), address("shipping")]);
+    }
+    
+    #[tokio::test]
+    async fn invalid_addresses_are_reported_by_field() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let product_id = insert_product(&db.pool, "Widget", 5).await;
+        let guest = add_to_cart(&app, None, serde_json::json!({ "product_id": product_id, "quantity": 1 })).await;
+        
+        let checkout = test::TestRequest::post()
+            .uri("/api/v1/orders")
+            .cookie(guest)
+            .set_json(serde_json::json!({
+                "customer_name": "Ann",
+                "customer_email": "ann@example.com",
+                "shipping_address": { "name": "Ann", "line1": "", "city": "Testville", "country": "XX" },
+                "billing_address": { "name": "Ann", "line1": "1 Test Street", "city": "", "country": "US" },
+            }))
+            .to_request();
+        let response = test::call_service(&app, checkout).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let problem: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(problem["code"], "validation_failed");
+        assert_eq!(problem["fields"], serde_json::json!({
+            "billing_address.city": ["is required"],
+            "shipping_address.country": ["must be an ISO 3166-1 alpha-2 code"],
+            "shipping_address.line1": ["is required"],
+        }));
+        
+        let quantity = test::TestRequest::post()
+            .uri("/api/v1/cart")
+            .set_json(serde_json::json!({ "product_id": product_id, "quantity": MAX_LINE_QUANTITY + 1 }))
+            .to_request();
+        let problem: serde_json::Value = test::call_and_read_body_json(&app, quantity).await;
+        assert_eq!(problem["fields"]["quantity"], serde_json::json!([format!("must be between 1 and {}", MAX_LINE_QUANTITY)]));
+    }
+    
+    #[tokio::test]
+    async fn order_is_taxed_at_the_shipping_destination() {
+        let db = TestDb::new().await;
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        sqlx::query(
+            r#"
+            INSERT INTO tax_rates (tax_class_id, country, region, name, rate) VALUES
+                (1, 'US', NULL, 'Federal', '5'),
+                (1, 'US', 'NY', 'New York', '8.875')
+            "#
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 2);
+        let mut order = order_data();
+        if let Some(address) = order.shipping_address.as_mut() {
+            address.country = "us".to_string();
+            address.region = Some("ny".to_string());
+        }
+        
+        let (order_id, total) = place_order(&db.pool, &cart, &order, None, PricingMode::Exclusive)
+            .await
+            .unwrap();
+        assert_eq!(total, Decimal::new(2178, 2));
+        
+        let amounts: (i64, i64, i64) = sqlx::query_as(
+            "SELECT subtotal_cents, tax_cents, total_cents FROM orders WHERE id = ?1"
+        )
+        .bind(order_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(amounts, (2000, 178, 2178));
+        
+        // With tax-inclusive prices the shopper pays the shelf price
+        let (_, total) = place_order(&db.pool, &cart, &order, None, PricingMode::Inclusive)
+            .await
+            .unwrap();
+        assert_eq!(total, Decimal::new(2000, 2));
+    }
+    
+    #[tokio::test]
+    async fn order_is_paid_only_once_payment_is_captured() {
+        use crate::handlers::payments::collect_payment;
+        use crate::models::Payment;
+        use crate::payments::{FakeProvider, FAKE_DECLINED_CARD};
+        
+        let db = TestDb::new().await;
+        let provider = FakeProvider::new("secret".to_string());
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 1);
+        let (order_id, total) = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive)
+            .await
+            .unwrap();
+        open_payment(&db.pool, &provider, order_id, total).await.unwrap();
+        
+        let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = ?1")
+            .bind(order_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let declined = collect_payment(&db.pool, &provider, &order, FAKE_DECLINED_CARD, None).await;
+        assert!(matches!(declined, Err(AppError::PaymentFailed(ref m)) if m == "Card declined"));
+        
+        let status: OrderStatus = sqlx::query_scalar("SELECT status FROM orders WHERE id = ?1")
+            .bind(order_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(status, OrderStatus::Pending);
+        
+        // A retry after a decline opens a new attempt
+        let captured = collect_payment(&db.pool, &provider, &order, "fake_card_ok", None).await.unwrap();
+        assert_eq!(captured.status, PaymentStatus::Captured);
+        assert_eq!(captured.amount, total);
+        
+        let payments = sqlx::query_as::<_, Payment>("SELECT * FROM payments WHERE order_id = ?1 ORDER BY id")
+            .bind(order_id)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        let statuses: Vec<_> = payments.iter().map(|p| p.status).collect();
+        assert_eq!(statuses, [PaymentStatus::Failed, PaymentStatus::Captured]);
+        
+        let status: OrderStatus = sqlx::query_scalar("SELECT status FROM orders WHERE id = ?1")
+            .bind(order_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(status, OrderStatus::Paid);
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_status_changes_apply_only_once() {
+        let db = TestDb::new().await;
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 1);
+        let (order_id, _) = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive)
+            .await
+            .unwrap();
+        
+        // Every task tries to mark it paid; paid -> paid isn't a move, so
+        // whoever reads a stale "pending" must lose on the guarded update
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let pool = db.pool.clone();
+                tokio::spawn(async move {
+                    let mut conn = pool.acquire().await.unwrap();
+                    transition_order_status(&mut conn, order_id, OrderStatus::Paid, None, None).await
+                })
+            })
+            .collect();
+        
+        let mut winners = Vec::new();
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok(order) => winners.push(order.status),
+                Err(AppError::Conflict(_)) => {},
+                Err(e) => panic!("unexpected transition error: {e}"),
+            }
+        }
+        assert_eq!(winners.len(), 1);
+        
+        let moves: Vec<(Option<OrderStatus>, OrderStatus)> = sqlx::query_as(
+            "SELECT from_status, to_status FROM order_status_history WHERE order_id = ?1 AND from_status IS NOT NULL"
+        )
+        .bind(order_id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(moves, [(Some(OrderStatus::Pending), OrderStatus::Paid)]);
+    }
+    
+    #[tokio::test]
+    async fn cancelling_returns_stock_and_coupon_uses() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let product_id = insert_product(&db.pool, "Widget", 5).await;
+        sqlx::query("INSERT INTO coupons (code, kind, percent_off, max_uses) VALUES ('SAVE10', 'percentage', 10, 1)")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        
+        let ann = register(&app, "Ann", "ann@example.com").await;
+        let ann_id: i64 = sqlx::query_scalar("SELECT id FROM users WHERE email = 'ann@example.com'")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let admin = admin_cookie(&app, &db).await;
+        
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 2);
+        cart.coupon_code = Some("SAVE10".to_string());
+        let stock_and_uses = || async {
+            sqlx::query_as::<_, (i32, i64)>(
+                "SELECT stock_quantity, (SELECT COUNT(*) FROM coupon_redemptions) FROM products WHERE id = ?1"
             )
+            .bind(product_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap()
+        };
+        
+        // The customer cancels their own pending order
+        let (order_id, _) = place_order(&db.pool, &cart, &order_data(), Some(ann_id), PricingMode::Exclusive)
+            .await
+            .unwrap();
+        assert_eq!(stock_and_uses().await, (3, 1));
+        let cancel = test::TestRequest::post()
+            .uri(&format!("/api/v1/orders/{}/cancel", order_id))
+            .cookie(ann)
+            .to_request();
+        assert_eq!(test::call_service(&app, cancel).await.status(), StatusCode::OK);
+        assert_eq!(stock_and_uses().await, (5, 0));
+        
+        // An admin cancels a paid order through the status endpoint
+        let (order_id, _) = place_order(&db.pool, &cart, &order_data(), Some(ann_id), PricingMode::Exclusive)
+            .await
+            .unwrap();
+        let mut conn = db.pool.acquire().await.unwrap();
+        transition_order_status(&mut conn, order_id, OrderStatus::Paid, None, None).await.unwrap();
+        assert_eq!(stock_and_uses().await, (3, 1));
+        let cancel = test::TestRequest::patch()
+            .uri(&format!("/api/v1/orders/{}/status", order_id))
+            .cookie(admin)
+            .set_json(serde_json::json!({ "status": "cancelled" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, cancel).await.status(), StatusCode::OK);
+        assert_eq!(stock_and_uses().await, (5, 0));
+    }
+    
+    #[tokio::test]
+    async fn guest_orders_are_paid_only_with_their_token_and_through_a_payment() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let product_id = insert_product(&db.pool, "Widget", 5).await;
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 1);
+        let (order_id, _) = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive)
+            .await
+            .unwrap();
+        let token: String = sqlx::query_scalar("SELECT access_token FROM orders WHERE id = ?1")
             .bind(order_id)
-            .fetch_all(&state.db)
-            .await?;
-            
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "order": o,
-                "items": items
-            })))
-        },
-        _ => Err(AppError::NotFound),
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        
+        // Admins can't mark an order paid by hand
+        let admin = admin_cookie(&app, &db).await;
+        let mark_paid = test::TestRequest::patch()
+            .uri(&format!("/api/v1/orders/{}/status", order_id))
+            .cookie(admin)
+            .set_json(serde_json::json!({ "status": "paid" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, mark_paid).await.status(), StatusCode::CONFLICT);
+        
+        let pay = |query: String| test::TestRequest::post()
+            .uri(&format!("/api/v1/orders/{}/pay{}", order_id, query))
+            .set_json(serde_json::json!({ "payment_method": "fake_card" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, pay(String::new())).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(test::call_service(&app, pay("?token=wrong".to_string())).await.status(), StatusCode::NOT_FOUND);
+        let payments: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payments WHERE status = 'captured'")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(payments, 0);
+        
+        assert_eq!(test::call_service(&app, pay(format!("?token={}", token))).await.status(), StatusCode::OK);
+        let status: OrderStatus = sqlx::query_scalar("SELECT status FROM orders WHERE id = ?1")
+            .bind(order_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(status, OrderStatus::Paid);
+    }
+    
+    #[tokio::test]
+    async fn cancelling_a_paid_order_refunds_its_payment() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let product_id = insert_product(&db.pool, "Widget", 5).await;
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 1);
+        let (order_id, _) = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive)
+            .await
+            .unwrap();
+        let token: String = sqlx::query_scalar("SELECT access_token FROM orders WHERE id = ?1")
+            .bind(order_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        
+        let pay = test::TestRequest::post()
+            .uri(&format!("/api/v1/orders/{}/pay?token={}", order_id, token))
+            .set_json(serde_json::json!({ "payment_method": "fake_card" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, pay).await.status(), StatusCode::OK);
+        
+        let admin = admin_cookie(&app, &db).await;
+        let cancel = test::TestRequest::patch()
+            .uri(&format!("/api/v1/orders/{}/status", order_id))
+            .cookie(admin)
+            .set_json(serde_json::json!({ "status": "cancelled" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, cancel).await.status(), StatusCode::OK);
+        
+        let statuses: (String, String, i64) = sqlx::query_as(
+            r#"
+            SELECT o.status, p.status, p.refunded_cents
+            FROM orders o JOIN payments p ON p.order_id = o.id
+            WHERE o.id = ?1
+            "#
+        )
+        .bind(order_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(statuses, ("cancelled".to_string(), "refunded".to_string(), 1000));
+    }
+    
+    // The fake provider, with refunds that fail while `refunds_fail` is set
+    #[derive(Clone)]
+    struct FlakyRefunds {
+        refunds_fail: Arc<AtomicBool>,
+        refunds: Arc<AtomicUsize>,
+    }
+    
+    impl PaymentProvider for FlakyRefunds {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+        
+        fn create_intent(&self, order_id: i64, amount: Decimal) -> BoxFuture<'_, Result<PaymentIntent>> {
+            Box::pin(async move { FakeProvider::new(String::new()).create_intent(order_id, amount).await })
+        }
+        
+        fn capture<'a>(&'a self, _: &'a str, _: &'a str, _: Decimal) -> BoxFuture<'a, Result<CaptureOutcome>> {
+            Box::pin(async move { Ok(CaptureOutcome::Captured) })
+        }
+        
+        fn refund<'a>(&'a self, _: &'a str, _: Decimal) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                if self.refunds_fail.load(Ordering::SeqCst) {
+                    return Err(AppError::PaymentFailed("Provider unavailable".to_string()));
+                }
+                self.refunds.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+        
+        fn verify_webhook(&self, _: Option<&str>, _: &[u8]) -> Result<WebhookEvent> {
+            Err(AppError::Unauthorized)
+        }
+    }
+    
+    #[actix_web::test]
+    async fn refunds_follow_the_committed_cancel_and_are_retried() {
+        let db = TestDb::new().await;
+        let provider = FlakyRefunds { refunds_fail: Arc::new(AtomicBool::new(true)), refunds: Arc::default() };
+        let app = test_app_with_payments(&db, Box::new(provider.clone())).await;
+        let admin = admin_cookie(&app, &db).await;
+        let product_id = insert_product(&db.pool, "Widget", 5).await;
+        
+        let mut orders = Vec::new();
+        for _ in 0..2 {
+            let mut cart = Cart::new();
+            cart.add_item(product_id, None, 1);
+            let (order_id, _) = place_order(&db.pool, &cart, &order_data(), None, PricingMode::Exclusive)
+                .await
+                .unwrap();
+            let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = ?1")
+                .bind(order_id)
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+            collect_payment(&db.pool, &provider, &order, "fake_card", None).await.unwrap();
+            orders.push(order_id);
+        }
+        let change_status = |order_id: i64, status: &str| test::TestRequest::patch()
+            .uri(&format!("/api/v1/orders/{}/status", order_id))
+            .cookie(admin.clone())
+            .set_json(serde_json::json!({ "status": status }))
+            .to_request();
+        let state = |order_id: i64| {
+            let pool = db.pool.clone();
+            async move {
+                sqlx::query_as::<_, (OrderStatus, PaymentStatus)>(
+                    "SELECT o.status, p.status FROM orders o JOIN payments p ON p.order_id = o.id WHERE o.id = ?1"
+                )
+                .bind(order_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+        
+        // The first order ships before the cancel lands: nothing is refunded
+        for status in ["processing", "shipped"] {
+            assert_eq!(test::call_service(&app, change_status(orders[0], status)).await.status(), StatusCode::OK);
+        }
+        provider.refunds_fail.store(false, Ordering::SeqCst);
+        assert_eq!(test::call_service(&app, change_status(orders[0], "cancelled")).await.status(), StatusCode::CONFLICT);
+        assert_eq!(state(orders[0]).await, (OrderStatus::Shipped, PaymentStatus::Captured));
+        assert_eq!(provider.refunds.load(Ordering::SeqCst), 0);
+        
+        // The second is cancelled while the provider is down: the cancel and
+        // restock stand, and the refund waits for the next attempt
+        provider.refunds_fail.store(true, Ordering::SeqCst);
+        assert_eq!(test::call_service(&app, change_status(orders[1], "cancelled")).await.status(), StatusCode::OK);
+        assert_eq!(state(orders[1]).await, (OrderStatus::Cancelled, PaymentStatus::RefundPending));
+        let stock: i32 = sqlx::query_scalar("SELECT stock_quantity FROM products WHERE id = ?1")
+            .bind(product_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stock, 4);
+        
+        provider.refunds_fail.store(false, Ordering::SeqCst);
+        assert_eq!(finish_refunds(&db.pool, &provider, None).await.unwrap(), 1);
+        assert_eq!(state(orders[1]).await, (OrderStatus::Cancelled, PaymentStatus::Refunded));
+        assert_eq!(finish_refunds(&db.pool, &provider, None).await.unwrap(), 0);
+        assert_eq!(provider.refunds.load(Ordering::SeqCst), 1);
+    }
+    
+    #[tokio::test]
+    async fn customers_see_only_their_own_order_history() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        
+        let mut customers = Vec::new();
+        for email in ["ann@example.com", "bob@example.com"] {
+            let register = test::TestRequest::post()
+                .uri("/api/v1/auth/register")
+                .set_json(serde_json::json!({ "name": "Shopper", "email": email, "password": "long enough" }))
+                .to_request();
+            let response = test::call_service(&app, register).await;
+            let cookie = session_cookie(&response);
+            let user: serde_json::Value = test::read_body_json(response).await;
+            customers.push((cookie, user["id"].as_i64().unwrap()));
+        }
+        let mut cart = Cart::new();
+        cart.add_item(product_id, None, 1);
+        let mut anns_orders = Vec::new();
+        for customer_id in [Some(customers[0].1), Some(customers[0].1), Some(customers[1].1), None] {
+            let (order_id, _) = place_order(&db.pool, &cart, &order_data(), customer_id, PricingMode::Exclusive)
+                .await
+                .unwrap();
+            if customer_id == Some(customers[0].1) {
+                anns_orders.push(order_id);
+            }
+        }
+        
+        let history = |cookie: Cookie<'static>| test::TestRequest::get()
+            .uri("/api/v1/me/orders")
+            .cookie(cookie)
+            .to_request();
+        let orders: Vec<Order> = test::call_and_read_body_json(&app, history(customers[0].0.clone())).await;
+        let mut ids: Vec<_> = orders.iter().map(|o| o.id).collect();
+        ids.sort();
+        assert_eq!(ids, anns_orders);
+        let orders: Vec<Order> = test::call_and_read_body_json(&app, history(customers[1].0.clone())).await;
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].customer_id, Some(customers[1].1));
+        
+        let signed_out = test::TestRequest::get().uri("/api/v1/me/orders").to_request();
+        assert_eq!(test::call_service(&app, signed_out).await.status(), StatusCode::UNAUTHORIZED);
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn orders_are_only_shown_to_their_owner_or_an_admin() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let product_id = insert_product(&db.pool, "Widget", 10).await;
+        
+        let app = &app;
+        let ann = register(app, "Ann", "ann@example.com").await;
+        let bob = register(app, "Bob", "bob@example.com").await;
+        
+        // Ann checks out; a guest checks out in a cookie session of their own
+        let checkout = |cookie: Option<Cookie<'static>>| async move {
+            // Guests get their session with the first cart item
+            let cookie = add_to_cart(app, cookie, serde_json::json!({ "product_id": product_id, "quantity": 1 })).await;
+            let order = test::TestRequest::post()
+                .uri("/api/v1/orders")
+                .cookie(cookie)
+                .set_json(serde_json::json!({
+                    "customer_name": "Shopper",
+                    "customer_email": "shopper@example.com",
+                    "shipping_address": order_data().shipping_address,
+                }))
+                .to_request();
+            let created: serde_json::Value = test::call_and_read_body_json(app, order).await;
+            (created["order_id"].as_i64().unwrap(), created["access_token"].as_str().map(str::to_string))
+        };
+        let (anns_order, token) = checkout(Some(ann.clone())).await;
+        assert_eq!(token, None);
+        let (guest_order, guest_token) = checkout(None).await;
+        let guest_token = guest_token.unwrap();
+        
+        let status = |uri: String, cookie: Option<Cookie<'static>>| async move {
+            let mut request = test::TestRequest::get().uri(&uri);
+            if let Some(cookie) = cookie {
+                request = request.cookie(cookie);
+            }
+            test::call_service(app, request.to_request()).await.status()
+        };
+        assert_eq!(status(format!("/api/v1/orders/{}", anns_order), Some(ann)).await, StatusCode::OK);
+        assert_eq!(status(format!("/api/v1/orders/{}", anns_order), Some(bob.clone())).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(format!("/api/v1/orders/{}", anns_order), None).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(format!("/api/v1/orders/{}", guest_order), Some(bob)).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(format!("/api/v1/orders/{}?token=wrong", guest_order), None).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(format!("/api/v1/orders/{}?token={}", guest_order, guest_token), None).await, StatusCode::OK);
+        
+        let admin = admin_cookie(app, &db).await;
+        assert_eq!(status(format!("/api/v1/orders/{}", anns_order), Some(admin.clone())).await, StatusCode::OK);
+        assert_eq!(status(format!("/api/v1/orders/{}", guest_order), Some(admin)).await, StatusCode::OK);
+    }
+}
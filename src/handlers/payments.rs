@@ -0,0 +1,353 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use rust_decimal::Decimal;
+use sqlx::{SqliteConnection, SqlitePool};
+use crate::{
+    models::{Cents, Order, OrderStatus, Payment, PaymentStatus},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::{AdminUser, AuthenticatedUser},
+    handlers::orders::{find_visible_order, transition_order_status, OrderAccessQuery},
+    payments::{CaptureOutcome, PaymentProvider, WebhookEvent},
+};
+
+// Header carrying the provider's signature on webhook calls
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Payment-Signature";
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct PayOrder {
+    // Token for the card or wallet, as handed out by the provider's browser SDK
+    pub payment_method: String,
+}
+
+// Pay for a pending order. Orders placed by a signed-in customer can only be
+// paid by that customer (or an admin); guest orders by whoever holds their
+// access token. Anyone else gets a 404, as for `GET /orders/{id}`.
+#[utoipa::path(
+    post,
+    path = "/orders/{id}/pay",
+    tag = "payments",
+    params(("id" = i64, Path, description = "Order id"), OrderAccessQuery),
+    request_body = PayOrder,
+    responses(
+        (status = 200, description = "Payment captured; the order is paid", body = Payment),
+        (status = 402, description = "Payment declined", body = Problem),
+        (status = 404, description = "No such order, or not yours", body = Problem),
+        (status = 409, description = "Order is not awaiting payment", body = Problem),
+    ),
+)]
+pub async fn pay_order(
+    user: Option<AuthenticatedUser>,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    query: web::Query<OrderAccessQuery>,
+    payment: web::Json<PayOrder>,
+) -> Result<HttpResponse> {
+    let user = user.map(|u| u.0);
+    let order = find_visible_order(&state.db, path.into_inner(), user.as_ref(), query.token.as_deref()).await?;
+    
+    let payment = collect_payment(
+        &state.db,
+        state.payments.as_ref(),
+        &order,
+        &payment.payment_method,
+        user.map(|u| u.id),
+    ).await?;
+    
+    Ok(HttpResponse::Ok().json(payment))
+}
+
+// Payment attempts for an order (admin)
+#[utoipa::path(
+    get,
+    path = "/orders/{id}/payments",
+    tag = "payments",
+    params(("id" = i64, Path, description = "Order id")),
+    responses(
+        (status = 200, description = "Payment attempts, oldest first", body = Vec<Payment>),
+        (status = 404, description = "No such order", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_order_payments(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let order_id = path.into_inner();
+    find_order(&state.db, order_id).await?;
+    
+    let payments = sqlx::query_as::<_, Payment>(
+        "SELECT * FROM payments WHERE order_id = ?1 ORDER BY id"
+    )
+    .bind(order_id)
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(payments))
+}
+
+// Status updates pushed by the provider. Providers retry deliveries, so an
+// event for a payment that has already moved on is acknowledged and ignored.
+#[utoipa::path(
+    post,
+    path = "/payments/webhook",
+    tag = "payments",
+    params(("X-Payment-Signature" = String, Header, description = "Signature from the payment provider")),
+    request_body = WebhookEvent,
+    responses(
+        (status = 200, description = "Event applied or already seen"),
+        (status = 401, description = "Bad signature", body = Problem),
+        (status = 404, description = "Unknown payment", body = Problem),
+    ),
+)]
+pub async fn payment_webhook(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    body: web::Bytes,
+) -> Result<HttpResponse> {
+    let signature = req.headers()
+        .get(WEBHOOK_SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let provider = state.payments.as_ref();
+    let event = provider.verify_webhook(signature, &body)?;
+    
+    let reference = match &event {
+        WebhookEvent::PaymentSucceeded { reference }
+        | WebhookEvent::PaymentFailed { reference, .. }
+        | WebhookEvent::Refunded { reference } => reference,
+    };
+    
+    let mut tx = state.db.begin().await?;
+    let payment = sqlx::query_as::<_, Payment>(
+        "SELECT * FROM payments WHERE provider = ?1 AND provider_reference = ?2"
+    )
+    .bind(provider.name())
+    .bind(reference)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    
+    match event {
+        WebhookEvent::PaymentSucceeded { .. } if payment.status == PaymentStatus::Pending => {
+            mark_captured(&mut tx, &payment, None).await?;
+        },
+        WebhookEvent::PaymentFailed { reason, .. } if payment.status == PaymentStatus::Pending => {
+            mark_failed(&mut tx, payment.id, &reason).await?;
+        },
+        WebhookEvent::Refunded { .. } if matches!(payment.status, PaymentStatus::Captured | PaymentStatus::RefundPending) => {
+            mark_refunded(&mut tx, &payment).await?;
+            let status: OrderStatus = sqlx::query_scalar("SELECT status FROM orders WHERE id = ?1")
+                .bind(payment.order_id)
+                .fetch_one(&mut *tx)
+                .await?;
+            if status.can_transition_to(OrderStatus::Refunded) {
+                transition_order_status(
+                    &mut tx,
+                    payment.order_id,
+                    OrderStatus::Refunded,
+                    None,
+                    Some("Refunded at the payment provider"),
+                ).await?;
+            }
+        },
+        _ => {},
+    }
+    tx.commit().await?;
+    
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Open a payment for an order at the provider and record it as pending
+pub async fn open_payment(
+    db: &SqlitePool,
+    provider: &dyn PaymentProvider,
+    order_id: i64,
+    amount: Decimal,
+) -> Result<Payment> {
+    let intent = provider.create_intent(order_id, amount).await?;
+    
+    let payment = sqlx::query_as::<_, Payment>(
+        r#"
+        INSERT INTO payments (order_id, provider, provider_reference, client_secret, amount_cents)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        RETURNING *
+        "#
+    )
+    .bind(order_id)
+    .bind(provider.name())
+    .bind(&intent.reference)
+    .bind(&intent.client_secret)
+    .bind(Cents::try_from(amount)?)
+    .fetch_one(db)
+    .await?;
+    
+    Ok(payment)
+}
+
+// Capture the order total and mark the order paid. The latest pending
+// attempt is reused; after a decline a fresh intent is opened, so every
+// attempt keeps its own row and outcome.
+pub async fn collect_payment(
+    db: &SqlitePool,
+    provider: &dyn PaymentProvider,
+    order: &Order,
+    payment_method: &str,
+    paid_by: Option<i64>,
+) -> Result<Payment> {
+    if order.status != OrderStatus::Pending {
+        return Err(AppError::Conflict("Order is not awaiting payment".to_string()));
+    }
+    
+    let pending = sqlx::query_as::<_, Payment>(
+        r#"
+        SELECT * FROM payments
+        WHERE order_id = ?1 AND provider = ?2 AND status = 'pending'
+        ORDER BY id DESC
+        LIMIT 1
+        "#
+    )
+    .bind(order.id)
+    .bind(provider.name())
+    .fetch_optional(db)
+    .await?;
+    let payment = match pending {
+        Some(payment) => payment,
+        None => open_payment(db, provider, order.id, order.total_amount).await?,
+    };
+    
+    let outcome = provider.capture(&payment.provider_reference, payment_method, payment.amount).await?;
+    
+    let mut tx = db.begin().await?;
+    match outcome {
+        CaptureOutcome::Captured => {
+            let payment = mark_captured(&mut tx, &payment, paid_by).await?;
+            tx.commit().await?;
+            Ok(payment)
+        },
+        CaptureOutcome::Declined(reason) => {
+            mark_failed(&mut tx, payment.id, &reason).await?;
+            tx.commit().await?;
+            Err(AppError::PaymentFailed(reason))
+        },
+    }
+}
+
+// Mark an order's captured payments for refund. Called in the transaction
+// that cancels or refunds the order; the provider is only asked by
+// `finish_refunds` once that has committed, so no write lock is held while
+// it answers and a refund is never made for a change that didn't happen.
+pub async fn request_refunds(conn: &mut SqliteConnection, provider: &dyn PaymentProvider, order_id: i64) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE payments
+        SET status = 'refund_pending', updated_at = datetime('now')
+        WHERE order_id = ?1 AND provider = ?2 AND status = 'captured'
+        "#
+    )
+    .bind(order_id)
+    .bind(provider.name())
+    .execute(conn)
+    .await?;
+    
+    Ok(())
+}
+
+// Refund payments marked by `request_refunds`, for one order or for every
+// order, and return how many went through. Each is recorded as the provider
+// accepts it; the first one rejected stops the run and it stays pending for
+// the next. A retry can repeat a refund whose recording failed, so providers
+// must treat a second refund of the same reference as a no-op.
+pub async fn finish_refunds(
+    db: &SqlitePool,
+    provider: &dyn PaymentProvider,
+    order_id: Option<i64>,
+) -> Result<usize> {
+    let pending = sqlx::query_as::<_, Payment>(
+        r#"
+        SELECT * FROM payments
+        WHERE status = 'refund_pending' AND provider = ?1 AND (?2 IS NULL OR order_id = ?2)
+        ORDER BY id
+        "#
+    )
+    .bind(provider.name())
+    .bind(order_id)
+    .fetch_all(db)
+    .await?;
+    
+    for payment in &pending {
+        provider.refund(&payment.provider_reference, payment.amount).await?;
+        mark_refunded(&mut *db.acquire().await?, payment).await?;
+    }
+    
+    Ok(pending.len())
+}
+
+// Record a successful capture and move a pending order to paid
+async fn mark_captured(conn: &mut SqliteConnection, payment: &Payment, paid_by: Option<i64>) -> Result<Payment> {
+    let captured = sqlx::query_as::<_, Payment>(
+        r#"
+        UPDATE payments
+        SET status = 'captured', failure_reason = NULL, updated_at = datetime('now')
+        WHERE id = ?1 AND status = 'pending'
+        RETURNING *
+        "#
+    )
+    .bind(payment.id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| AppError::Conflict("Payment status changed concurrently".to_string()))?;
+    
+    let status: OrderStatus = sqlx::query_scalar("SELECT status FROM orders WHERE id = ?1")
+        .bind(payment.order_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    if status == OrderStatus::Pending {
+        transition_order_status(conn, payment.order_id, OrderStatus::Paid, paid_by, Some("Payment captured")).await?;
+    } else {
+        // Money arrived for an order that was cancelled meanwhile; leave it for an admin to refund
+        log::warn!("Payment {} captured for order {} in status {}", payment.id, payment.order_id, status);
+    }
+    
+    Ok(captured)
+}
+
+async fn mark_failed(conn: &mut SqliteConnection, payment_id: i64, reason: &str) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE payments
+        SET status = 'failed', failure_reason = ?1, updated_at = datetime('now')
+        WHERE id = ?2 AND status = 'pending'
+        "#
+    )
+    .bind(reason)
+    .bind(payment_id)
+    .execute(conn)
+    .await?;
+    
+    Ok(())
+}
+
+async fn mark_refunded(conn: &mut SqliteConnection, payment: &Payment) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE payments
+        SET status = 'refunded', refunded_cents = amount_cents, updated_at = datetime('now')
+        WHERE id = ?1 AND status IN ('captured', 'refund_pending')
+        "#
+    )
+    .bind(payment.id)
+    .execute(conn)
+    .await?;
+    
+    Ok(())
+}
+
+async fn find_order(db: &SqlitePool, order_id: i64) -> Result<Order> {
+    sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = ?1")
+        .bind(order_id)
+        .fetch_optional(db)
+        .await?
+        .ok_or(AppError::NotFound)
+}
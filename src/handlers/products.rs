@@ -1,21 +1,227 @@
-use actix_web::{web, HttpResponse};
-use crate::{models::{Product, CreateProduct}, errors::Result, AppState};
+use actix_web::{web, HttpRequest, HttpResponse};
+use rust_decimal::Decimal;
+use sqlx::{QueryBuilder, Sqlite};
+use validator::Validate;
+use crate::{
+    models::{Cents, Page, Product, ProductStatus, CreateProduct},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AdminUser,
+    handlers::{
+        from_query_str, images::{load_images, remove_image_files}, page_offset, page_params, paginate, PageQuery,
+    },
+};
 
-// Get all products
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductSort {
+    #[default]
+    Newest,
+    Price,
+    Name,
+    Stock,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// Query parameters for the product listings. The filters are documented
+// as `ProductFilterQuery` beside it.
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProductListQuery {
+    #[serde(default)]
+    pub sort: ProductSort,
+    pub order: Option<SortOrder>,
+    #[serde(flatten)]
+    #[param(ignore)]
+    pub filter: ProductFilterQuery,
+    // Admins only; defaults to published
+    pub status: Option<ProductStatus>,
+}
+
+// Filters shared by the product listings, search and the tag facets.
+// Flattened into the other queries, where serde hands every value over as
+// text, so numbers and booleans are parsed with `from_query_str`.
+#[derive(Debug, Default, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProductFilterQuery {
+    #[serde(default, deserialize_with = "from_query_str")]
+    pub min_price: Option<Decimal>,
+    #[serde(default, deserialize_with = "from_query_str")]
+    pub max_price: Option<Decimal>,
+    #[serde(default, deserialize_with = "from_query_str")]
+    pub category_id: Option<i32>,
+    // Tag slug
+    pub tag: Option<String>,
+    #[serde(default, deserialize_with = "from_query_str")]
+    pub in_stock: Option<bool>,
+}
+
+// Filters shared by the product listing and search
+#[derive(Debug, Clone, Default)]
+pub struct ProductFilter {
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub category_id: Option<i32>,
+    pub tag: Option<String>,
+    pub in_stock: Option<bool>,
+    pub status: ProductStatus,
+}
+
+// Published products only; listings that take a status set it themselves
+impl From<&ProductFilterQuery> for ProductFilter {
+    fn from(query: &ProductFilterQuery) -> Self {
+        ProductFilter {
+            min_price: query.min_price,
+            max_price: query.max_price,
+            category_id: query.category_id,
+            tag: query.tag.clone(),
+            in_stock: query.in_stock,
+            status: ProductStatus::default(),
+        }
+    }
+}
+
+// Append the WHERE clause shared by product listings. Columns are qualified
+// with `products.`, so the query may join other tables (search joins the
+// full-text index) as long as `products` itself isn't aliased.
+pub fn push_product_filters(qb: &mut QueryBuilder<'_, Sqlite>, filter: ProductFilter) -> Result<()> {
+    qb.push(" WHERE products.status = ").push_bind(filter.status);
+    if let Some(category_id) = filter.category_id {
+        qb.push(" AND products.id IN (SELECT product_id FROM product_categories WHERE category_id = ")
+            .push_bind(category_id)
+            .push(")");
+    }
+    if let Some(tag) = filter.tag {
+        qb.push(" AND products.id IN (SELECT pt.product_id FROM product_tags pt JOIN tags t ON t.id = pt.tag_id WHERE t.slug = ")
+            .push_bind(tag)
+            .push(")");
+    }
+    if let Some(min_price) = filter.min_price {
+        qb.push(" AND products.price_cents >= ").push_bind(Cents::try_from(min_price)?);
+    }
+    if let Some(max_price) = filter.max_price {
+        qb.push(" AND products.price_cents <= ").push_bind(Cents::try_from(max_price)?);
+    }
+    match filter.in_stock {
+        Some(true) => { qb.push(" AND products.stock_quantity > 0"); }
+        Some(false) => { qb.push(" AND products.stock_quantity = 0"); }
+        None => {}
+    }
+    Ok(())
+}
+
+// Drafts and archived products can only be listed by admins
+fn check_status_access(status: Option<ProductStatus>, admin: Option<&AdminUser>) -> Result<()> {
+    match status {
+        Some(ProductStatus::Draft | ProductStatus::Archived) if admin.is_none() => Err(AppError::Forbidden),
+        _ => Ok(()),
+    }
+}
+
+fn order_by_clause(sort: ProductSort, order: Option<SortOrder>) -> &'static str {
+    use SortOrder::*;
+    match (sort, order) {
+        (ProductSort::Newest, Some(Asc)) => " ORDER BY created_at ASC, id ASC",
+        (ProductSort::Newest, _) => " ORDER BY created_at DESC, id DESC",
+        (ProductSort::Price, Some(Desc)) => " ORDER BY price_cents DESC, id",
+        (ProductSort::Price, _) => " ORDER BY price_cents ASC, id",
+        (ProductSort::Name, Some(Desc)) => " ORDER BY name DESC, id",
+        (ProductSort::Name, _) => " ORDER BY name ASC, id",
+        (ProductSort::Stock, Some(Asc)) => " ORDER BY stock_quantity ASC, id",
+        (ProductSort::Stock, _) => " ORDER BY stock_quantity DESC, id",
+    }
+}
+
+// Get products, filtered and sorted
+#[utoipa::path(
+    get,
+    path = "/products",
+    tag = "products",
+    params(ProductListQuery, ProductFilterQuery),
+    responses(
+        (status = 200, description = "All matching products", body = Vec<Product>),
+        (status = 403, description = "Only admins can list unpublished products", body = Problem),
+    ),
+)]
 pub async fn get_products(
+    admin: Option<AdminUser>,
     state: web::Data<AppState>,
+    query: web::Query<ProductListQuery>,
 ) -> Result<HttpResponse> {
-    let products = sqlx::query_as::<_, Product>(
-        "SELECT * FROM products ORDER BY created_at DESC"
-    )
-    .fetch_all(&state.db)
-    .await?;
+    let query = query.into_inner();
+    check_status_access(query.status, admin.as_ref())?;
+    let filter = ProductFilter { status: query.status.unwrap_or_default(), ..ProductFilter::from(&query.filter) };
+    
+    let mut select = QueryBuilder::<Sqlite>::new("SELECT * FROM products");
+    push_product_filters(&mut select, filter)?;
+    select.push(order_by_clause(query.sort, query.order));
+    let products = select.build_query_as::<Product>()
+        .fetch_all(&state.db)
+        .await?;
     
     Ok(HttpResponse::Ok().json(products))
 }
 
-// Get single product
+// Get products a page at a time, filtered and sorted (v2)
+#[utoipa::path(
+    get,
+    path = "/products",
+    tag = "products",
+    params(PageQuery, ProductListQuery, ProductFilterQuery),
+    responses(
+        (status = 200, description = "A page of products", body = Page<Product>),
+        (status = 403, description = "Only admins can list unpublished products", body = Problem),
+    ),
+)]
+pub async fn get_products_page(
+    req: HttpRequest,
+    admin: Option<AdminUser>,
+    state: web::Data<AppState>,
+    paging: web::Query<PageQuery>,
+    query: web::Query<ProductListQuery>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    check_status_access(query.status, admin.as_ref())?;
+    let filter = ProductFilter { status: query.status.unwrap_or_default(), ..ProductFilter::from(&query.filter) };
+    let (page, per_page) = page_params(paging.page, paging.per_page);
+    
+    let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM products");
+    push_product_filters(&mut count, filter.clone())?;
+    let total: i64 = count.build_query_scalar()
+        .fetch_one(&state.db)
+        .await?;
+    
+    let mut select = QueryBuilder::<Sqlite>::new("SELECT * FROM products");
+    push_product_filters(&mut select, filter)?;
+    select.push(order_by_clause(query.sort, query.order));
+    select.push(" LIMIT ").push_bind(per_page);
+    select.push(" OFFSET ").push_bind(page_offset(page, per_page));
+    let products = select.build_query_as::<Product>()
+        .fetch_all(&state.db)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(paginate(&req, products, page, per_page, total)))
+}
+
+// Get single product. Drafts and archived products are only shown to admins.
+#[utoipa::path(
+    get,
+    path = "/products/{id}",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "The product", body = Product),
+        (status = 404, description = "No such product", body = Problem),
+    ),
+)]
 pub async fn get_product(
+    admin: Option<AdminUser>,
     state: web::Data<AppState>,
     path: web::Path<i32>,
 ) -> Result<HttpResponse> {
@@ -26,34 +232,52 @@ pub async fn get_product(
     )
     .bind(product_id)
     .fetch_optional(&state.db)
-    .await?;
+    .await?
+    .filter(|p| p.status == ProductStatus::Published || admin.is_some());
     
     match product {
         Some(p) => Ok(HttpResponse::Ok().json(p)),
-        _ => Err(crate::errors::AppError::NotFound),
+        _ => Err(AppError::NotFound),
     }
 }
 
 // Create product (admin)
+#[utoipa::path(
+    post,
+    path = "/products",
+    tag = "products",
+    request_body = CreateProduct,
+    responses(
+        (status = 201, description = "Product created", body = Product),
+        (status = 422, description = "Invalid product", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
 pub async fn create_product(
+    _admin: AdminUser,
     state: web::Data<AppState>,
     product: web::Json<CreateProduct>,
 ) -> Result<HttpResponse> {
     let product = product.into_inner();
+    product.validate()?;
     
     let result = sqlx::query_as::<_, Product>(
         r#"
-        INSERT INTO products (name, description, price, stock_quantity, category_id, image_url)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        INSERT INTO products (name, description, price_cents, stock_quantity, category_id, image_url, weight_grams, status, deleted_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, CASE WHEN ?8 = 'archived' THEN datetime('now') END)
         RETURNING *
         "#
     )
     .bind(&product.name)
     .bind(&product.description)
-    .bind(product.price)
+    .bind(Cents::try_from(product.price)?)
     .bind(product.stock_quantity)
     .bind(product.category_id)
     .bind(&product.image_url)
+    .bind(product.weight_grams)
+    .bind(product.status.unwrap_or_default())
     .fetch_one(&state.db)
     .await?;
     
@@ -61,81 +285,458 @@ pub async fn create_product(
 }
 
 // Update product (admin)
+#[utoipa::path(
+    put,
+    path = "/products/{id}",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    request_body = CreateProduct,
+    responses(
+        (status = 200, description = "Product updated", body = Product),
+        (status = 404, description = "No such product", body = Problem),
+        (status = 422, description = "Invalid product", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
 pub async fn update_product(
+    _admin: AdminUser,
     state: web::Data<AppState>,
     path: web::Path<i32>,
     product: web::Json<CreateProduct>,
 ) -> Result<HttpResponse> {
     let product_id = path.into_inner();
     let product = product.into_inner();
+    product.validate()?;
     
     let result = sqlx::query_as::<_, Product>(
         r#"
         UPDATE products 
-        SET name = ?1, description = ?2, price = ?3, 
-            stock_quantity = ?4, category_id = ?5, image_url = ?6,
+        SET name = ?1, description = ?2, price_cents = ?3, 
+            stock_quantity = ?4, category_id = ?5, image_url = ?6, weight_grams = ?7,
+            status = IFNULL(?9, status),
+            deleted_at = CASE
+                WHEN IFNULL(?9, status) <> 'archived' THEN NULL
+                ELSE IFNULL(deleted_at, datetime('now'))
+            END,
             updated_at = datetime('now')
-        WHERE id = ?7
+        WHERE id = ?8
         RETURNING *
         "#
     )
     .bind(&product.name)
     .bind(&product.description)
-    .bind(product.price)
+    .bind(Cents::try_from(product.price)?)
     .bind(product.stock_quantity)
     .bind(product.category_id)
     .bind(&product.image_url)
+    .bind(product.weight_grams)
     .bind(product_id)
+    .bind(product.status)
     .fetch_optional(&state.db)
     .await?;
     
     match result {
         Some(p) => Ok(HttpResponse::Ok().json(p)),
-        _ => Err(crate::errors::AppError::NotFound),
+        _ => Err(AppError::NotFound),
     }
 }
 
-// Delete product (admin)
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteProductQuery {
+    // Remove the product for good instead of archiving it; only allowed
+    // for products that were never ordered
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+// Archive product (admin). It disappears from the storefront but stays
+// available to the orders that refer to it, and can be restored.
+#[utoipa::path(
+    delete,
+    path = "/products/{id}",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id"), DeleteProductQuery),
+    responses(
+        (status = 204, description = "Product archived, or deleted if permanent"),
+        (status = 404, description = "No such product", body = Problem),
+        (status = 409, description = "Permanent delete of a product that has been ordered", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
 pub async fn delete_product(
+    _admin: AdminUser,
     state: web::Data<AppState>,
     path: web::Path<i32>,
+    query: web::Query<DeleteProductQuery>,
 ) -> Result<HttpResponse> {
     let product_id = path.into_inner();
     
+    if !query.permanent {
+        let result = sqlx::query(
+            r#"
+            UPDATE products
+            SET status = 'archived', deleted_at = IFNULL(deleted_at, datetime('now')), updated_at = datetime('now')
+            WHERE id = ?1
+            "#
+        )
+        .bind(product_id)
+        .execute(&state.db)
+        .await?;
+        
+        return if result.rows_affected() > 0 {
+            Ok(HttpResponse::NoContent().finish())
+        } else {
+            Err(AppError::NotFound)
+        };
+    }
+    
+    let ordered: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM order_items WHERE product_id = ?1)")
+        .bind(product_id)
+        .fetch_one(&state.db)
+        .await?;
+    if ordered {
+        return Err(AppError::Conflict(
+            "Product has been ordered; archive it instead".to_string()
+        ));
+    }
+    
+    // Image rows go with the product; their files are removed once it's gone
+    let images = load_images(&state.db, state.storage.as_ref(), product_id).await?;
+    
     let result = sqlx::query("DELETE FROM products WHERE id = ?1")
         .bind(product_id)
         .execute(&state.db)
         .await?;
     
     if result.rows_affected() > 0 {
+        remove_image_files(state.storage.as_ref(), &images).await;
         Ok(HttpResponse::NoContent().finish())
     } else {
-        Err(crate::errors::AppError::NotFound)
+        Err(AppError::NotFound)
     }
 }
 
-// Search products
-pub async fn search_products(
+// Bring an archived product back (admin). It returns as published.
+#[utoipa::path(
+    post,
+    path = "/products/{id}/restore",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "Product restored", body = Product),
+        (status = 404, description = "No such product", body = Problem),
+        (status = 409, description = "Product isn't archived", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn restore_product(
+    _admin: AdminUser,
     state: web::Data<AppState>,
-    query: web::Query<SearchQuery>,
+    path: web::Path<i32>,
 ) -> Result<HttpResponse> {
-    let search_term = format!("%{}%", query.q.trim());
+    let product_id = path.into_inner();
     
-    let products = sqlx::query_as::<_, Product>(
+    let restored = sqlx::query_as::<_, Product>(
         r#"
-        SELECT * FROM products 
-        WHERE name LIKE ?1 OR description LIKE ?1
-        ORDER BY created_at DESC
+        UPDATE products
+        SET status = 'published', deleted_at = NULL, updated_at = datetime('now')
+        WHERE id = ?1 AND status = 'archived'
+        RETURNING *
         "#
     )
-    .bind(&search_term)
-    .fetch_all(&state.db)
+    .bind(product_id)
+    .fetch_optional(&state.db)
     .await?;
+    if let Some(product) = restored {
+        return Ok(HttpResponse::Ok().json(product));
+    }
     
-    Ok(HttpResponse::Ok().json(products))
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM products WHERE id = ?1)")
+        .bind(product_id)
+        .fetch_one(&state.db)
+        .await?;
+    if exists {
+        Err(AppError::Conflict("Product isn't archived".to_string()))
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+// Search products by relevance. Each word must match; the last word also
+// matches as a prefix so results update while the shopper is typing.
+#[utoipa::path(
+    get,
+    path = "/products/search",
+    tag = "products",
+    params(SearchQuery, ProductFilterQuery),
+    responses(
+        (status = 200, description = "A page of matches, most relevant first", body = Page<SearchResult>),
+        (status = 403, description = "Only admins can search unpublished products", body = Problem),
+    ),
+)]
+pub async fn search_products(
+    req: HttpRequest,
+    admin: Option<AdminUser>,
+    state: web::Data<AppState>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    check_status_access(query.status, admin.as_ref())?;
+    let filter = ProductFilter { status: query.status.unwrap_or_default(), ..ProductFilter::from(&query.filter) };
+    let (page, per_page) = page_params(query.page, query.per_page);
+    
+    let Some(fts_query) = fts_match_expression(&query.q) else {
+        return Ok(HttpResponse::Ok().json(paginate::<SearchResult>(&req, Vec::new(), page, per_page, 0)));
+    };
+    
+    let mut count = QueryBuilder::<Sqlite>::new(
+        "SELECT COUNT(*) FROM products_fts JOIN products ON products.id = products_fts.rowid"
+    );
+    push_product_filters(&mut count, filter.clone())?;
+    count.push(" AND products_fts MATCH ").push_bind(fts_query.clone());
+    let total: i64 = count.build_query_scalar()
+        .fetch_one(&state.db)
+        .await?;
+    
+    let mut select = QueryBuilder::<Sqlite>::new(
+        r#"
+        SELECT products.*,
+            bm25(products_fts, 10.0, 1.0) AS rank,
+            highlight(products_fts, 0, char(57344), char(57345)) AS highlighted_name,
+            snippet(products_fts, 1, char(57344), char(57345), '…', 16) AS snippet
+        FROM products_fts
+        JOIN products ON products.id = products_fts.rowid
+        "#
+    );
+    push_product_filters(&mut select, filter)?;
+    select.push(" AND products_fts MATCH ").push_bind(fts_query);
+    select.push(" ORDER BY rank, products.id");
+    select.push(" LIMIT ").push_bind(per_page);
+    select.push(" OFFSET ").push_bind(page_offset(page, per_page));
+    let mut results = select.build_query_as::<SearchResult>()
+        .fetch_all(&state.db)
+        .await?;
+    for result in &mut results {
+        result.highlighted_name = mark_matches(&result.highlighted_name);
+        result.snippet = mark_matches(&result.snippet);
+    }
+    
+    Ok(HttpResponse::Ok().json(paginate(&req, results, page, per_page, total)))
+}
+
+// HTML-escape highlighted product text, then turn the markers the search
+// query wraps matches in (U+E000 and U+E001, private use characters) into tags
+fn mark_matches(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\u{E000}' => html.push_str("<mark>"),
+            '\u{E001}' => html.push_str("</mark>"),
+            c => html.push(c),
+        }
+    }
+    html
 }
 
-#[derive(serde::Deserialize)]
+// Turn free text into an FTS5 query: every word is quoted so user input
+// can't inject FTS syntax, and the last word gets a prefix wildcard
+fn fts_match_expression(input: &str) -> Option<String> {
+    let terms: Vec<&str> = input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let (last, rest) = terms.split_last()?;
+    
+    let mut expression: Vec<String> = rest.iter().map(|t| format!("\"{}\"", t)).collect();
+    expression.push(format!("\"{}\"*", last));
+    Some(expression.join(" "))
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchQuery {
     pub q: String,
-}
\ No newline at end of file
+    #[serde(default, deserialize_with = "from_query_str")]
+    pub page: Option<i64>,
+    #[serde(default, deserialize_with = "from_query_str")]
+    pub per_page: Option<i64>,
+    #[serde(flatten)]
+    #[param(ignore)]
+    pub filter: ProductFilterQuery,
+    // Admins only; defaults to published
+    pub status: Option<ProductStatus>,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub product: Product,
+    // BM25 score; lower is more relevant
+    pub rank: f64,
+    pub highlighted_name: String,
+    pub snippet: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_and_read_body_json, TestRequest};
+    use super::*;
+    use crate::test_support::{test_app, TestDb};
+    
+    #[actix_web::test]
+    async fn listings_sort_filter_and_page() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        for (name, price_cents, stock) in [("Apron", 500, 3), ("Boots", 4000, 0), ("Cap", 1500, 10), ("Dress", 2500, 2)] {
+            sqlx::query("INSERT INTO products (name, price_cents, stock_quantity) VALUES (?1, ?2, ?3)")
+                .bind(name)
+                .bind(price_cents)
+                .bind(stock)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        let get = |uri: String| call_and_read_body_json::<_, _, serde_json::Value>(
+            &app,
+            TestRequest::get().uri(&uri).to_request(),
+        );
+        let names = |products: &serde_json::Value| -> Vec<String> {
+            products.as_array().unwrap().iter().map(|p| p["name"].as_str().unwrap().to_string()).collect()
+        };
+        
+        // v1, and the unversioned alias, return every match as an array
+        let listed = get("/api/v1/products?sort=price".to_string()).await;
+        assert_eq!(names(&listed), ["Apron", "Cap", "Dress", "Boots"]);
+        assert_eq!(get("/api/products?sort=price".to_string()).await, listed);
+        let listed = get("/api/v1/products?sort=price&order=desc&min_price=10&max_price=25.00".to_string()).await;
+        assert_eq!(names(&listed), ["Dress", "Cap"]);
+        let listed = get("/api/v1/products?sort=name&in_stock=true".to_string()).await;
+        assert_eq!(names(&listed), ["Apron", "Cap", "Dress"]);
+        let listed = get("/api/v1/products?in_stock=false".to_string()).await;
+        assert_eq!(names(&listed), ["Boots"]);
+        
+        // v2 pages the same listing, linking the neighbouring pages
+        let first = get("/api/v2/products?sort=name&per_page=3".to_string()).await;
+        assert_eq!(names(&first["items"]), ["Apron", "Boots", "Cap"]);
+        assert_eq!((first["total"].as_i64(), first["total_pages"].as_i64()), (Some(4), Some(2)));
+        assert_eq!(first["prev"], serde_json::Value::Null);
+        let next = first["next"].as_str().unwrap().to_string();
+        assert_eq!(next, "/api/v2/products?sort=name&per_page=3&page=2");
+        let second = get(next).await;
+        assert_eq!(names(&second["items"]), ["Dress"]);
+        assert_eq!(second["next"], serde_json::Value::Null);
+        assert_eq!(second["prev"], "/api/v2/products?sort=name&per_page=3&page=1");
+        
+        let in_stock = get("/api/v2/products?in_stock=true&min_price=10".to_string()).await;
+        assert_eq!(in_stock["total"], 2);
+    }
+    
+    #[actix_web::test]
+    async fn search_ranks_marks_and_follows_product_changes() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let mut ids = Vec::new();
+        for (name, description) in [
+            ("Wool Hat", "Warm <b>knitted</b> hat & scarf set"),
+            ("Cotton Shirt", "Pairs well with a wool hat"),
+            ("Woollen Socks", "Soft socks"),
+        ] {
+            let id = sqlx::query("INSERT INTO products (name, description, price_cents, stock_quantity) VALUES (?1, ?2, 1000, 5)")
+                .bind(name)
+                .bind(description)
+                .execute(&db.pool)
+                .await
+                .unwrap()
+                .last_insert_rowid();
+            ids.push(id);
+        }
+        let search = |q: &str| call_and_read_body_json::<_, _, serde_json::Value>(
+            &app,
+            TestRequest::get().uri(&format!("/api/v1/products/search?q={}", q)).to_request(),
+        );
+        let names = |page: &serde_json::Value| -> Vec<String> {
+            page["items"].as_array().unwrap().iter().map(|p| p["name"].as_str().unwrap().to_string()).collect()
+        };
+        
+        // Matches in the name outrank matches in the description; only the
+        // last word matches as a prefix
+        let found = search("wool%20hat").await;
+        assert_eq!(names(&found), ["Wool Hat", "Cotton Shirt"]);
+        assert_eq!(found["items"][0]["highlighted_name"], "<mark>Wool</mark> <mark>Hat</mark>");
+        assert_eq!(search("woo").await["total"], 3);
+        assert_eq!(names(&search("soc").await), ["Woollen Socks"]);
+        
+        // Stored text is escaped; only the markers become tags
+        assert_eq!(
+            search("knitted").await["items"][0]["snippet"],
+            "Warm &lt;b&gt;<mark>knitted</mark>&lt;/b&gt; hat &amp; scarf set",
+        );
+        
+        // The index follows renames and deletes
+        sqlx::query("UPDATE products SET name = 'Linen Hat' WHERE id = ?1")
+            .bind(ids[0])
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(names(&search("wool%20hat").await), ["Cotton Shirt"]);
+        assert_eq!(names(&search("linen").await), ["Linen Hat"]);
+        sqlx::query("DELETE FROM products WHERE id = ?1")
+            .bind(ids[1])
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(names(&search("wool").await), ["Woollen Socks"]);
+        
+        // Listing filters apply to the joined search query
+        assert_eq!(search("wool&min_price=10&in_stock=true").await["total"], 1);
+        assert_eq!(search("wool&max_price=9.99").await["total"], 0);
+    }
+    
+    #[test]
+    fn flattened_filters_are_parsed_from_their_text() {
+        let query = web::Query::<SearchQuery>::from_query(
+            "q=wool&page=2&min_price=9.50&category_id=3&in_stock=false&status=draft"
+        ).unwrap();
+        assert_eq!(query.page, Some(2));
+        let filter = ProductFilter::from(&query.filter);
+        assert_eq!(
+            (filter.min_price, filter.category_id, filter.in_stock, filter.status),
+            (Some(rust_decimal_macros::dec!(9.50)), Some(3), Some(false), ProductStatus::Published),
+        );
+        assert_eq!(query.status, Some(ProductStatus::Draft));
+        
+        assert!(web::Query::<ProductListQuery>::from_query("in_stock=maybe").is_err());
+        assert!(web::Query::<ProductFilterQuery>::from_query("max_price=lots").is_err());
+    }
+    
+    #[test]
+    fn search_input_becomes_a_quoted_prefix_query() {
+        assert_eq!(fts_match_expression("wool"), Some("\"wool\"*".to_string()));
+        assert_eq!(fts_match_expression("red  wool ha"), Some("\"red\" \"wool\" \"ha\"*".to_string()));
+        // FTS syntax and quotes are split away rather than passed through
+        assert_eq!(fts_match_expression("a\" OR name:b*"), Some("\"a\" \"OR\" \"name\" \"b\"*".to_string()));
+        assert_eq!(fts_match_expression(""), None);
+        assert_eq!(fts_match_expression("  -*\" "), None);
+    }
+    
+    #[test]
+    fn highlighted_text_is_escaped_before_matches_are_marked() {
+        assert_eq!(
+            mark_matches("<script>alert('x')</script> \u{E000}Hat\u{E001} & co"),
+            "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; <mark>Hat</mark> &amp; co",
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use actix_session::Session;
+use actix_web::{web, HttpResponse};
+use rust_decimal::Decimal;
+use sqlx::SqliteConnection;
+use validator::Validate;
+use crate::{
+    models::{Cart, Cents, CreateShippingMethod, ShippingKind, ShippingMethod},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::{AdminUser, AuthenticatedUser},
+    handlers::cart::{load_cart, summarize_cart},
+};
+
+// Get all shipping methods (admin)
+#[utoipa::path(
+    get,
+    path = "/shipping-methods",
+    tag = "shipping",
+    responses(
+        (status = 200, description = "All shipping methods", body = Vec<ShippingMethod>),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_shipping_methods(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let methods = sqlx::query_as::<_, ShippingMethod>(
+        "SELECT * FROM shipping_methods ORDER BY position, id"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(methods))
+}
+
+// Create shipping method (admin)
+#[utoipa::path(
+    post,
+    path = "/shipping-methods",
+    tag = "shipping",
+    request_body = CreateShippingMethod,
+    responses(
+        (status = 201, description = "Shipping method created", body = ShippingMethod),
+        (status = 422, description = "Invalid method", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_shipping_method(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    method: web::Json<CreateShippingMethod>,
+) -> Result<HttpResponse> {
+    let method = method.into_inner();
+    method.validate()?;
+    
+    let created = sqlx::query_as::<_, ShippingMethod>(
+        r#"
+        INSERT INTO shipping_methods (name, kind, rate_cents, per_kg_cents, threshold_cents, active, position)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        RETURNING *
+        "#
+    )
+    .bind(method.name.trim())
+    .bind(method.kind)
+    .bind(Cents::try_from(method.rate)?)
+    .bind(Cents::try_from(method.per_kg)?)
+    .bind(Cents::try_from(method.threshold)?)
+    .bind(method.active)
+    .bind(method.position)
+    .fetch_one(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Created().json(created))
+}
+
+// Update shipping method (admin)
+#[utoipa::path(
+    put,
+    path = "/shipping-methods/{id}",
+    tag = "shipping",
+    params(("id" = i64, Path, description = "Shipping method id")),
+    request_body = CreateShippingMethod,
+    responses(
+        (status = 200, description = "Shipping method updated", body = ShippingMethod),
+        (status = 404, description = "No such shipping method", body = Problem),
+        (status = 422, description = "Invalid method", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn update_shipping_method(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    method: web::Json<CreateShippingMethod>,
+) -> Result<HttpResponse> {
+    let method_id = path.into_inner();
+    let method = method.into_inner();
+    method.validate()?;
+    
+    let updated = sqlx::query_as::<_, ShippingMethod>(
+        r#"
+        UPDATE shipping_methods
+        SET name = ?1, kind = ?2, rate_cents = ?3, per_kg_cents = ?4, threshold_cents = ?5,
+            active = ?6, position = ?7, updated_at = datetime('now')
+        WHERE id = ?8
+        RETURNING *
+        "#
+    )
+    .bind(method.name.trim())
+    .bind(method.kind)
+    .bind(Cents::try_from(method.rate)?)
+    .bind(Cents::try_from(method.per_kg)?)
+    .bind(Cents::try_from(method.threshold)?)
+    .bind(method.active)
+    .bind(method.position)
+    .bind(method_id)
+    .fetch_optional(&state.db)
+    .await?;
+    
+    match updated {
+        Some(m) => Ok(HttpResponse::Ok().json(m)),
+        None => Err(AppError::NotFound),
+    }
+}
+
+// Delete shipping method (admin). Orders keep the method name and cost they were placed with.
+#[utoipa::path(
+    delete,
+    path = "/shipping-methods/{id}",
+    tag = "shipping",
+    params(("id" = i64, Path, description = "Shipping method id")),
+    responses(
+        (status = 204, description = "Shipping method deleted"),
+        (status = 404, description = "No such shipping method", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn delete_shipping_method(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let method_id = path.into_inner();
+    
+    let result = sqlx::query("DELETE FROM shipping_methods WHERE id = ?1")
+        .bind(method_id)
+        .execute(&state.db)
+        .await?;
+    
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+// Shipping methods available for the current cart, with their prices
+#[utoipa::path(
+    get,
+    path = "/cart/shipping",
+    tag = "cart",
+    responses(
+        (status = 200, description = "Shipping methods available for the cart, with their cost", body = Vec<ShippingQuote>),
+    ),
+)]
+pub async fn quote_shipping(
+    session: Session,
+    state: web::Data<AppState>,
+    customer: Option<AuthenticatedUser>,
+) -> Result<HttpResponse> {
+    let cart = load_cart(&state.db, &session).await?;
+    let summary = summarize_cart(&state.db, cart, customer.as_ref()).await?;
+    
+    let mut conn = state.db.acquire().await?;
+    let quotes = quote_methods(
+        &mut conn,
+        summary.total,
+        cart_weight(&summary.cart),
+        summary.free_shipping,
+    ).await?;
+    
+    Ok(HttpResponse::Ok().json(quotes))
+}
+
+// A shipping method offered for a cart
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct ShippingQuote {
+    pub method_id: i64,
+    pub name: String,
+    pub kind: ShippingKind,
+    pub cost: Decimal,
+}
+
+// Price every active method for goods of this value and weight, leaving out
+// methods that don't apply. A free shipping coupon zeroes every price.
+pub async fn quote_methods(
+    conn: &mut SqliteConnection,
+    goods_amount: Decimal,
+    weight_grams: i64,
+    free_shipping: bool,
+) -> Result<Vec<ShippingQuote>> {
+    let methods = sqlx::query_as::<_, ShippingMethod>(
+        "SELECT * FROM shipping_methods WHERE active = 1 ORDER BY position, id"
+    )
+    .fetch_all(conn)
+    .await?;
+    
+    let quotes = methods.into_iter()
+        .filter_map(|method| {
+            let cost = method.cost_for(goods_amount, weight_grams)?;
+            Some(ShippingQuote {
+                method_id: method.id,
+                name: method.name,
+                kind: method.kind,
+                cost: if free_shipping { Decimal::ZERO } else { cost },
+            })
+        })
+        .collect();
+    
+    Ok(quotes)
+}
+
+// Total weight of a cart whose product details are loaded
+pub fn cart_weight(cart: &Cart) -> i64 {
+    cart.items.iter()
+        .filter_map(|item| {
+            item.product.as_ref().map(|p| i64::from(p.weight_grams) * i64::from(item.quantity))
+        })
+        .sum()
+}
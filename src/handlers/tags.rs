@@ -0,0 +1,257 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use validator::Validate;
+use crate::{
+    models::{Page, Product, SetProductTags, Tag, TagCount},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AdminUser,
+    handlers::{
+        page_offset, page_params, paginate, PageQuery,
+        products::{push_product_filters, ProductFilter, ProductFilterQuery},
+    },
+};
+
+// Tags in use with how many of the matching products carry each, most used first
+#[utoipa::path(
+    get,
+    path = "/tags",
+    tag = "tags",
+    params(ProductFilterQuery),
+    responses(
+        (status = 200, description = "Tags with product counts", body = Vec<TagCount>),
+        (status = 400, description = "Invalid price filter", body = Problem),
+    ),
+)]
+pub async fn list_tags(
+    state: web::Data<AppState>,
+    query: web::Query<ProductFilterQuery>,
+) -> Result<HttpResponse> {
+    let counts = tag_counts(&state.db, ProductFilter::from(&*query)).await?;
+    
+    Ok(HttpResponse::Ok().json(counts))
+}
+
+// Products carrying a tag, paginated by name
+#[utoipa::path(
+    get,
+    path = "/tags/{slug}/products",
+    tag = "tags",
+    params(("slug" = String, Path, description = "Tag slug"), PageQuery),
+    responses(
+        (status = 200, description = "A page of the tag's products", body = Page<Product>),
+        (status = 404, description = "No such tag", body = Problem),
+    ),
+)]
+pub async fn get_tag_products(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse> {
+    let slug = path.into_inner();
+    let (page, per_page) = page_params(query.page, query.per_page);
+    
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM tags WHERE slug = ?1)")
+        .bind(&slug)
+        .fetch_one(&state.db)
+        .await?;
+    if !exists {
+        return Err(AppError::NotFound);
+    }
+    
+    let filter = ProductFilter { tag: Some(slug), ..Default::default() };
+    
+    let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM products");
+    push_product_filters(&mut count, filter.clone())?;
+    let total: i64 = count.build_query_scalar()
+        .fetch_one(&state.db)
+        .await?;
+    
+    let mut select = QueryBuilder::<Sqlite>::new("SELECT * FROM products");
+    push_product_filters(&mut select, filter)?;
+    select.push(" ORDER BY name, id LIMIT ").push_bind(per_page)
+        .push(" OFFSET ").push_bind(page_offset(page, per_page));
+    let products = select.build_query_as::<Product>()
+        .fetch_all(&state.db)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(paginate(&req, products, page, per_page, total)))
+}
+
+// Tags on a product, by name
+#[utoipa::path(
+    get,
+    path = "/products/{id}/tags",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "The product's tags", body = Vec<Tag>),
+    ),
+)]
+pub async fn get_product_tags(
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let mut conn = state.db.acquire().await?;
+    let tags = load_product_tags(&mut conn, path.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+// Replace a product's tags (admin). Names are matched to existing tags by
+// slug, so "Summer Sale" and "summer-sale" are the same tag; new names
+// create tags.
+#[utoipa::path(
+    put,
+    path = "/products/{id}/tags",
+    tag = "products",
+    params(("id" = i32, Path, description = "Product id")),
+    request_body = SetProductTags,
+    responses(
+        (status = 200, description = "The product's tags", body = Vec<Tag>),
+        (status = 404, description = "No such product", body = Problem),
+        (status = 422, description = "Invalid tag names", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn set_product_tags(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+    body: web::Json<SetProductTags>,
+) -> Result<HttpResponse> {
+    let product_id = path.into_inner();
+    let body = body.into_inner();
+    body.validate()?;
+    
+    let mut tx = state.db.begin().await?;
+    
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM products WHERE id = ?1)")
+        .bind(product_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !exists {
+        return Err(AppError::NotFound);
+    }
+    
+    let tags = replace_product_tags(&mut tx, product_id, &body.tags).await?;
+    
+    tx.commit().await?;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+// Lowercase, with every run of other characters turned into a single dash
+pub fn slugify(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+async fn replace_product_tags(conn: &mut SqliteConnection, product_id: i32, names: &[String]) -> Result<Vec<Tag>> {
+    sqlx::query("DELETE FROM product_tags WHERE product_id = ?1")
+        .bind(product_id)
+        .execute(&mut *conn)
+        .await?;
+    
+    for name in names {
+        let name = name.trim();
+        let slug = slugify(name);
+        sqlx::query("INSERT OR IGNORE INTO tags (name, slug) VALUES (?1, ?2)")
+            .bind(name)
+            .bind(&slug)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO product_tags (product_id, tag_id)
+            SELECT ?1, id FROM tags WHERE slug = ?2
+            "#
+        )
+        .bind(product_id)
+        .bind(&slug)
+        .execute(&mut *conn)
+        .await?;
+    }
+    
+    load_product_tags(conn, product_id).await
+}
+
+async fn load_product_tags(conn: &mut SqliteConnection, product_id: i32) -> Result<Vec<Tag>> {
+    let tags = sqlx::query_as::<_, Tag>(
+        r#"
+        SELECT t.* FROM product_tags pt
+        JOIN tags t ON t.id = pt.tag_id
+        WHERE pt.product_id = ?1
+        ORDER BY t.name
+        "#
+    )
+    .bind(product_id)
+    .fetch_all(conn)
+    .await?;
+    
+    Ok(tags)
+}
+
+async fn tag_counts(db: &SqlitePool, filter: ProductFilter) -> Result<Vec<TagCount>> {
+    let mut qb = QueryBuilder::<Sqlite>::new(
+        r#"
+        SELECT t.*, COUNT(*) AS product_count
+        FROM tags t JOIN product_tags pt ON pt.tag_id = t.id
+        WHERE pt.product_id IN (SELECT id FROM products
+        "#
+    );
+    push_product_filters(&mut qb, filter)?;
+    qb.push(") GROUP BY t.id ORDER BY product_count DESC, t.name");
+    
+    let counts = qb.build_query_as::<TagCount>()
+        .fetch_all(db)
+        .await?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestDb;
+    
+    #[tokio::test]
+    async fn tags_are_shared_by_slug_and_counted_per_filter() {
+        assert_eq!(slugify("  Summer Sale!! 2025 "), "summer-sale-2025");
+        
+        let db = TestDb::new().await;
+        let insert = |name: &'static str, stock: i32| {
+            sqlx::query_scalar::<_, i32>(
+                "INSERT INTO products (name, price_cents, stock_quantity) VALUES (?1, 1000, ?2) RETURNING id"
+            )
+            .bind(name)
+            .bind(stock)
+            .fetch_one(&db.pool)
+        };
+        let hat = insert("Hat", 5).await.unwrap();
+        let scarf = insert("Scarf", 0).await.unwrap();
+        
+        let mut conn = db.pool.acquire().await.unwrap();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        replace_product_tags(&mut conn, hat, &names(&["Summer Sale", "Wool"])).await.unwrap();
+        let tags = replace_product_tags(&mut conn, scarf, &names(&["summer-sale", "WOOL", "wool"])).await.unwrap();
+        let tags: Vec<_> = tags.into_iter().map(|t| (t.name, t.slug)).collect();
+        assert_eq!(tags, [("Summer Sale".to_string(), "summer-sale".to_string()), ("Wool".to_string(), "wool".to_string())]);
+        
+        let counts = |filter: ProductFilter| async {
+            tag_counts(&db.pool, filter).await.unwrap()
+                .into_iter()
+                .map(|c| (c.tag.slug, c.product_count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(counts(ProductFilter::default()).await, [("summer-sale".to_string(), 2), ("wool".to_string(), 2)]);
+        
+        replace_product_tags(&mut conn, scarf, &names(&["Wool"])).await.unwrap();
+        let in_stock = ProductFilter { in_stock: Some(false), ..Default::default() };
+        assert_eq!(counts(in_stock).await, [("wool".to_string(), 1)]);
+    }
+}
@@ -0,0 +1,267 @@
+use actix_web::{web, HttpResponse};
+use validator::Validate;
+use crate::{
+    models::{CreateTaxClass, CreateTaxRate, DecimalText, TaxClass, TaxRate},
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AdminUser,
+    tax::STANDARD_TAX_CLASS_ID,
+};
+
+// Get all tax classes (admin)
+#[utoipa::path(
+    get,
+    path = "/tax-classes",
+    tag = "tax",
+    responses(
+        (status = 200, description = "All tax classes", body = Vec<TaxClass>),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_tax_classes(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let classes = sqlx::query_as::<_, TaxClass>(
+        "SELECT * FROM tax_classes ORDER BY id"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(classes))
+}
+
+// Create tax class (admin)
+#[utoipa::path(
+    post,
+    path = "/tax-classes",
+    tag = "tax",
+    request_body = CreateTaxClass,
+    responses(
+        (status = 201, description = "Tax class created", body = TaxClass),
+        (status = 409, description = "Name already taken", body = Problem),
+        (status = 422, description = "Invalid name", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_tax_class(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    class: web::Json<CreateTaxClass>,
+) -> Result<HttpResponse> {
+    let class = class.into_inner();
+    class.validate()?;
+    let name = class.name.trim();
+    
+    let created = sqlx::query_as::<_, TaxClass>(
+        "INSERT INTO tax_classes (name) VALUES (?1) ON CONFLICT (name) DO NOTHING RETURNING *"
+    )
+    .bind(name)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::Conflict(format!("Tax class {} already exists", name)))?;
+    
+    Ok(HttpResponse::Created().json(created))
+}
+
+// Delete tax class (admin). Its categories fall back to the standard class.
+#[utoipa::path(
+    delete,
+    path = "/tax-classes/{id}",
+    tag = "tax",
+    params(("id" = i64, Path, description = "Tax class id")),
+    responses(
+        (status = 204, description = "Tax class deleted"),
+        (status = 404, description = "No such tax class", body = Problem),
+        (status = 409, description = "The standard class cannot be deleted", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn delete_tax_class(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let class_id = path.into_inner();
+    if class_id == STANDARD_TAX_CLASS_ID {
+        return Err(AppError::BadRequest("The standard tax class can't be deleted".to_string()));
+    }
+    
+    let result = sqlx::query("DELETE FROM tax_classes WHERE id = ?1")
+        .bind(class_id)
+        .execute(&state.db)
+        .await?;
+    
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+// Get all tax rates (admin)
+#[utoipa::path(
+    get,
+    path = "/tax-rates",
+    tag = "tax",
+    responses(
+        (status = 200, description = "All tax rates", body = Vec<TaxRate>),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_tax_rates(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let rates = sqlx::query_as::<_, TaxRate>(
+        "SELECT * FROM tax_rates ORDER BY country, region, tax_class_id"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(rates))
+}
+
+// Create tax rate (admin)
+#[utoipa::path(
+    post,
+    path = "/tax-rates",
+    tag = "tax",
+    request_body = CreateTaxRate,
+    responses(
+        (status = 201, description = "Tax rate created", body = TaxRate),
+        (status = 422, description = "Invalid rate", body = Problem),
+        (status = 409, description = "A rate already exists for that class and place", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_tax_rate(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    rate: web::Json<CreateTaxRate>,
+) -> Result<HttpResponse> {
+    let rate = normalize_rate(rate.into_inner())?;
+    
+    let created = sqlx::query_as::<_, TaxRate>(
+        r#"
+        INSERT INTO tax_rates (tax_class_id, country, region, name, rate)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT DO NOTHING
+        RETURNING *
+        "#
+    )
+    .bind(rate.tax_class_id)
+    .bind(&rate.country)
+    .bind(&rate.region)
+    .bind(&rate.name)
+    .bind(DecimalText::from(rate.rate))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::Conflict(
+        "A rate for this tax class and destination already exists".to_string()
+    ))?;
+    
+    Ok(HttpResponse::Created().json(created))
+}
+
+// Update tax rate (admin)
+#[utoipa::path(
+    put,
+    path = "/tax-rates/{id}",
+    tag = "tax",
+    params(("id" = i64, Path, description = "Tax rate id")),
+    request_body = CreateTaxRate,
+    responses(
+        (status = 200, description = "Tax rate updated", body = TaxRate),
+        (status = 404, description = "No such tax rate", body = Problem),
+        (status = 422, description = "Invalid rate", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn update_tax_rate(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    rate: web::Json<CreateTaxRate>,
+) -> Result<HttpResponse> {
+    let rate_id = path.into_inner();
+    let rate = normalize_rate(rate.into_inner())?;
+    
+    let updated = sqlx::query_as::<_, TaxRate>(
+        r#"
+        UPDATE tax_rates
+        SET tax_class_id = ?1, country = ?2, region = ?3, name = ?4, rate = ?5,
+            updated_at = datetime('now')
+        WHERE id = ?6
+        RETURNING *
+        "#
+    )
+    .bind(rate.tax_class_id)
+    .bind(&rate.country)
+    .bind(&rate.region)
+    .bind(&rate.name)
+    .bind(DecimalText::from(rate.rate))
+    .bind(rate_id)
+    .fetch_optional(&state.db)
+    .await?;
+    
+    match updated {
+        Some(r) => Ok(HttpResponse::Ok().json(r)),
+        None => Err(AppError::NotFound),
+    }
+}
+
+// Delete tax rate (admin)
+#[utoipa::path(
+    delete,
+    path = "/tax-rates/{id}",
+    tag = "tax",
+    params(("id" = i64, Path, description = "Tax rate id")),
+    responses(
+        (status = 204, description = "Tax rate deleted"),
+        (status = 404, description = "No such tax rate", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn delete_tax_rate(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let rate_id = path.into_inner();
+    
+    let result = sqlx::query("DELETE FROM tax_rates WHERE id = ?1")
+        .bind(rate_id)
+        .execute(&state.db)
+        .await?;
+    
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+// Countries are ISO 3166-1 alpha-2 codes; a blank region means the whole country
+fn normalize_rate(mut rate: CreateTaxRate) -> Result<CreateTaxRate> {
+    rate.country = rate.country.trim().to_uppercase();
+    rate.region = rate.region
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty());
+    rate.validate()?;
+    Ok(rate)
+}
@@ -0,0 +1,521 @@
+use std::collections::HashSet;
+
+use actix_web::{web, HttpResponse};
+use sqlx::SqliteConnection;
+use validator::Validate;
+use crate::{
+    models::{
+        Cents, CreateOptionType, CreateOptionValue, CreateVariant, OptionType, OptionValue,
+        ProductVariant, VariantOption,
+    },
+    errors::{Result, AppError, Problem},
+    AppState,
+    auth::AdminUser,
+};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct OptionTypeWithValues {
+    pub option_type: OptionType,
+    pub values: Vec<OptionValue>,
+}
+
+// Get all option types with their values
+#[utoipa::path(
+    get,
+    path = "/option-types",
+    tag = "variants",
+    responses(
+        (status = 200, description = "Option types with their values", body = Vec<OptionTypeWithValues>),
+    ),
+)]
+pub async fn get_option_types(
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let types = sqlx::query_as::<_, OptionType>(
+        "SELECT * FROM option_types ORDER BY name"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    
+    let values = sqlx::query_as::<_, OptionValue>(
+        "SELECT * FROM option_values ORDER BY option_type_id, position, id"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    
+    let result: Vec<_> = types.into_iter()
+        .map(|t| {
+            let values = values.iter()
+                .filter(|v| v.option_type_id == t.id)
+                .cloned()
+                .collect();
+            OptionTypeWithValues { option_type: t, values }
+        })
+        .collect();
+    
+    Ok(HttpResponse::Ok().json(result))
+}
+
+// Create option type with initial values (admin)
+#[utoipa::path(
+    post,
+    path = "/option-types",
+    tag = "variants",
+    request_body = CreateOptionType,
+    responses(
+        (status = 201, description = "Option type created", body = OptionTypeWithValues),
+        (status = 422, description = "Invalid option type", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_option_type(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    option_type: web::Json<CreateOptionType>,
+) -> Result<HttpResponse> {
+    let option_type = option_type.into_inner();
+    option_type.validate()?;
+    
+    let mut tx = state.db.begin().await?;
+    
+    let created = sqlx::query_as::<_, OptionType>(
+        "INSERT INTO option_types (name) VALUES (?1) RETURNING *"
+    )
+    .bind(&option_type.name)
+    .fetch_one(&mut *tx)
+    .await?;
+    
+    let mut values = Vec::with_capacity(option_type.values.len());
+    for (position, value) in option_type.values.iter().enumerate() {
+        values.push(insert_option_value(&mut tx, created.id, value, position as i32).await?);
+    }
+    
+    tx.commit().await?;
+    
+    Ok(HttpResponse::Created().json(OptionTypeWithValues { option_type: created, values }))
+}
+
+// Add a value to an option type (admin)
+#[utoipa::path(
+    post,
+    path = "/option-types/{id}/values",
+    tag = "variants",
+    params(("id" = i64, Path, description = "Option type id")),
+    request_body = CreateOptionValue,
+    responses(
+        (status = 201, description = "Value added", body = OptionValue),
+        (status = 404, description = "No such option type", body = Problem),
+        (status = 422, description = "Invalid value", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_option_value(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    value: web::Json<CreateOptionValue>,
+) -> Result<HttpResponse> {
+    let option_type_id = path.into_inner();
+    let value = value.into_inner();
+    value.validate()?;
+    
+    let mut tx = state.db.begin().await?;
+    
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM option_types WHERE id = ?1)"
+    )
+    .bind(option_type_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !exists {
+        return Err(AppError::NotFound);
+    }
+    
+    let created = insert_option_value(&mut tx, option_type_id, &value.value, value.position.unwrap_or(0)).await?;
+    tx.commit().await?;
+    
+    Ok(HttpResponse::Created().json(created))
+}
+
+// Get a product's variants
+#[utoipa::path(
+    get,
+    path = "/products/{id}/variants",
+    tag = "variants",
+    params(("id" = i32, Path, description = "Product id")),
+    responses(
+        (status = 200, description = "The product's variants", body = Vec<ProductVariant>),
+        (status = 404, description = "No such product", body = Problem),
+    ),
+)]
+pub async fn get_product_variants(
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse> {
+    let product_id = path.into_inner();
+    
+    let mut variants = sqlx::query_as::<_, ProductVariant>(
+        "SELECT * FROM product_variants WHERE product_id = ?1 ORDER BY id"
+    )
+    .bind(product_id)
+    .fetch_all(&state.db)
+    .await?;
+    
+    let mut conn = state.db.acquire().await?;
+    for variant in &mut variants {
+        variant.options = load_variant_options(&mut conn, variant.id).await?;
+    }
+    
+    Ok(HttpResponse::Ok().json(variants))
+}
+
+// Create variant (admin)
+#[utoipa::path(
+    post,
+    path = "/products/{id}/variants",
+    tag = "variants",
+    params(("id" = i32, Path, description = "Product id")),
+    request_body = CreateVariant,
+    responses(
+        (status = 201, description = "Variant created", body = ProductVariant),
+        (status = 404, description = "No such product", body = Problem),
+        (status = 422, description = "Invalid variant", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn create_variant(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i32>,
+    variant: web::Json<CreateVariant>,
+) -> Result<HttpResponse> {
+    let product_id = path.into_inner();
+    let variant = variant.into_inner();
+    variant.validate()?;
+    let price = variant.price.map(Cents::try_from).transpose()?;
+    
+    let mut tx = state.db.begin().await?;
+    
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM products WHERE id = ?1)"
+    )
+    .bind(product_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if !exists {
+        return Err(AppError::NotFound);
+    }
+    
+    let variant_id: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO product_variants (product_id, sku, price_cents, stock_quantity)
+        VALUES (?1, ?2, ?3, ?4)
+        RETURNING id
+        "#
+    )
+    .bind(product_id)
+    .bind(&variant.sku)
+    .bind(price)
+    .bind(variant.stock_quantity)
+    .fetch_one(&mut *tx)
+    .await?;
+    
+    set_variant_options(&mut tx, variant_id, &variant.option_value_ids).await?;
+    tx.commit().await?;
+    
+    let created = find_variant(&mut *state.db.acquire().await?, variant_id).await?.ok_or(AppError::NotFound)?;
+    Ok(HttpResponse::Created().json(created))
+}
+
+// Update variant (admin)
+#[utoipa::path(
+    put,
+    path = "/variants/{id}",
+    tag = "variants",
+    params(("id" = i64, Path, description = "Variant id")),
+    request_body = CreateVariant,
+    responses(
+        (status = 200, description = "Variant updated", body = ProductVariant),
+        (status = 404, description = "No such variant", body = Problem),
+        (status = 422, description = "Invalid variant", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn update_variant(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+    variant: web::Json<CreateVariant>,
+) -> Result<HttpResponse> {
+    let variant_id = path.into_inner();
+    let variant = variant.into_inner();
+    variant.validate()?;
+    let price = variant.price.map(Cents::try_from).transpose()?;
+    
+    let mut tx = state.db.begin().await?;
+    
+    let result = sqlx::query(
+        r#"
+        UPDATE product_variants
+        SET sku = ?1, price_cents = ?2, stock_quantity = ?3, updated_at = datetime('now')
+        WHERE id = ?4
+        "#
+    )
+    .bind(&variant.sku)
+    .bind(price)
+    .bind(variant.stock_quantity)
+    .bind(variant_id)
+    .execute(&mut *tx)
+    .await?;
+    
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    
+    set_variant_options(&mut tx, variant_id, &variant.option_value_ids).await?;
+    tx.commit().await?;
+    
+    let updated = find_variant(&mut *state.db.acquire().await?, variant_id).await?.ok_or(AppError::NotFound)?;
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+// Delete variant (admin)
+#[utoipa::path(
+    delete,
+    path = "/variants/{id}",
+    tag = "variants",
+    params(("id" = i64, Path, description = "Variant id")),
+    responses(
+        (status = 204, description = "Variant deleted"),
+        (status = 404, description = "No such variant", body = Problem),
+        (status = 409, description = "Variant has been ordered", body = Problem),
+        (status = 401, description = "Not signed in", body = Problem),
+        (status = 403, description = "Not an admin", body = Problem),
+    ),
+    security(("session" = [])),
+)]
+pub async fn delete_variant(
+    _admin: AdminUser,
+    state: web::Data<AppState>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse> {
+    let variant_id = path.into_inner();
+    
+    // Order lines keep pointing at the variant they were bought as
+    let ordered: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM order_items WHERE variant_id = ?1)"
+    )
+    .bind(variant_id)
+    .fetch_one(&state.db)
+    .await?;
+    if ordered {
+        return Err(AppError::Conflict(
+            "Variant has been ordered; set its stock to 0 instead".to_string()
+        ));
+    }
+    
+    let result = sqlx::query("DELETE FROM product_variants WHERE id = ?1")
+        .bind(variant_id)
+        .execute(&state.db)
+        .await?;
+    
+    if result.rows_affected() > 0 {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+// Helper functions
+
+// Load a variant with its option values
+pub async fn find_variant(conn: &mut SqliteConnection, variant_id: i64) -> Result<Option<ProductVariant>> {
+    let variant = sqlx::query_as::<_, ProductVariant>(
+        "SELECT * FROM product_variants WHERE id = ?1"
+    )
+    .bind(variant_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    
+    match variant {
+        Some(mut v) => {
+            v.options = load_variant_options(conn, v.id).await?;
+            Ok(Some(v))
+        },
+        None => Ok(None),
+    }
+}
+
+async fn load_variant_options(conn: &mut SqliteConnection, variant_id: i64) -> Result<Vec<VariantOption>> {
+    let options = sqlx::query_as::<_, VariantOption>(
+        r#"
+        SELECT ov.id AS option_value_id, ot.name AS option_type, ov.value
+        FROM variant_option_values vov
+        JOIN option_values ov ON ov.id = vov.option_value_id
+        JOIN option_types ot ON ot.id = ov.option_type_id
+        WHERE vov.variant_id = ?1
+        ORDER BY ot.name, ov.position
+        "#
+    )
+    .bind(variant_id)
+    .fetch_all(conn)
+    .await?;
+    
+    Ok(options)
+}
+
+async fn insert_option_value(
+    conn: &mut SqliteConnection,
+    option_type_id: i64,
+    value: &str,
+    position: i32,
+) -> Result<OptionValue> {
+    let created = sqlx::query_as::<_, OptionValue>(
+        r#"
+        INSERT INTO option_values (option_type_id, value, position)
+        VALUES (?1, ?2, ?3)
+        RETURNING *
+        "#
+    )
+    .bind(option_type_id)
+    .bind(value)
+    .bind(position)
+    .fetch_one(conn)
+    .await?;
+    
+    Ok(created)
+}
+
+// Replace a variant's option values; a variant takes at most one value per option type
+async fn set_variant_options(
+    conn: &mut SqliteConnection,
+    variant_id: i64,
+    option_value_ids: &[i64],
+) -> Result<()> {
+    let mut option_types = HashSet::new();
+    for option_value_id in option_value_ids {
+        let option_type_id: i64 = sqlx::query_scalar(
+            "SELECT option_type_id FROM option_values WHERE id = ?1"
+        )
+        .bind(option_value_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| AppError::BadRequest(format!("Unknown option value {}", option_value_id)))?;
+        
+        if !option_types.insert(option_type_id) {
+            return Err(AppError::BadRequest(
+                "A variant can have only one value per option type".to_string()
+            ));
+        }
+    }
+    
+    sqlx::query("DELETE FROM variant_option_values WHERE variant_id = ?1")
+        .bind(variant_id)
+        .execute(&mut *conn)
+        .await?;
+    
+    for option_value_id in option_value_ids {
+        sqlx::query(
+            "INSERT INTO variant_option_values (variant_id, option_value_id) VALUES (?1, ?2)"
+        )
+        .bind(variant_id)
+        .bind(option_value_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{cookie::Cookie, http::StatusCode, test};
+    use crate::test_support::{add_to_cart, admin_cookie, insert_product, register, session_cookie, test_app, TestDb};
+    
+    #[tokio::test]
+    async fn variant_lines_merge_and_reserve_their_own_stock() {
+        let db = TestDb::new().await;
+        let app = test_app(&db).await;
+        let product_id = insert_product(&db.pool, "Shirt", 50).await;
+        let insert_variant = |sku: &'static str| {
+            sqlx::query_scalar::<_, i64>(
+                "INSERT INTO product_variants (product_id, sku, stock_quantity) VALUES (?1, ?2, 5) RETURNING id"
+            )
+            .bind(product_id)
+            .bind(sku)
+            .fetch_one(&db.pool)
+        };
+        let small = insert_variant("SHIRT-S").await.unwrap();
+        let large = insert_variant("SHIRT-L").await.unwrap();
+        let unsold = insert_variant("SHIRT-XL").await.unwrap();
+        
+        let customer = register(&app, "Ann", "ann@example.com").await;
+        let add = |cookie: Option<Cookie<'static>>, variant_id: i64, quantity: i32| {
+            add_to_cart(&app, cookie, serde_json::json!({ "product_id": product_id, "variant_id": variant_id, "quantity": quantity }))
+        };
+        
+        // The guest's small shirt merges into the customer's small line, not the large one
+        let customer = add(Some(customer), small, 2).await;
+        add(Some(customer), large, 1).await;
+        let guest = add(None, small, 1).await;
+        let login = test::TestRequest::post()
+            .uri("/api/v1/auth/login")
+            .cookie(guest)
+            .set_json(serde_json::json!({ "email": "ann@example.com", "password": "long enough" }))
+            .to_request();
+        let customer = session_cookie(&test::call_service(&app, login).await);
+        
+        let cart: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get()
+            .uri("/api/v1/cart")
+            .cookie(customer.clone())
+            .to_request()).await;
+        let lines: Vec<_> = cart["items"].as_array().unwrap().iter()
+            .map(|item| (item["variant_id"].as_i64().unwrap(), item["quantity"].as_i64().unwrap()))
+            .collect();
+        assert_eq!(lines, [(small, 3), (large, 1)]);
+        
+        // Checkout takes stock from each variant, leaving the product's own count alone
+        let checkout = test::TestRequest::post()
+            .uri("/api/v1/orders")
+            .cookie(customer)
+            .set_json(serde_json::json!({
+                "customer_name": "Ann",
+                "customer_email": "ann@example.com",
+                "shipping_address": {
+                    "name": "Ann", "line1": "1 Test Street", "city": "Testville",
+                    "postal_code": "12345", "country": "US",
+                },
+            }))
+            .to_request();
+        assert_eq!(test::call_service(&app, checkout).await.status(), StatusCode::OK);
+        let stock: Vec<(i64, i32)> = sqlx::query_as("SELECT id, stock_quantity FROM product_variants ORDER BY id")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stock, [(small, 2), (large, 4), (unsold, 5)]);
+        let product_stock: i32 = sqlx::query_scalar("SELECT stock_quantity FROM products WHERE id = ?1")
+            .bind(product_id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(product_stock, 50);
+        
+        // Ordered variants stay for the order lines that refer to them
+        let admin = admin_cookie(&app, &db).await;
+        for (variant_id, status) in [(small, StatusCode::CONFLICT), (unsold, StatusCode::NO_CONTENT)] {
+            let delete = test::TestRequest::delete()
+                .uri(&format!("/api/v1/variants/{}", variant_id))
+                .cookie(admin.clone())
+                .to_request();
+            assert_eq!(test::call_service(&app, delete).await.status(), status);
+        }
+    }
+}
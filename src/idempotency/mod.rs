@@ -0,0 +1,294 @@
+use actix_session::{Session, SessionExt};
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    web, HttpResponse,
+};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use crate::{auth::current_user_id, errors::{Result, AppError}, images::MAX_UPLOAD_REQUEST_BYTES, AppState};
+
+// Header clients send to make a mutating request safe to retry
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+// Set on responses served from a stored result
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+// How long stored responses are kept
+pub const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+// Session key holding a guest's idempotency key namespace
+const SESSION_GUEST_TOKEN_KEY: &str = "idempotency_token";
+
+#[derive(sqlx::FromRow)]
+struct StoredRequest {
+    method: String,
+    path: String,
+    request_hash: String,
+    response_status: Option<u16>,
+    response_content_type: Option<String>,
+    response_body: Option<Vec<u8>>,
+}
+
+// Middleware for POST, PUT, PATCH and DELETE requests carrying an
+// Idempotency-Key. The first request claims the key and its response is
+// stored; repeats of the same request get that response back without the
+// handler running again. Reusing a key for a different request is rejected,
+// as is a repeat that arrives while the first is still running. Server
+// errors aren't stored, so those requests can be retried under the same key.
+// Keys belong to the signed-in user, or for guests to their session.
+pub async fn idempotency(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> std::result::Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(key) if is_mutating(req.method()) => parse_key(key)?,
+        _ => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+    };
+    
+    let db = req.app_data::<web::Data<AppState>>()
+        .map(|state| state.db.clone())
+        .ok_or(AppError::InternalError)?;
+    let session = req.get_session();
+    let user_id = current_user_id(&session)?;
+    let guest_token = match user_id {
+        Some(_) => None,
+        None => Some(guest_token(&session)?),
+    };
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+    
+    // Buffer the body, up to the largest any route accepts, so its hash can
+    // be compared with later repeats
+    let request_body = req.extract::<web::Payload>().await?
+        .to_bytes_limited(MAX_UPLOAD_REQUEST_BYTES)
+        .await
+        .map_err(|_| AppError::BadRequest("Request body is too large".to_string()))??;
+    let request_hash = hex::encode(Sha256::digest(&request_body));
+    req.set_payload(Payload::from(request_body));
+    
+    let claimed = sqlx::query(
+        r#"
+        INSERT INTO idempotency_keys (idempotency_key, user_id, guest_token, method, path, request_hash)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT DO NOTHING
+        "#
+    )
+    .bind(&key)
+    .bind(user_id)
+    .bind(&guest_token)
+    .bind(&method)
+    .bind(&path)
+    .bind(&request_hash)
+    .execute(&db)
+    .await
+    .map_err(AppError::from)?;
+    
+    if claimed.rows_affected() == 0 {
+        let stored = sqlx::query_as::<_, StoredRequest>(
+            r#"
+            SELECT * FROM idempotency_keys
+            WHERE idempotency_key = ?1 AND user_id IS ?2 AND guest_token IS ?3
+            "#
+        )
+        .bind(&key)
+        .bind(user_id)
+        .bind(&guest_token)
+        .fetch_one(&db)
+        .await
+        .map_err(AppError::from)?;
+        
+        return Ok(match replay(stored, &method, &path, &request_hash) {
+            Ok(response) => req.into_response(response),
+            Err(e) => req.error_response(e),
+        });
+    }
+    let claim_id = claimed.last_insert_rowid();
+    
+    let response = match next.call(req).await {
+        Ok(response) => response,
+        Err(e) => {
+            release_key(&db, claim_id).await;
+            return Err(e);
+        },
+    };
+    if response.status().is_server_error() {
+        release_key(&db, claim_id).await;
+        return Ok(response.map_into_boxed_body());
+    }
+    
+    let (req, response) = response.into_parts();
+    let (response, response_body) = response.into_parts();
+    let response_body = body::to_bytes(response_body)
+        .await
+        .map_err(|_| AppError::InternalError)?;
+    
+    let content_type = response.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let stored = sqlx::query(
+        r#"
+        UPDATE idempotency_keys
+        SET response_status = ?1, response_content_type = ?2, response_body = ?3
+        WHERE id = ?4
+        "#
+    )
+    .bind(response.status().as_u16())
+    .bind(content_type)
+    .bind(response_body.as_ref())
+    .bind(claim_id)
+    .execute(&db)
+    .await;
+    // The request has already run; losing the stored copy only means a retry runs it again
+    if let Err(e) = stored {
+        log::error!("Failed to store response for idempotency key {}: {}", key, e);
+    }
+    
+    let response = response.set_body(response_body).map_into_boxed_body();
+    Ok(ServiceResponse::new(req, response))
+}
+
+// Drop stored responses once clients have stopped retrying
+pub async fn expire_idempotency_keys(db: &SqlitePool) -> Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM idempotency_keys WHERE created_at < datetime('now', ?1)"
+    )
+    .bind(format!("-{} hours", IDEMPOTENCY_KEY_TTL_HOURS))
+    .execute(db)
+    .await?;
+    
+    Ok(result.rows_affected())
+}
+
+fn is_mutating(method: &Method) -> bool {
+    matches!(*method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE)
+}
+
+fn parse_key(value: &header::HeaderValue) -> Result<String> {
+    value.to_str()
+        .ok()
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && key.len() <= 255)
+        .map(str::to_string)
+        .ok_or_else(|| AppError::BadRequest(
+            format!("{} must be 1 to 255 printable characters", IDEMPOTENCY_KEY_HEADER)
+        ))
+}
+
+// The guest's key namespace, started on their first keyed request
+fn guest_token(session: &Session) -> Result<String> {
+    if let Some(token) = session.get::<String>(SESSION_GUEST_TOKEN_KEY)
+        .map_err(|_| AppError::SessionError)? {
+        return Ok(token);
+    }
+    let token = uuid::Uuid::new_v4().to_string();
+    session.insert(SESSION_GUEST_TOKEN_KEY, &token)
+        .map_err(|_| AppError::SessionError)?;
+    Ok(token)
+}
+
+// The stored response for a repeat of the same request
+fn replay(stored: StoredRequest, method: &str, path: &str, request_hash: &str) -> Result<HttpResponse> {
+    if stored.method != method || stored.path != path || stored.request_hash != request_hash {
+        return Err(AppError::BadRequest(format!(
+            "{} was already used for a different request", IDEMPOTENCY_KEY_HEADER
+        )));
+    }
+    
+    let status = stored.response_status
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .ok_or_else(|| AppError::Conflict(format!(
+            "A request with this {} is still being processed", IDEMPOTENCY_KEY_HEADER
+        )))?;
+    
+    let mut response = HttpResponse::build(status);
+    response.insert_header((REPLAYED_HEADER, "true"));
+    if let Some(content_type) = stored.response_content_type {
+        response.content_type(content_type);
+    }
+    Ok(response.body(stored.response_body.unwrap_or_default()))
+}
+
+// Free a claimed key so the request can be retried
+async fn release_key(db: &SqlitePool, claim_id: i64) {
+    if let Err(e) = sqlx::query("DELETE FROM idempotency_keys WHERE id = ?1")
+        .bind(claim_id)
+        .execute(db)
+        .await
+    {
+        log::error!("Failed to release idempotency key {}: {}", claim_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{cookie::Key, middleware::from_fn, test, App};
+    use super::*;
+    use crate::{payments::FakeProvider, storage::LocalStorage, tax::PricingMode, test_support::{session_cookie, TestDb}};
+    
+    async fn count(calls: web::Data<AtomicUsize>) -> HttpResponse {
+        let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+        HttpResponse::Created().json(serde_json::json!({ "call": n }))
+    }
+    
+    #[actix_web::test]
+    async fn repeated_requests_replay_the_first_response() {
+        let db = TestDb::new().await;
+        let state = web::Data::new(AppState {
+            db: db.pool.clone(),
+            pricing: PricingMode::Exclusive,
+            payments: Box::new(FakeProvider::new("secret".to_string())),
+            storage: Box::new(LocalStorage::new(std::env::temp_dir(), "/uploads")),
+        });
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(state)
+                .app_data(calls.clone())
+                .wrap(from_fn(idempotency))
+                .wrap(SessionMiddleware::builder(CookieSessionStore::default(), Key::generate()).cookie_secure(false).build())
+                .route("/things", web::post().to(count))
+        ).await;
+        
+        let request = |key: &str, body: Vec<u8>| test::TestRequest::post()
+            .uri("/things")
+            .insert_header((IDEMPOTENCY_KEY_HEADER, key.to_string()))
+            .set_payload(body);
+        
+        let first = test::call_service(&app, request("abc", b"{}".to_vec()).to_request()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        let guest = session_cookie(&first);
+        assert_eq!(test::read_body(first).await, r#"{"call":1}"#);
+        
+        let repeat = test::call_service(&app, request("abc", b"{}".to_vec()).cookie(guest.clone()).to_request()).await;
+        assert_eq!(repeat.status(), StatusCode::CREATED);
+        assert!(repeat.headers().contains_key(REPLAYED_HEADER));
+        assert_eq!(test::read_body(repeat).await, r#"{"call":1}"#);
+        
+        let changed = request("abc", br#"{"other":true}"#.to_vec()).cookie(guest.clone()).to_request();
+        assert_eq!(test::call_service(&app, changed).await.status(), StatusCode::BAD_REQUEST);
+        
+        // Another guest's key of the same name is their own
+        let other_guest = test::call_service(&app, request("abc", b"{}".to_vec()).to_request()).await;
+        assert_eq!(test::read_body(other_guest).await, r#"{"call":2}"#);
+        
+        let fresh = test::call_service(&app, request("def", b"{}".to_vec()).cookie(guest.clone()).to_request()).await;
+        assert_eq!(test::read_body(fresh).await, r#"{"call":3}"#);
+        
+        // Bodies larger than the default payload limit, such as image uploads, still go through
+        let large = test::call_service(&app, request("ghi", vec![b'x'; 1024 * 1024]).cookie(guest).to_request()).await;
+        assert_eq!(large.status(), StatusCode::CREATED);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        
+        let hashes: Vec<String> = sqlx::query_scalar("SELECT request_hash FROM idempotency_keys")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert!(hashes.iter().all(|hash| hash.len() == 64));
+    }
+}
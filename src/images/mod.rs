@@ -0,0 +1,119 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, DynamicImage, ImageFormat, ImageReader, Limits};
+use crate::errors::{Result, AppError};
+
+// Largest upload accepted, in bytes
+pub const MAX_IMAGE_BYTES: usize = 10 * 1024 * 1024;
+
+// Largest upload request: the image plus room for the other form fields
+pub const MAX_UPLOAD_REQUEST_BYTES: usize = MAX_IMAGE_BYTES + 64 * 1024;
+
+// Largest width or height accepted, so a small file can't decode into a huge bitmap
+pub const MAX_IMAGE_DIMENSION: u32 = 8000;
+
+// Sizes generated for every upload, by the length of the longest edge.
+// Images smaller than a size are kept at their own size, never enlarged.
+pub const RENDITIONS: [(&str, u32); 3] = [
+    ("thumbnail", 150),
+    ("medium", 600),
+    ("large", 1200),
+];
+
+// A checked upload and its renditions, encoded in the upload's own format
+pub struct ProcessedImage {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    pub renditions: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl ProcessedImage {
+    pub fn extension(&self) -> &'static str {
+        self.format.extensions_str()[0]
+    }
+    
+    pub fn content_type(&self) -> &'static str {
+        self.format.to_mime_type()
+    }
+}
+
+// Decode an upload and resize it into each rendition. The format is taken
+// from the bytes, not the client's content type; JPEG, PNG and WebP are
+// accepted. CPU bound, so call it off the async workers.
+pub fn process_image(bytes: &[u8]) -> Result<ProcessedImage> {
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|_| unreadable())?;
+    let format = match reader.format() {
+        Some(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => format,
+        _ => return Err(AppError::BadRequest("Images must be JPEG, PNG or WebP".to_string())),
+    };
+    
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|_| unreadable())?;
+    
+    let renditions = RENDITIONS.iter()
+        .map(|&(name, edge)| Ok((name, encode(&fit_within(&image, edge), format)?)))
+        .collect::<Result<_>>()?;
+    
+    Ok(ProcessedImage {
+        format,
+        width: image.width(),
+        height: image.height(),
+        renditions,
+    })
+}
+
+fn fit_within(image: &DynamicImage, edge: u32) -> DynamicImage {
+    if image.width() <= edge && image.height() <= edge {
+        image.clone()
+    } else {
+        image.resize(edge, edge, FilterType::Lanczos3)
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), format)
+        .map_err(|e| {
+            log::error!("Failed to encode {:?} rendition: {}", format, e);
+            AppError::InternalError
+        })?;
+    Ok(bytes)
+}
+
+fn unreadable() -> AppError {
+    AppError::BadRequest(format!(
+        "Image could not be read; it must be a valid JPEG, PNG or WebP file of at most {}x{} pixels",
+        MAX_IMAGE_DIMENSION, MAX_IMAGE_DIMENSION
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, RgbImage};
+    use super::*;
+    
+    #[test]
+    fn renditions_shrink_to_fit_without_enlarging() {
+        let mut upload = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(1000, 400))
+            .write_to(&mut Cursor::new(&mut upload), ImageFormat::Png)
+            .unwrap();
+        
+        let processed = process_image(&upload).unwrap();
+        assert_eq!((processed.width, processed.height), (1000, 400));
+        assert_eq!(processed.extension(), "png");
+        
+        let sizes: Vec<_> = processed.renditions.iter()
+            .map(|(name, bytes)| (*name, image::load_from_memory(bytes).unwrap().dimensions()))
+            .collect();
+        assert_eq!(sizes, [("thumbnail", (150, 60)), ("medium", (600, 240)), ("large", (1000, 400))]);
+        
+        assert!(matches!(process_image(b"not an image"), Err(AppError::BadRequest(_))));
+    }
+}
@@ -1,16 +1,31 @@
 mod errors;
 mod models;
 mod handlers;
+mod auth;
+mod tax;
+mod payments;
+mod storage;
+mod images;
+mod idempotency;
+mod request_id;
+mod openapi;
+mod api;
+#[cfg(test)]
+mod test_support;
 
 use actix_files::Files;
 use actix_session::{SessionMiddleware, storage::CookieSessionStore};
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use actix_web::{web, App, HttpServer, middleware::{from_fn, Logger}};
 use actix_web::cookie::Key;
 use sqlx::sqlite::SqlitePoolOptions;
 use std::env;
+use std::time::Duration;
 
 pub struct AppState {
     pub db: sqlx::SqlitePool,
+    pub pricing: tax::PricingMode,
+    pub payments: Box<dyn payments::PaymentProvider>,
+    pub storage: Box<dyn storage::Storage>,
 }
 
 #[actix_web::main]
@@ -43,8 +58,46 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to run migrations");
     
+    // A missing or placeholder password only skips the admin account; the
+    // shop still starts so it can be fixed in .env
+    if let Ok(email) = env::var("ADMIN_EMAIL") {
+        match auth::ensure_admin(&db_pool, &email, env::var("ADMIN_PASSWORD").ok().as_deref()).await {
+            Ok(()) => {},
+            Err(errors::AppError::BadRequest(message)) => log::error!("Not creating the admin user: {}", message),
+            Err(e) => panic!("Failed to create admin user: {}", e),
+        }
+    }
+    
     let app_state = web::Data::new(AppState {
         db: db_pool,
+        pricing: tax::PricingMode::from_env(),
+        payments: payments::provider_from_env(),
+        storage: storage::storage_from_env(),
+    });
+    
+    // Periodically drop abandoned guest carts and stale idempotency keys,
+    // and retry refunds the payment provider turned down
+    let cleanup = app_state.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            match handlers::cart::expire_guest_carts(&cleanup.db).await {
+                Ok(0) => {},
+                Ok(n) => log::info!("Expired {} guest carts", n),
+                Err(e) => log::error!("Failed to expire guest carts: {}", e),
+            }
+            match idempotency::expire_idempotency_keys(&cleanup.db).await {
+                Ok(0) => {},
+                Ok(n) => log::info!("Expired {} idempotency keys", n),
+                Err(e) => log::error!("Failed to expire idempotency keys: {}", e),
+            }
+            match handlers::payments::finish_refunds(&cleanup.db, cleanup.payments.as_ref(), None).await {
+                Ok(0) => {},
+                Ok(n) => log::info!("Made {} pending refunds", n),
+                Err(e) => log::error!("Failed to make pending refunds: {}", e),
+            }
+        }
     });
     
     // Generate a secure random key if not provided in environment
@@ -60,12 +113,19 @@ async fn main() -> std::io::Result<()> {
         Key::generate()
     };
     
+    // Created up front so the file service below has a directory to serve
+    let (upload_dir, upload_url) = storage::upload_location();
+    std::fs::create_dir_all(&upload_dir)?;
+    
     log::info!("Starting server at http://{}:{}", server_host, server_port);
     
     HttpServer::new(move || {
         App::new()
             .app_data(app_state.clone())
-            .wrap(Logger::default())
+            .configure(api::extractor_config)
+            // Inside the session middleware so keys can be scoped to the signed-in user
+            .wrap(from_fn(idempotency::idempotency))
+            .wrap(Logger::new(r#"%a "%r" %s %b %{X-Request-Id}i %T"#))
             .wrap(
                 SessionMiddleware::builder(
                     CookieSessionStore::default(),
@@ -74,36 +134,23 @@ async fn main() -> std::io::Result<()> {
                 .cookie_secure(false)
                 .build()
             )
+            // Outermost, so every response and error carries the id
+            .wrap(from_fn(request_id::request_id))
+            // Ahead of /static, which would otherwise answer for the default /static/uploads
+            .service(Files::new(&upload_url, &upload_dir))
             .service(Files::new("/static", "./static"))
             // Pages
             .route("/", web::get().to(handlers::index))
             .route("/store", web::get().to(handlers::store_page))
             .route("/admin", web::get().to(handlers::admin_page))
             .route("/cart", web::get().to(handlers::cart_page))
-            // API Routes - Products
-            .route("/api/products", web::get().to(handlers::products::get_products))
-            .route("/api/products", web::post().to(handlers::products::create_product))
-            .route("/api/products/search", web::get().to(handlers::products::search_products))
-            .route("/api/products/{id}", web::get().to(handlers::products::get_product))
-            .route("/api/products/{id}", web::put().to(handlers::products::update_product))
-            .route("/api/products/{id}", web::delete().to(handlers::products::delete_product))
-            // API Routes - Categories
-            .route("/api/categories", web::get().to(handlers::categories::get_categories))
-            .route("/api/categories", web::post().to(handlers::categories::create_category))
-            .route("/api/categories/{id}", web::put().to(handlers::categories::update_category))
-            .route("/api/categories/{id}", web::delete().to(handlers::categories::delete_category))
-            .route("/api/categories/{id}/products", web::get().to(handlers::categories::get_category_products))
-            // API Routes - Cart
-            .route("/api/cart", web::get().to(handlers::cart::get_cart))
-            .route("/api/cart", web::post().to(handlers::cart::add_to_cart))
-            .route("/api/cart/clear", web::post().to(handlers::cart::clear_cart))
-            .route("/api/cart/{id}", web::put().to(handlers::cart::update_cart_item))
-            .route("/api/cart/{id}", web::delete().to(handlers::cart::remove_from_cart))
-            // Add these routes after the cart routes in main.rs
-            // API Routes - Orders
-            .route("/api/orders", web::post().to(handlers::orders::create_order))
-            .route("/api/orders", web::get().to(handlers::orders::get_orders))
-            .route("/api/orders/{id}", web::get().to(handlers::orders::get_order))
+            .route("/login", web::get().to(handlers::login_page))
+            // API, one scope per version
+            .service(web::scope("/api/v1").configure(api::v1))
+            .service(web::scope("/api/v2").configure(api::v2))
+            // Unversioned paths from before /api/v1, kept as aliases of v1
+            .service(web::scope("/api").configure(api::v1))
+            .default_service(web::to(handlers::not_found))
     })
     .bind((server_host, server_port))?
     .run()
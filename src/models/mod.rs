@@ -1,8 +1,18 @@
 use chrono::{DateTime, Utc, NaiveDateTime};
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationErrors};
+use crate::errors::AppError;
+
+mod countries;
+mod validation;
+pub use countries::is_country_code;
+pub use validation::{line_quantity, new_line_quantity, new_password, not_blank, password_length, valid_price, MAX_LINE_QUANTITY};
 
 // Custom type for SQLite datetime handling
+#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SqliteDateTime(pub DateTime<Utc>);
 
@@ -14,113 +24,988 @@ impl From<String> for SqliteDateTime {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+// Money is stored in SQLite as integer minor units (cents) and handled as Decimal everywhere else
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct Cents(pub i64);
+
+impl From<Cents> for Decimal {
+    fn from(cents: Cents) -> Self {
+        Decimal::new(cents.0, 2)
+    }
+}
+
+impl TryFrom<Decimal> for Cents {
+    type Error = AppError;
+    
+    fn try_from(amount: Decimal) -> Result<Self, Self::Error> {
+        if amount.normalize().scale() > 2 {
+            return Err(AppError::BadRequest(
+                format!("Amount {} has more than 2 decimal places", amount)
+            ));
+        }
+        (amount * Decimal::ONE_HUNDRED)
+            .to_i64()
+            .map(Cents)
+            .ok_or_else(|| AppError::BadRequest(format!("Amount {} is out of range", amount)))
+    }
+}
+
+// Percentages are stored in SQLite as decimal text so rates like 8.875 stay exact
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct DecimalText(pub String);
+
+impl TryFrom<DecimalText> for Decimal {
+    type Error = rust_decimal::Error;
+    
+    fn try_from(text: DecimalText) -> Result<Self, Self::Error> {
+        text.0.parse()
+    }
+}
+
+impl From<Decimal> for DecimalText {
+    fn from(value: Decimal) -> Self {
+        DecimalText(value.normalize().to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Category {
     pub id: i32,
     pub name: String,
     pub description: Option<String>,
+    pub parent_id: Option<i32>,
+    pub tax_class_id: Option<i64>,
+    // Set while the category is archived
+    pub deleted_at: Option<String>,
     #[sqlx(rename = "created_at")]
     pub created_at: String,
     #[sqlx(rename = "updated_at")]
     pub updated_at: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+// Only published products are shown in the storefront and can be bought
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ProductStatus {
+    Draft,
+    #[default]
+    Published,
+    Archived,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Product {
     pub id: i32,
     pub name: String,
     pub description: Option<String>,
-    pub price: f64,  // SQLite uses REAL, which maps to f64
+    #[sqlx(rename = "price_cents", try_from = "Cents")]
+    pub price: Decimal,
     pub stock_quantity: i32,
     pub category_id: Option<i32>,
     pub image_url: Option<String>,
+    pub weight_grams: i32,
+    pub status: ProductStatus,
+    // Set while the product is archived
+    pub deleted_at: Option<String>,
     #[sqlx(rename = "created_at")]
     pub created_at: String,
     #[sqlx(rename = "updated_at")]
     pub updated_at: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// One page of a listing, with links to the neighbouring pages
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+    pub total_pages: i64,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct OptionType {
+    pub id: i64,
+    pub name: String,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct OptionValue {
+    pub id: i64,
+    pub option_type_id: i64,
+    pub value: String,
+    pub position: i32,
+}
+
+// An option value as attached to a variant, e.g. Size: M
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct VariantOption {
+    pub option_value_id: i64,
+    pub option_type: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProductVariant {
+    pub id: i64,
+    pub product_id: i32,
+    pub sku: String,
+    // Overrides the product price when set
+    pub price: Option<Decimal>,
+    pub stock_quantity: i32,
+    pub options: Vec<VariantOption>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+// Written by hand because the nullable price override can't go through `try_from`
+impl<'r> FromRow<'r, SqliteRow> for ProductVariant {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(ProductVariant {
+            id: row.try_get("id")?,
+            product_id: row.try_get("product_id")?,
+            sku: row.try_get("sku")?,
+            price: row.try_get::<Option<Cents>, _>("price_cents")?.map(Decimal::from),
+            stock_quantity: row.try_get("stock_quantity")?,
+            options: Vec::new(),
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+// An uploaded product image. `urls` is filled in from the storage backend
+// after loading.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ProductImage {
+    pub id: i64,
+    pub product_id: i32,
+    pub position: i32,
+    #[serde(skip)]
+    pub storage_key: String,
+    #[serde(skip)]
+    pub extension: String,
+    pub content_type: String,
+    pub width: i32,
+    pub height: i32,
+    pub alt_text: Option<String>,
+    #[sqlx(skip)]
+    pub urls: ImageUrls,
+    pub created_at: String,
+}
+
+// Where each size of an image can be fetched
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImageUrls {
+    pub original: String,
+    pub thumbnail: String,
+    pub medium: String,
+    pub large: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReorderImages {
+    // Every image of the product, first to last
+    pub image_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    pub slug: String,
+    pub created_at: String,
+}
+
+// A tag with the number of products carrying it, for filter facets
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct TagCount {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub tag: Tag,
+    pub product_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct SetProductTags {
+    // Tag names; tags that don't exist yet are created
+    #[validate(length(max = 30, message = "must have at most 30 tags"), custom(function = "validation::tag_names"))]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SetProductCategories {
+    // The first becomes the product's main category (`category_id`); none
+    // clears it, leaving the product in the standard tax class
+    pub category_ids: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CartItem {
     pub product_id: i32,
+    pub variant_id: Option<i64>,
     pub quantity: i32,
     pub product: Option<Product>,
+    pub variant: Option<ProductVariant>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl CartItem {
+    // Unit price once product details are loaded; a variant's override wins
+    pub fn unit_price(&self) -> Option<Decimal> {
+        let product = self.product.as_ref()?;
+        Some(self.variant.as_ref().and_then(|v| v.price).unwrap_or(product.price))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Cart {
     pub items: Vec<CartItem>,
+    pub coupon_code: Option<String>,
 }
 
 #[allow(dead_code)]
 impl Cart {
     pub fn new() -> Self {
-        Self { items: Vec::new() }
+        Self { items: Vec::new(), coupon_code: None }
     }
     
-    pub fn add_item(&mut self, product_id: i32, quantity: i32) {
-        if let Some(item) = self.items.iter_mut().find(|i| i.product_id == product_id) {
-            item.quantity += quantity;
+    // Adding to an existing line stops at MAX_LINE_QUANTITY
+    pub fn add_item(&mut self, product_id: i32, variant_id: Option<i64>, quantity: i32) {
+        if let Some(item) = self.find_item(product_id, variant_id) {
+            item.quantity = item.quantity.saturating_add(quantity).min(MAX_LINE_QUANTITY);
         } else {
             self.items.push(CartItem {
                 product_id,
+                variant_id,
                 quantity,
                 product: None,
+                variant: None,
             });
         }
     }
     
-    pub fn remove_item(&mut self, product_id: i32) {
-        self.items.retain(|i| i.product_id != product_id);
+    // How many of a product (or variant) the cart holds
+    pub fn quantity_of(&self, product_id: i32, variant_id: Option<i64>) -> i32 {
+        self.items.iter()
+            .find(|i| i.product_id == product_id && i.variant_id == variant_id)
+            .map_or(0, |i| i.quantity)
     }
     
-    pub fn update_quantity(&mut self, product_id: i32, quantity: i32) {
+    pub fn remove_item(&mut self, product_id: i32, variant_id: Option<i64>) {
+        self.items.retain(|i| !(i.product_id == product_id && i.variant_id == variant_id));
+    }
+    
+    pub fn update_quantity(&mut self, product_id: i32, variant_id: Option<i64>, quantity: i32) {
         if quantity <= 0 {
-            self.remove_item(product_id);
-        } else if let Some(item) = self.items.iter_mut().find(|i| i.product_id == product_id) {
+            self.remove_item(product_id, variant_id);
+        } else if let Some(item) = self.find_item(product_id, variant_id) {
             item.quantity = quantity;
         }
     }
     
     pub fn clear(&mut self) {
         self.items.clear();
+        self.coupon_code = None;
     }
     
-    pub fn total_with_products(&self) -> f64 {
+    pub fn total_with_products(&self) -> Decimal {
         self.items.iter()
             .filter_map(|item| {
-                item.product.as_ref().map(|p| p.price * item.quantity as f64)
+                item.unit_price().map(|price| price * Decimal::from(item.quantity))
             })
             .sum()
     }
+    
+    fn find_item(&mut self, product_id: i32, variant_id: Option<i64>) -> Option<&mut CartItem> {
+        self.items.iter_mut().find(|i| i.product_id == product_id && i.variant_id == variant_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Processing,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Processing => "processing",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+    
+    // Allowed lifecycle moves; cancelled and refunded are terminal
+    pub fn can_transition_to(&self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Paid) | (Pending, Cancelled)
+                | (Paid, Processing) | (Paid, Cancelled) | (Paid, Refunded)
+                | (Processing, Shipped) | (Processing, Cancelled) | (Processing, Refunded)
+                | (Shipped, Delivered)
+                | (Delivered, Refunded)
+        )
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+impl std::fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Order {
     pub id: i64,
-    pub total_amount: f64,
-    pub status: String,
+    #[sqlx(rename = "total_cents", try_from = "Cents")]
+    pub total_amount: Decimal,
+    pub status: OrderStatus,
     pub customer_name: String,
     pub customer_email: String,
     pub shipping_address: String,
+    pub customer_id: Option<i64>,
+    #[sqlx(rename = "discount_cents", try_from = "Cents")]
+    pub discount_amount: Decimal,
+    pub coupon_code: Option<String>,
+    pub shipping_country: Option<String>,
+    pub shipping_region: Option<String>,
+    pub prices_include_tax: bool,
+    // Goods net of tax and discount;
+    // total_amount = subtotal_amount + tax_amount + shipping_amount
+    #[sqlx(rename = "subtotal_cents", try_from = "Cents")]
+    pub subtotal_amount: Decimal,
+    #[sqlx(rename = "tax_cents", try_from = "Cents")]
+    pub tax_amount: Decimal,
+    pub shipping_method_id: Option<i64>,
+    pub shipping_method: Option<String>,
+    #[sqlx(rename = "shipping_cents", try_from = "Cents")]
+    pub shipping_amount: Decimal,
     #[sqlx(rename = "created_at")]
     pub created_at: String,
     #[sqlx(rename = "updated_at")]
     pub updated_at: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct CreateProduct {
+    #[validate(custom(function = "not_blank"), length(max = 200, message = "must be at most 200 characters"))]
     pub name: String,
+    #[validate(length(max = 5000, message = "must be at most 5000 characters"))]
     pub description: Option<String>,
-    pub price: f64,
+    #[validate(custom(function = "valid_price"))]
+    pub price: Decimal,
+    #[validate(range(min = 0, message = "must not be negative"))]
     pub stock_quantity: i32,
+    #[validate(range(min = 1, message = "must be a category id"))]
     pub category_id: Option<i32>,
+    #[validate(custom(function = "validation::image_url"))]
     pub image_url: Option<String>,
+    #[serde(default)]
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub weight_grams: i32,
+    // New products are published unless given; updates keep the current status
+    pub status: Option<ProductStatus>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct CreateCategory {
+    #[validate(custom(function = "not_blank"), length(max = 100, message = "must be at most 100 characters"))]
     pub name: String,
+    #[validate(length(max = 2000, message = "must be at most 2000 characters"))]
     pub description: Option<String>,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub tax_class_id: Option<i64>,
+    // Leave out for a top-level category
+    #[serde(default)]
+    #[validate(range(min = 1, message = "must be a category id"))]
+    pub parent_id: Option<i32>,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum UserRole {
+    Admin,
+    Customer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub email: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub role: UserRole,
+    pub name: Option<String>,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+    #[sqlx(rename = "updated_at")]
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct OrderStatusChange {
+    pub id: i64,
+    pub order_id: i64,
+    pub from_status: Option<OrderStatus>,
+    pub to_status: OrderStatus,
+    pub changed_by: Option<i64>,
+    pub note: Option<String>,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateOptionType {
+    #[validate(custom(function = "not_blank"), length(max = 100, message = "must be at most 100 characters"))]
+    pub name: String,
+    #[serde(default)]
+    #[validate(custom(function = "validation::option_values"))]
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateOptionValue {
+    #[validate(custom(function = "not_blank"), length(max = 100, message = "must be at most 100 characters"))]
+    pub value: String,
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateVariant {
+    #[validate(custom(function = "not_blank"), length(max = 64, message = "must be at most 64 characters"))]
+    pub sku: String,
+    #[validate(custom(function = "valid_price"))]
+    pub price: Option<Decimal>,
+    #[validate(range(min = 0, message = "must not be negative"))]
+    pub stock_quantity: i32,
+    #[serde(default)]
+    pub option_value_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum CouponKind {
+    Percentage,
+    Fixed,
+    FreeShipping,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Coupon {
+    pub id: i64,
+    pub code: String,
+    pub kind: CouponKind,
+    pub percent_off: Option<i64>,
+    pub amount_off: Option<Decimal>,
+    pub min_order: Decimal,
+    pub expires_at: Option<String>,
+    pub max_uses: Option<i64>,
+    pub max_uses_per_customer: Option<i64>,
+    pub active: bool,
+    pub times_used: i64,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl Coupon {
+    // Amount taken off a subtotal; never more than the subtotal itself.
+    // Free shipping coupons discount the shipping charge, not the goods.
+    pub fn discount_for(&self, subtotal: Decimal) -> Decimal {
+        let discount = match self.kind {
+            CouponKind::Percentage => {
+                let percent = Decimal::from(self.percent_off.unwrap_or(0));
+                (subtotal * percent / Decimal::ONE_HUNDRED)
+                    .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+            },
+            CouponKind::Fixed => self.amount_off.unwrap_or(Decimal::ZERO),
+            CouponKind::FreeShipping => Decimal::ZERO,
+        };
+        discount.min(subtotal)
+    }
+}
+
+// Written by hand for the nullable fixed amount, like `ProductVariant`
+impl<'r> FromRow<'r, SqliteRow> for Coupon {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Coupon {
+            id: row.try_get("id")?,
+            code: row.try_get("code")?,
+            kind: row.try_get("kind")?,
+            percent_off: row.try_get("percent_off")?,
+            amount_off: row.try_get::<Option<Cents>, _>("amount_off_cents")?.map(Decimal::from),
+            min_order: row.try_get::<Cents, _>("min_order_cents")?.into(),
+            expires_at: row.try_get("expires_at")?,
+            max_uses: row.try_get("max_uses")?,
+            max_uses_per_customer: row.try_get("max_uses_per_customer")?,
+            active: row.try_get("active")?,
+            times_used: row.try_get("times_used")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateCoupon {
+    #[validate(custom(function = "not_blank"), length(max = 50, message = "must be at most 50 characters"))]
+    pub code: String,
+    pub kind: CouponKind,
+    #[validate(range(min = 1, max = 100, message = "must be between 1 and 100"))]
+    pub percent_off: Option<i64>,
+    #[validate(custom(function = "valid_price"))]
+    pub amount_off: Option<Decimal>,
+    #[validate(custom(function = "valid_price"))]
+    pub min_order: Option<Decimal>,
+    // UTC, e.g. "2025-12-31T23:59:59"
+    pub expires_at: Option<NaiveDateTime>,
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub max_uses: Option<i64>,
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    pub max_uses_per_customer: Option<i64>,
+    #[serde(default = "default_true")]
+    pub active: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct TaxClass {
+    pub id: i64,
+    pub name: String,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct TaxRate {
+    pub id: i64,
+    pub tax_class_id: i64,
+    pub country: String,
+    pub region: Option<String>,
+    pub name: String,
+    // Percentage, e.g. 20 for 20%
+    #[sqlx(try_from = "DecimalText")]
+    pub rate: Decimal,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+    #[sqlx(rename = "updated_at")]
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateTaxClass {
+    #[validate(custom(function = "not_blank"), length(max = 100, message = "must be at most 100 characters"))]
+    pub name: String,
+}
+
+// Checked once the country is uppercased and a blank region dropped
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateTaxRate {
+    pub tax_class_id: i64,
+    #[validate(custom(function = "validation::country_code"))]
+    pub country: String,
+    #[validate(length(max = 100, message = "must be at most 100 characters"))]
+    pub region: Option<String>,
+    #[validate(custom(function = "not_blank"), length(max = 100, message = "must be at most 100 characters"))]
+    pub name: String,
+    #[validate(custom(function = "validation::percentage"))]
+    pub rate: Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ShippingKind {
+    Flat,
+    WeightBased,
+    FreeOver,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ShippingMethod {
+    pub id: i64,
+    pub name: String,
+    pub kind: ShippingKind,
+    #[sqlx(rename = "rate_cents", try_from = "Cents")]
+    pub rate: Decimal,
+    #[sqlx(rename = "per_kg_cents", try_from = "Cents")]
+    pub per_kg: Decimal,
+    #[sqlx(rename = "threshold_cents", try_from = "Cents")]
+    pub threshold: Decimal,
+    pub active: bool,
+    pub position: i32,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+    #[sqlx(rename = "updated_at")]
+    pub updated_at: String,
+}
+
+impl ShippingMethod {
+    // Price for goods worth `goods_amount` weighing `weight_grams`, or None
+    // when the method isn't offered for them
+    pub fn cost_for(&self, goods_amount: Decimal, weight_grams: i64) -> Option<Decimal> {
+        match self.kind {
+            ShippingKind::Flat => Some(self.rate),
+            ShippingKind::WeightBased => {
+                let started_kgs = (weight_grams.max(0) + 999) / 1000;
+                Some(self.rate + self.per_kg * Decimal::from(started_kgs))
+            },
+            ShippingKind::FreeOver => (goods_amount >= self.threshold).then_some(Decimal::ZERO),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateShippingMethod {
+    #[validate(custom(function = "not_blank"), length(max = 100, message = "must be at most 100 characters"))]
+    pub name: String,
+    pub kind: ShippingKind,
+    #[serde(default)]
+    #[validate(custom(function = "valid_price"))]
+    pub rate: Decimal,
+    #[serde(default)]
+    #[validate(custom(function = "valid_price"))]
+    pub per_kg: Decimal,
+    #[serde(default)]
+    #[validate(custom(function = "valid_price"))]
+    pub threshold: Decimal,
+    #[serde(default = "default_true")]
+    pub active: bool,
+    #[serde(default)]
+    pub position: i32,
+}
+
+// A postal address. Country is an ISO 3166-1 alpha-2 code.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Address {
+    pub name: String,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: String,
+    pub phone: Option<String>,
+}
+
+impl Address {
+    // Trim and check every field, reporting every problem under its field's
+    // name. Callers nest the errors under the address's own field.
+    pub fn validated(self) -> Result<Address, ValidationErrors> {
+        let trim = |value: String| value.trim().to_string();
+        let present = |value: Option<String>| value.map(trim).filter(|v| !v.is_empty());
+        let address = Address {
+            name: trim(self.name),
+            line1: trim(self.line1),
+            line2: present(self.line2),
+            city: trim(self.city),
+            region: present(self.region),
+            postal_code: present(self.postal_code),
+            country: self.country.trim().to_uppercase(),
+            phone: present(self.phone),
+        };
+        
+        let mut errors = ValidationErrors::new();
+        let mut check = |field: &'static str, ok: bool, code: &'static str, message: &'static str| {
+            if !ok {
+                errors.add(field, validation::error(code, message));
+            }
+        };
+        
+        for (field, value) in [("name", &address.name), ("line1", &address.line1), ("city", &address.city)] {
+            check(field, !value.is_empty(), "required", "is required");
+        }
+        let text = [
+            ("name", Some(&address.name)),
+            ("line1", Some(&address.line1)),
+            ("line2", address.line2.as_ref()),
+            ("city", Some(&address.city)),
+            ("region", address.region.as_ref()),
+        ];
+        for (field, value) in text {
+            check(field, value.is_none_or(|v| v.chars().count() <= 200), "length", "must be at most 200 characters");
+        }
+        
+        check(
+            "postal_code",
+            address.postal_code.as_ref().is_none_or(|p| {
+                p.len() <= 16 && p.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-')
+            }),
+            "postal_code",
+            "is not a valid postal code",
+        );
+        check(
+            "country",
+            is_country_code(&address.country),
+            "country",
+            "must be an ISO 3166-1 alpha-2 code",
+        );
+        check(
+            "phone",
+            address.phone.as_ref().is_none_or(|p| {
+                let digits = p.chars().filter(char::is_ascii_digit).count();
+                (6..=15).contains(&digits) && p.chars().all(|c| c.is_ascii_digit() || " +-().".contains(c))
+            }),
+            "phone",
+            "is not a valid phone number",
+        );
+        
+        if errors.is_empty() { Ok(address) } else { Err(errors) }
+    }
+    
+    // Single-line form for display
+    pub fn formatted(&self) -> String {
+        let locality = [self.region.as_deref(), self.postal_code.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        [
+            Some(self.name.as_str()),
+            Some(self.line1.as_str()),
+            self.line2.as_deref(),
+            Some(self.city.as_str()),
+            Some(locality.as_str()).filter(|l| !l.is_empty()),
+            Some(self.country.as_str()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum AddressKind {
+    Shipping,
+    Billing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct OrderAddress {
+    pub order_id: i64,
+    pub kind: AddressKind,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub address: Address,
+}
+
+// An entry in a customer's address book
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct SavedAddress {
+    pub id: i64,
+    pub customer_id: i64,
+    pub label: Option<String>,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub address: Address,
+    pub is_default: bool,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+    #[sqlx(rename = "updated_at")]
+    pub updated_at: String,
+}
+
+// The address itself is checked by `Address::validated`
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct CreateSavedAddress {
+    #[validate(length(max = 100, message = "must be at most 100 characters"))]
+    pub label: Option<String>,
+    #[serde(flatten)]
+    pub address: Address,
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Pending,
+    Captured,
+    Failed,
+    // The order was cancelled or refunded; the provider refund is still to be made
+    RefundPending,
+    Refunded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Payment {
+    pub id: i64,
+    pub order_id: i64,
+    pub provider: String,
+    pub provider_reference: String,
+    // Handed to the shopper's browser to confirm the payment with the provider
+    pub client_secret: Option<String>,
+    pub status: PaymentStatus,
+    #[sqlx(rename = "amount_cents", try_from = "Cents")]
+    pub amount: Decimal,
+    #[sqlx(rename = "refunded_cents", try_from = "Cents")]
+    pub refunded_amount: Decimal,
+    pub failure_reason: Option<String>,
+    #[sqlx(rename = "created_at")]
+    pub created_at: String,
+    #[sqlx(rename = "updated_at")]
+    pub updated_at: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn order_status_transitions_follow_the_lifecycle() {
+        use OrderStatus::*;
+        let all = [Pending, Paid, Processing, Shipped, Delivered, Cancelled, Refunded];
+        let allowed: &[(OrderStatus, &[OrderStatus])] = &[
+            (Pending, &[Paid, Cancelled]),
+            (Paid, &[Processing, Cancelled, Refunded]),
+            (Processing, &[Shipped, Cancelled, Refunded]),
+            (Shipped, &[Delivered]),
+            (Delivered, &[Refunded]),
+            (Cancelled, &[]),
+            (Refunded, &[]),
+        ];
+        for (from, targets) in allowed {
+            for to in all {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    targets.contains(&to),
+                    "{} -> {}", from, to
+                );
+            }
+        }
+    }
+    
+    #[test]
+    fn cart_lines_stop_at_the_maximum_quantity() {
+        let mut cart = Cart::new();
+        cart.add_item(1, None, MAX_LINE_QUANTITY - 1);
+        cart.add_item(1, None, 5);
+        cart.add_item(1, Some(7), 2);
+        let lines: Vec<_> = cart.items.iter().map(|i| (i.variant_id, i.quantity)).collect();
+        assert_eq!(lines, [(None, MAX_LINE_QUANTITY), (Some(7), 2)]);
+    }
+    
+    #[test]
+    fn shipping_methods_price_by_kind() {
+        let method = |kind: ShippingKind| ShippingMethod {
+            id: 1,
+            name: "Standard".to_string(),
+            kind,
+            rate: Decimal::new(500, 2),
+            per_kg: Decimal::new(150, 2),
+            threshold: Decimal::new(5000, 2),
+            active: true,
+            position: 0,
+            created_at: String::new(),
+            updated_at: String::new(),
+        };
+        let dollars = |d: i64| Decimal::new(d * 100, 2);
+        
+        let flat = method(ShippingKind::Flat);
+        assert_eq!(flat.cost_for(dollars(0), 0), Some(Decimal::new(500, 2)));
+        assert_eq!(flat.cost_for(dollars(200), 25_000), Some(Decimal::new(500, 2)));
+        
+        // The base rate plus the per-kg rate for every started kilogram
+        let weight_based = method(ShippingKind::WeightBased);
+        assert_eq!(weight_based.cost_for(dollars(10), 0), Some(Decimal::new(500, 2)));
+        assert_eq!(weight_based.cost_for(dollars(10), 1), Some(Decimal::new(650, 2)));
+        assert_eq!(weight_based.cost_for(dollars(10), 1000), Some(Decimal::new(650, 2)));
+        assert_eq!(weight_based.cost_for(dollars(10), 1001), Some(Decimal::new(800, 2)));
+        assert_eq!(weight_based.cost_for(dollars(10), -50), Some(Decimal::new(500, 2)));
+        
+        // Free from the threshold up, and not offered below it
+        let free_over = method(ShippingKind::FreeOver);
+        assert_eq!(free_over.cost_for(Decimal::new(4999, 2), 0), None);
+        assert_eq!(free_over.cost_for(Decimal::new(5000, 2), 0), Some(Decimal::ZERO));
+        assert_eq!(free_over.cost_for(Decimal::new(5001, 2), 0), Some(Decimal::ZERO));
+    }
+    
+    #[test]
+    fn addresses_are_trimmed_and_checked() {
+        let address = || Address {
+            name: " Ann Example ".to_string(),
+            line1: "1 Test Street".to_string(),
+            line2: Some("  ".to_string()),
+            city: "Testville".to_string(),
+            region: None,
+            postal_code: Some("AB1 2CD".to_string()),
+            country: " gb".to_string(),
+            phone: Some("+44 (0)20 7946 0000".to_string()),
+        };
+        // Every problem, as (field, message), sorted by field
+        let problems = |address: Address| {
+            let errors = address.validated().unwrap_err();
+            let mut problems: Vec<(String, String)> = errors.field_errors()
+                .into_iter()
+                .flat_map(|(field, errors)| errors.iter().map(move |e| {
+                    (field.to_string(), e.message.as_deref().unwrap_or_default().to_string())
+                }))
+                .collect();
+            problems.sort();
+            problems
+        };
+        let problem = |field: &str, message: &str| vec![(field.to_string(), message.to_string())];
+        
+        let valid = address().validated().unwrap();
+        assert_eq!((valid.name.as_str(), valid.line2, valid.country.as_str()), ("Ann Example", None, "GB"));
+        
+        assert_eq!(problems(Address { name: "   ".to_string(), ..address() }), problem("name", "is required"));
+        assert_eq!(problems(Address { city: "x".repeat(201), ..address() }), problem("city", "must be at most 200 characters"));
+        assert_eq!(
+            problems(Address { postal_code: Some("AB1_2CD".to_string()), ..address() }),
+            problem("postal_code", "is not a valid postal code"),
+        );
+        assert_eq!(problems(Address { phone: Some("12345".to_string()), ..address() }), problem("phone", "is not a valid phone number"));
+        for country in ["", "UK", "GBR", "xx"] {
+            assert_eq!(
+                problems(Address { country: country.to_string(), ..address() }),
+                problem("country", "must be an ISO 3166-1 alpha-2 code"),
+            );
+        }
+        
+        // Problems in several fields are all reported
+        let blank = Address { line1: String::new(), city: " ".to_string(), country: "ZZ".to_string(), ..address() };
+        assert_eq!(
+            problems(blank),
+            [
+                ("city".to_string(), "is required".to_string()),
+                ("country".to_string(), "must be an ISO 3166-1 alpha-2 code".to_string()),
+                ("line1".to_string(), "is required".to_string()),
+            ],
+        );
+    }
+    
+    #[test]
+    fn country_codes_are_sorted_for_lookup() {
+        assert!(countries::COUNTRY_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(countries::COUNTRY_CODES.iter().all(|code| is_country_code(code)));
+        assert!(!is_country_code("us"));
+    }
+}
@@ -0,0 +1,123 @@
+use rust_decimal::Decimal;
+use validator::ValidationError;
+
+// Largest quantity of one line a cart will hold
+pub const MAX_LINE_QUANTITY: i32 = 999;
+
+// A quantity to add to a cart line, 1 to MAX_LINE_QUANTITY
+pub fn line_quantity(quantity: i32) -> Result<(), ValidationError> {
+    quantity_in_range(quantity, 1)
+}
+
+// A cart line's new quantity, 0 to MAX_LINE_QUANTITY; 0 removes the line
+pub fn new_line_quantity(quantity: i32) -> Result<(), ValidationError> {
+    quantity_in_range(quantity, 0)
+}
+
+fn quantity_in_range(quantity: i32, min: i32) -> Result<(), ValidationError> {
+    if !(min..=MAX_LINE_QUANTITY).contains(&quantity) {
+        let message = format!("must be between {} and {}", min, MAX_LINE_QUANTITY);
+        return Err(ValidationError::new("range").with_message(message.into()));
+    }
+    Ok(())
+}
+
+// Largest price accepted for a product or variant
+pub const MAX_PRICE: Decimal = Decimal::from_parts(100_000_000, 0, 0, false, 2);
+
+// Rejects empty and whitespace-only text
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(error("blank", "must not be blank"));
+    }
+    Ok(())
+}
+
+// A price in whole cents between zero and MAX_PRICE
+pub fn valid_price(price: &Decimal) -> Result<(), ValidationError> {
+    if price.is_sign_negative() && !price.is_zero() {
+        return Err(error("range", "must not be negative"));
+    }
+    if *price > MAX_PRICE {
+        return Err(error("range", "must be at most 1000000.00"));
+    }
+    if price.normalize().scale() > 2 {
+        return Err(error("precision", "must have at most 2 decimal places"));
+    }
+    Ok(())
+}
+
+// A tax rate as a percentage, 0 to 100
+pub fn percentage(rate: &Decimal) -> Result<(), ValidationError> {
+    if *rate < Decimal::ZERO || *rate > Decimal::ONE_HUNDRED {
+        return Err(error("range", "must be a percentage between 0 and 100"));
+    }
+    Ok(())
+}
+
+// An uppercase ISO 3166-1 alpha-2 code
+pub fn country_code(code: &str) -> Result<(), ValidationError> {
+    if !super::is_country_code(code) {
+        return Err(error("country", "must be an ISO 3166-1 alpha-2 code"));
+    }
+    Ok(())
+}
+
+// Option values of 1 to 100 characters
+pub fn option_values(values: &[String]) -> Result<(), ValidationError> {
+    for value in values {
+        not_blank(value).map_err(|_| error("blank", "each value must not be blank"))?;
+        if value.trim().chars().count() > 100 {
+            return Err(error("length", "each value must be at most 100 characters"));
+        }
+    }
+    Ok(())
+}
+
+// Absolute http(s) URLs, or paths under this site such as /static/...
+pub fn image_url(url: &str) -> Result<(), ValidationError> {
+    let url = url.trim();
+    let ok = url.starts_with('/')
+        || ((url.starts_with("https://") || url.starts_with("http://")) && validator::ValidateUrl::validate_url(&url));
+    if !ok {
+        return Err(error("url", "must be an http(s) URL or a path starting with /"));
+    }
+    Ok(())
+}
+
+// Tag names of 1 to 50 characters with at least one letter or digit
+pub fn tag_names(names: &[String]) -> Result<(), ValidationError> {
+    for name in names {
+        let name = name.trim();
+        if name.chars().count() > 50 {
+            return Err(error("length", "each tag must be at most 50 characters"));
+        }
+        if !name.chars().any(char::is_alphanumeric) {
+            return Err(error("tag", "each tag must contain a letter or digit"));
+        }
+    }
+    Ok(())
+}
+
+// bcrypt ignores everything past a password's first 72 bytes
+const MAX_PASSWORD_BYTES: usize = 72;
+
+// A password being chosen: at least 8 characters and at most 72 bytes
+pub fn new_password(password: &str) -> Result<(), ValidationError> {
+    if password.chars().count() < 8 {
+        return Err(error("length", "must be at least 8 characters"));
+    }
+    password_length(password)
+}
+
+// A password given at sign-in; only its length is checked
+pub fn password_length(password: &str) -> Result<(), ValidationError> {
+    if password.len() > MAX_PASSWORD_BYTES {
+        return Err(error("length", "must be at most 72 bytes"));
+    }
+    Ok(())
+}
+
+pub(super) fn error(code: &'static str, message: &'static str) -> ValidationError {
+    ValidationError::new(code).with_message(message.into())
+}
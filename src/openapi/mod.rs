@@ -0,0 +1,251 @@
+use actix_web::{HttpResponse, Result};
+use utoipa::{
+    openapi::{security::{ApiKey, ApiKeyValue, SecurityScheme}, Server},
+    Modify, OpenApi,
+};
+use crate::handlers;
+
+// Description of API v1, built from the `#[utoipa::path]` annotations on the
+// handlers. Every route registered in `api::v1` must be listed here; the test
+// below fails when the two drift apart.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Rust E-Commerce API",
+        description = "Storefront and admin API. Errors are application/problem+json bodies.",
+        version = "1",
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        handlers::auth::register,
+        handlers::auth::login,
+        handlers::auth::logout,
+        handlers::auth::me,
+        handlers::products::get_products,
+        handlers::products::create_product,
+        handlers::products::search_products,
+        handlers::products::get_product,
+        handlers::products::update_product,
+        handlers::products::delete_product,
+        handlers::products::restore_product,
+        handlers::variants::get_product_variants,
+        handlers::variants::create_variant,
+        handlers::images::get_product_images,
+        handlers::images::upload_product_image,
+        handlers::images::reorder_product_images,
+        handlers::images::delete_product_image,
+        handlers::categories::get_product_categories,
+        handlers::categories::set_product_categories,
+        handlers::tags::get_product_tags,
+        handlers::tags::set_product_tags,
+        handlers::variants::get_option_types,
+        handlers::variants::create_option_type,
+        handlers::variants::create_option_value,
+        handlers::variants::update_variant,
+        handlers::variants::delete_variant,
+        handlers::categories::get_categories,
+        handlers::categories::create_category,
+        handlers::categories::get_category_tree,
+        handlers::categories::get_archived_categories,
+        handlers::categories::update_category,
+        handlers::categories::delete_category,
+        handlers::categories::restore_category,
+        handlers::categories::get_category_products,
+        handlers::tags::list_tags,
+        handlers::tags::get_tag_products,
+        handlers::tax::get_tax_classes,
+        handlers::tax::create_tax_class,
+        handlers::tax::delete_tax_class,
+        handlers::tax::get_tax_rates,
+        handlers::tax::create_tax_rate,
+        handlers::tax::update_tax_rate,
+        handlers::tax::delete_tax_rate,
+        handlers::shipping::get_shipping_methods,
+        handlers::shipping::create_shipping_method,
+        handlers::shipping::update_shipping_method,
+        handlers::shipping::delete_shipping_method,
+        handlers::coupons::get_coupons,
+        handlers::coupons::create_coupon,
+        handlers::coupons::update_coupon,
+        handlers::coupons::delete_coupon,
+        handlers::cart::get_cart,
+        handlers::cart::add_to_cart,
+        handlers::cart::clear_cart,
+        handlers::cart::apply_coupon,
+        handlers::cart::remove_coupon,
+        handlers::shipping::quote_shipping,
+        handlers::cart::update_cart_item,
+        handlers::cart::remove_from_cart,
+        handlers::orders::create_order,
+        handlers::orders::get_orders,
+        handlers::orders::get_order,
+        handlers::orders::update_order_status,
+        handlers::orders::cancel_order,
+        handlers::payments::pay_order,
+        handlers::payments::get_order_payments,
+        handlers::orders::get_my_orders,
+        handlers::payments::payment_webhook,
+        handlers::addresses::get_my_addresses,
+        handlers::addresses::create_my_address,
+        handlers::addresses::update_my_address,
+        handlers::addresses::delete_my_address,
+        openapi_json,
+        api_docs,
+    ),
+    modifiers(&SessionCookie),
+)]
+pub struct ApiDoc;
+
+// The operations v2 adds or changes
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        handlers::products::get_products_page,
+        handlers::categories::get_categories_page,
+        handlers::orders::get_orders_page,
+        handlers::orders::get_my_orders_page,
+        openapi_json_v2,
+    ),
+)]
+struct ApiDocV2;
+
+// Description of API v2: its own operations, plus the v1 ones it still serves
+pub fn v2_spec() -> utoipa::openapi::OpenApi {
+    let v1 = ApiDoc::openapi();
+    let mut spec = ApiDocV2::openapi();
+    spec.info = v1.info.clone();
+    spec.info.version = "2".to_string();
+    // Operations already present are kept, so the v2 ones win
+    spec.merge(v1);
+    spec.servers = Some(vec![Server::new("/api/v2")]);
+    spec
+}
+
+// Signed-in requests are authenticated by the session cookie set on login
+struct SessionCookie;
+
+impl Modify for SessionCookie {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("id"))),
+        );
+    }
+}
+
+// The OpenAPI 3 document for v1
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "docs",
+    responses(
+        (status = 200, description = "This document", content_type = "application/json"),
+    ),
+)]
+pub async fn openapi_json() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(ApiDoc::openapi()))
+}
+
+// The OpenAPI 3 document for v2
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "docs",
+    responses(
+        (status = 200, description = "This document", content_type = "application/json"),
+    ),
+)]
+pub async fn openapi_json_v2() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(v2_spec()))
+}
+
+// Browsable API reference
+#[utoipa::path(
+    get,
+    path = "/docs",
+    tag = "docs",
+    responses(
+        (status = 200, description = "Swagger UI for this API", content_type = "text/html"),
+    ),
+)]
+pub async fn api_docs() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("../../templates/api_docs.html")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    
+    use super::*;
+    
+    type Operations = BTreeSet<(String, String)>;
+    
+    // (method, path) for each route registered by a version's function in
+    // src/api/mod.rs, including the routes it picks up by calling another
+    // version's function
+    fn registered_routes(version: &str) -> Operations {
+        let source = include_str!("../api/mod.rs");
+        let mut routes = BTreeSet::new();
+        let mut in_version = false;
+        for line in source.lines() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("pub fn ") {
+                in_version = name.starts_with(&format!("{}(", version));
+            } else if !in_version {
+                continue;
+            } else if let Some(rest) = line.strip_prefix(".route(\"") {
+                let Some((path, rest)) = rest.split_once('"') else { continue };
+                let Some(method) = rest.split_once("web::").and_then(|(_, r)| r.split_once('(')) else { continue };
+                routes.insert((method.0.to_string(), path.to_string()));
+            } else if let Some(inner) = line.strip_suffix("(cfg);") {
+                routes.extend(registered_routes(inner));
+            }
+        }
+        routes
+    }
+    
+    fn documented_operations(spec: &utoipa::openapi::OpenApi) -> Operations {
+        let mut documented = BTreeSet::new();
+        for (path, item) in &spec.paths.paths {
+            let operations = [
+                ("get", &item.get),
+                ("post", &item.post),
+                ("put", &item.put),
+                ("patch", &item.patch),
+                ("delete", &item.delete),
+            ];
+            for (method, operation) in operations {
+                if operation.is_some() {
+                    documented.insert((method.to_string(), path.clone()));
+                }
+            }
+        }
+        documented
+    }
+    
+    fn assert_in_sync(registered: Operations, documented: Operations) {
+        assert!(!registered.is_empty());
+        let undocumented: Vec<_> = registered.difference(&documented).collect();
+        let unrouted: Vec<_> = documented.difference(&registered).collect();
+        assert!(undocumented.is_empty(), "routes missing from the spec: {:?}", undocumented);
+        assert!(unrouted.is_empty(), "documented operations with no route: {:?}", unrouted);
+    }
+    
+    #[test]
+    fn spec_matches_registered_routes() {
+        assert_in_sync(registered_routes("v1"), documented_operations(&ApiDoc::openapi()));
+        assert_in_sync(registered_routes("v2"), documented_operations(&v2_spec()));
+    }
+    
+    #[test]
+    fn v2_spec_describes_its_own_operations() {
+        let spec = v2_spec();
+        let categories = spec.paths.paths["/categories"].get.as_ref().unwrap();
+        assert_eq!(categories.operation_id.as_deref(), Some("get_categories_page"));
+        let products = spec.paths.paths["/products"].get.as_ref().unwrap();
+        assert_eq!(products.operation_id.as_deref(), Some("get_products_page"));
+    }
+}
@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use rust_decimal::Decimal;
+use subtle::ConstantTimeEq;
+use crate::errors::{Result, AppError};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// A payment opened at the provider for an order
+#[derive(Debug, Clone)]
+pub struct PaymentIntent {
+    // The provider's id for the intent
+    pub reference: String,
+    // Lets the shopper's browser confirm the payment directly with the provider
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureOutcome {
+    Captured,
+    Declined(String),
+}
+
+// Notifications the provider sends about an intent
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    PaymentSucceeded { reference: String },
+    PaymentFailed { reference: String, reason: String },
+    Refunded { reference: String },
+}
+
+// A payment gateway. Futures are boxed so the provider can be chosen at
+// startup and shared through AppState as a trait object.
+pub trait PaymentProvider: Send + Sync {
+    // Stored with each payment to say which provider holds it
+    fn name(&self) -> &'static str;
+    
+    fn create_intent(&self, order_id: i64, amount: Decimal) -> BoxFuture<'_, Result<PaymentIntent>>;
+    
+    // Charge the intent using a payment method token from the shopper's browser
+    fn capture<'a>(
+        &'a self,
+        reference: &'a str,
+        payment_method: &'a str,
+        amount: Decimal,
+    ) -> BoxFuture<'a, Result<CaptureOutcome>>;
+    
+    fn refund<'a>(&'a self, reference: &'a str, amount: Decimal) -> BoxFuture<'a, Result<()>>;
+    
+    // Check a webhook's signature and decode it
+    fn verify_webhook(&self, signature: Option<&str>, payload: &[u8]) -> Result<WebhookEvent>;
+}
+
+// Pick the provider named by PAYMENT_PROVIDER; only the fake is built in.
+// There is no default, so a deployment never takes fake payments by accident.
+pub fn provider_from_env() -> Box<dyn PaymentProvider> {
+    let provider = std::env::var("PAYMENT_PROVIDER").expect("PAYMENT_PROVIDER must be set");
+    match provider.as_str() {
+        "fake" => {
+            let secret = std::env::var("FAKE_PAYMENTS_WEBHOOK_SECRET")
+                .ok()
+                .filter(|s| !s.trim().is_empty())
+                .expect("FAKE_PAYMENTS_WEBHOOK_SECRET must be set for the fake payment provider");
+            Box::new(FakeProvider::new(secret))
+        },
+        other => panic!("Unknown PAYMENT_PROVIDER: {}", other),
+    }
+}
+
+// Payment method token the fake provider always declines
+pub const FAKE_DECLINED_CARD: &str = "fake_card_declined";
+
+// In-process provider for tests and local development. Every capture
+// succeeds except with `FAKE_DECLINED_CARD`; webhooks are JSON events signed
+// by sending the shared secret as the signature.
+pub struct FakeProvider {
+    webhook_secret: String,
+}
+
+impl FakeProvider {
+    pub fn new(webhook_secret: String) -> Self {
+        FakeProvider { webhook_secret }
+    }
+}
+
+impl PaymentProvider for FakeProvider {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+    
+    fn create_intent(&self, order_id: i64, _amount: Decimal) -> BoxFuture<'_, Result<PaymentIntent>> {
+        Box::pin(async move {
+            let id = uuid::Uuid::new_v4().simple().to_string();
+            Ok(PaymentIntent {
+                reference: format!("fake_pi_{}_{}", order_id, id),
+                client_secret: Some(format!("fake_secret_{}", id)),
+            })
+        })
+    }
+    
+    fn capture<'a>(
+        &'a self,
+        _reference: &'a str,
+        payment_method: &'a str,
+        _amount: Decimal,
+    ) -> BoxFuture<'a, Result<CaptureOutcome>> {
+        Box::pin(async move {
+            if payment_method == FAKE_DECLINED_CARD {
+                Ok(CaptureOutcome::Declined("Card declined".to_string()))
+            } else {
+                Ok(CaptureOutcome::Captured)
+            }
+        })
+    }
+    
+    fn refund<'a>(&'a self, _reference: &'a str, _amount: Decimal) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { Ok(()) })
+    }
+    
+    fn verify_webhook(&self, signature: Option<&str>, payload: &[u8]) -> Result<WebhookEvent> {
+        // Compared in constant time, so the secret can't be guessed byte by byte
+        let signature = signature.unwrap_or_default().as_bytes();
+        if !bool::from(signature.ct_eq(self.webhook_secret.as_bytes())) {
+            return Err(AppError::Unauthorized);
+        }
+        serde_json::from_slice(payload)
+            .map_err(|e| AppError::BadRequest(format!("Invalid webhook payload: {}", e)))
+    }
+}
@@ -0,0 +1,65 @@
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    error::InternalError,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+};
+
+// Header carrying the request id, both ways
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+// The id of the request being handled, if called while handling one
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+// Middleware giving every request an id. A sensible id sent by the client
+// or a proxy is kept, otherwise a new one is made. The id is echoed in the
+// response header and is available to error responses through `current`.
+pub async fn request_id(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let id = req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    
+    let header_value = HeaderValue::from_str(&id).ok();
+    // Also on the request, so the access log can show it
+    if let Some(value) = header_value.clone() {
+        req.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+    
+    let response = REQUEST_ID.scope(id.clone(), next.call(req)).await;
+    match response {
+        Ok(response) => {
+            let mut response = response.map_into_boxed_body();
+            if let Some(value) = header_value {
+                response.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+            }
+            Ok(response)
+        },
+        // Errors from inner middleware would otherwise be rendered after the
+        // id has gone out of scope
+        Err(e) => {
+            let mut rendered = REQUEST_ID.sync_scope(id, || e.error_response());
+            if let Some(value) = header_value {
+                rendered.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+            }
+            Err(InternalError::from_response(e, rendered).into())
+        },
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
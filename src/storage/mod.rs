@@ -0,0 +1,101 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::{errors::{Result, AppError}, payments::BoxFuture};
+
+// Where uploaded files are kept. Keys are slash-separated relative paths
+// such as `products/12/<uuid>/large.jpg`; an object store can use them as
+// object names unchanged.
+pub trait Storage: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>, content_type: &'a str) -> BoxFuture<'a, Result<()>>;
+    
+    // Removing a key that doesn't exist is not an error
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>>;
+    
+    // Public URL the file is served from
+    fn url(&self, key: &str) -> String;
+}
+
+// Pick the backend named by STORAGE_BACKEND; only the local filesystem is built in
+pub fn storage_from_env() -> Box<dyn Storage> {
+    match std::env::var("STORAGE_BACKEND").as_deref() {
+        Ok("local") | Err(_) => {
+            let (dir, url) = upload_location();
+            Box::new(LocalStorage::new(dir, url))
+        },
+        Ok(other) => panic!("Unknown STORAGE_BACKEND: {}", other),
+    }
+}
+
+// Directory local uploads are written to and the URL path main.rs serves it
+// at, from UPLOAD_DIR and UPLOAD_URL
+pub fn upload_location() -> (String, String) {
+    let dir = std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "./static/uploads".to_string());
+    let url = std::env::var("UPLOAD_URL").unwrap_or_else(|_| "/static/uploads".to_string());
+    (dir, url.trim_end_matches('/').to_string())
+}
+
+// Files in a directory on this machine, served by the app's static file
+// handler at the URL from upload_location.
+pub struct LocalStorage {
+    root: PathBuf,
+    base_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>, base_url: impl Into<String>) -> Self {
+        LocalStorage {
+            root: root.into(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+    
+    // Resolve a key inside the root, refusing anything that could escape it
+    fn path_for(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(AppError::BadRequest(format!("Invalid storage key: {}", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>, _content_type: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = self.path_for(key)?;
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await.map_err(|e| storage_error(key, e))?;
+            }
+            tokio::fs::write(&path, bytes).await.map_err(|e| storage_error(key, e))
+        })
+    }
+    
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = self.path_for(key)?;
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {},
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => return Err(storage_error(key, e)),
+            }
+            // Tidy up directories left empty; fails harmlessly while other files remain
+            let mut dir = path.parent();
+            while let Some(d) = dir.filter(|d| *d != self.root) {
+                if tokio::fs::remove_dir(d).await.is_err() {
+                    break;
+                }
+                dir = d.parent();
+            }
+            Ok(())
+        })
+    }
+    
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+}
+
+fn storage_error(key: &str, e: std::io::Error) -> AppError {
+    log::error!("Storage operation on {} failed: {}", key, e);
+    AppError::InternalError
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use sqlx::SqliteConnection;
+use crate::{models::TaxRate, errors::Result};
+
+// Class used for products whose category doesn't name one
+pub const STANDARD_TAX_CLASS_ID: i64 = 1;
+
+// Whether catalogue prices already contain tax
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PricingMode {
+    // Tax is added on top of prices at checkout
+    Exclusive,
+    // Prices are what the shopper pays; tax is extracted from them
+    Inclusive,
+}
+
+impl PricingMode {
+    pub fn from_env() -> Self {
+        match std::env::var("PRICES_INCLUDE_TAX").as_deref() {
+            Ok("true") | Ok("1") => PricingMode::Inclusive,
+            _ => PricingMode::Exclusive,
+        }
+    }
+    
+    pub fn includes_tax(&self) -> bool {
+        *self == PricingMode::Inclusive
+    }
+}
+
+// A line amount split into its net, tax and gross parts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaxedAmount {
+    pub net: Decimal,
+    pub tax: Decimal,
+    pub gross: Decimal,
+}
+
+// Tax an amount at a percentage rate, rounding the tax to the cent
+pub fn apply_rate(amount: Decimal, rate: Decimal, mode: PricingMode) -> TaxedAmount {
+    match mode {
+        PricingMode::Exclusive => {
+            let tax = round_cents(amount * rate / Decimal::ONE_HUNDRED);
+            TaxedAmount { net: amount, tax, gross: amount + tax }
+        },
+        PricingMode::Inclusive => {
+            let tax = round_cents(amount * rate / (Decimal::ONE_HUNDRED + rate));
+            TaxedAmount { net: amount - tax, tax, gross: amount }
+        },
+    }
+}
+
+// Split an amount across lines in proportion to their values. Each share is
+// rounded to the cent and the last line absorbs the rounding difference, so
+// the shares always add back up to the amount.
+pub fn allocate(amount: Decimal, weights: &[Decimal]) -> Vec<Decimal> {
+    let total: Decimal = weights.iter().sum();
+    if total.is_zero() {
+        return vec![Decimal::ZERO; weights.len()];
+    }
+    
+    let mut shares: Vec<Decimal> = weights.iter()
+        .map(|w| round_cents(amount * w / total))
+        .collect();
+    let allocated: Decimal = shares.iter().sum();
+    if let Some(last) = shares.last_mut() {
+        *last += amount - allocated;
+    }
+    shares
+}
+
+// Rates that apply at a destination, by tax class. A rate for the region
+// replaces the country-wide rate of the same class.
+pub async fn rates_for_destination(
+    conn: &mut SqliteConnection,
+    country: Option<&str>,
+    region: Option<&str>,
+) -> Result<HashMap<i64, TaxRate>> {
+    let Some(country) = country else {
+        return Ok(HashMap::new());
+    };
+    
+    let rates = sqlx::query_as::<_, TaxRate>(
+        r#"
+        SELECT * FROM tax_rates
+        WHERE country = upper(?1) AND (region IS NULL OR region = ?2)
+        ORDER BY region IS NULL
+        "#
+    )
+    .bind(country.trim())
+    .bind(region.map(str::trim))
+    .fetch_all(conn)
+    .await?;
+    
+    let mut by_class = HashMap::new();
+    for rate in rates {
+        by_class.entry(rate.tax_class_id).or_insert(rate);
+    }
+    Ok(by_class)
+}
+
+// The tax class a product is sold under, through its category
+pub async fn tax_class_for_product(conn: &mut SqliteConnection, product_id: i32) -> Result<i64> {
+    let class_id: Option<i64> = sqlx::query_scalar(
+        r#"
+        SELECT c.tax_class_id
+        FROM products p
+        LEFT JOIN categories c ON c.id = p.category_id
+        WHERE p.id = ?1
+        "#
+    )
+    .bind(product_id)
+    .fetch_optional(conn)
+    .await?
+    .flatten();
+    
+    Ok(class_id.unwrap_or(STANDARD_TAX_CLASS_ID))
+}
+
+fn round_cents(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    
+    #[test]
+    fn exclusive_and_inclusive_modes_split_the_same_way() {
+        let exclusive = apply_rate(dec!(100.00), dec!(20), PricingMode::Exclusive);
+        assert_eq!(exclusive, TaxedAmount { net: dec!(100.00), tax: dec!(20.00), gross: dec!(120.00) });
+        
+        let inclusive = apply_rate(dec!(120.00), dec!(20), PricingMode::Inclusive);
+        assert_eq!(inclusive, TaxedAmount { net: dec!(100.00), tax: dec!(20.00), gross: dec!(120.00) });
+        
+        let fractional = apply_rate(dec!(19.99), dec!(8.875), PricingMode::Exclusive);
+        assert_eq!(fractional.tax, dec!(1.77));
+    }
+    
+    #[test]
+    fn allocation_adds_back_up_to_the_amount() {
+        let shares = allocate(dec!(10.00), &[dec!(33.33), dec!(33.33), dec!(33.34)]);
+        assert_eq!(shares.iter().sum::<Decimal>(), dec!(10.00));
+        assert_eq!(shares[0], dec!(3.33));
+        
+        assert_eq!(allocate(dec!(5), &[Decimal::ZERO]), vec![Decimal::ZERO]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix_files::Files;
+use actix_session::{SessionMiddleware, storage::CookieSessionStore};
+use actix_web::{
+    body::MessageBody, cookie::{Cookie, Key}, dev::{Service, ServiceResponse}, middleware::from_fn,
+    test, web, App,
+};
+use actix_http::Request;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use crate::{api, auth::ensure_admin, idempotency, payments::{FakeProvider, PaymentProvider}, request_id, storage::LocalStorage, tax::PricingMode, AppState};
+
+// A migrated SQLite database in a temp file, removed on drop
+pub struct TestDb {
+    pub pool: SqlitePool,
+    path: PathBuf,
+}
+
+impl TestDb {
+    pub async fn new() -> Self {
+        let path = std::env::temp_dir()
+            .join(format!("actx_shop_test_{}.db", uuid::Uuid::new_v4()));
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .busy_timeout(Duration::from_secs(30));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(8)
+            .connect_with(options)
+            .await
+            .expect("Failed to open test database");
+        
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .expect("Failed to run migrations");
+        
+        TestDb { pool, path }
+    }
+    
+    // Where the test app stores uploads, served under `/uploads`
+    pub fn uploads(&self) -> PathBuf {
+        self.path.with_extension("uploads")
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+        let _ = std::fs::remove_dir_all(self.uploads());
+    }
+}
+
+// The API over a test database, with every version mounted and wrapped like
+// main.rs does it. Uploads go to a temp directory and payments to the fake
+// provider.
+pub async fn test_app(
+    db: &TestDb,
+) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error> {
+    test_app_with_payments(db, Box::new(FakeProvider::new("test-secret".to_string()))).await
+}
+
+// `test_app` taking its payments through the given provider
+pub async fn test_app_with_payments(
+    db: &TestDb,
+    payments: Box<dyn PaymentProvider>,
+) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error> {
+    // Created up front so the file service has a directory to serve, as in main.rs
+    std::fs::create_dir_all(db.uploads()).expect("Failed to create the uploads directory");
+    let state = web::Data::new(AppState {
+        db: db.pool.clone(),
+        pricing: PricingMode::Exclusive,
+        payments,
+        storage: Box::new(LocalStorage::new(db.uploads(), "/uploads")),
+    });
+    test::init_service(
+        App::new()
+            .app_data(state)
+            .configure(api::extractor_config)
+            .wrap(from_fn(idempotency::idempotency))
+            .wrap(SessionMiddleware::builder(CookieSessionStore::default(), Key::generate()).cookie_secure(false).build())
+            .wrap(from_fn(request_id::request_id))
+            .service(web::scope("/api/v1").configure(api::v1))
+            .service(web::scope("/api/v2").configure(api::v2))
+            .service(web::scope("/api").configure(api::v1))
+            .service(Files::new("/uploads", db.uploads()))
+    ).await
+}
+
+// Sign in through the API and return the session cookie
+pub async fn sign_in<S, B>(app: &S, email: &str, password: &str) -> Cookie<'static>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let response = test::call_service(
+        app,
+        test::TestRequest::post()
+            .uri("/api/v1/auth/login")
+            .set_json(serde_json::json!({ "email": email, "password": password }))
+            .to_request(),
+    ).await;
+    assert!(response.status().is_success(), "sign in as {} failed: {}", email, response.status());
+    session_cookie(&response)
+}
+
+// The session cookie a response set
+pub fn session_cookie<B>(response: &ServiceResponse<B>) -> Cookie<'static> {
+    response.response()
+        .cookies()
+        .find(|c| c.name() == "id")
+        .expect("response set no session cookie")
+        .into_owned()
+}
+
+// Register a customer through the API and return their session cookie
+pub async fn register<S, B>(app: &S, name: &str, email: &str) -> Cookie<'static>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let response = test::call_service(
+        app,
+        test::TestRequest::post()
+            .uri("/api/v1/auth/register")
+            .set_json(serde_json::json!({ "name": name, "email": email, "password": "long enough" }))
+            .to_request(),
+    ).await;
+    assert!(response.status().is_success(), "registering {} failed: {}", email, response.status());
+    session_cookie(&response)
+}
+
+// Seed the admin account and sign in as it
+pub async fn admin_cookie<S, B>(app: &S, db: &TestDb) -> Cookie<'static>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    ensure_admin(&db.pool, "admin@example.com", Some("admin password")).await.unwrap();
+    sign_in(app, "admin@example.com", "admin password").await
+}
+
+// A product priced at 10.00 with the given stock
+pub async fn insert_product(db: &SqlitePool, name: &str, stock: i32) -> i32 {
+    sqlx::query("INSERT INTO products (name, price_cents, stock_quantity) VALUES (?1, 1000, ?2)")
+        .bind(name)
+        .bind(stock)
+        .execute(db)
+        .await
+        .unwrap()
+        .last_insert_rowid() as i32
+}
+
+// Add an item to the cart of the given session, or of a new guest session,
+// and return the session cookie to keep using
+pub async fn add_to_cart<S, B>(app: &S, cookie: Option<Cookie<'static>>, item: serde_json::Value) -> Cookie<'static>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let mut request = test::TestRequest::post().uri("/api/v1/cart").set_json(item);
+    if let Some(cookie) = cookie.clone() {
+        request = request.cookie(cookie);
+    }
+    let response = test::call_service(app, request.to_request()).await;
+    assert!(response.status().is_success(), "adding to the cart failed: {}", response.status());
+    match cookie {
+        Some(cookie) => response.response().cookies().find(|c| c.name() == "id").map(|c| c.into_owned()).unwrap_or(cookie),
+        None => session_cookie(&response),
+    }
+}
+
+// A blank PNG of the given size
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(image::RgbImage::new(width, height))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    png
+}
+
+// A multipart upload of `file` as an image of the product
+pub fn image_upload(product_id: i32, admin: &Cookie<'static>, file: &[u8]) -> Request {
+    let mut body = b"--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"image.png\"\r\nContent-Type: image/png\r\n\r\n".to_vec();
+    body.extend(file);
+    body.extend(b"\r\n--boundary--\r\n");
+    test::TestRequest::post()
+        .uri(&format!("/api/v1/products/{}/images", product_id))
+        .cookie(admin.clone())
+        .insert_header(("Content-Type", "multipart/form-data; boundary=boundary"))
+        .set_payload(body)
+        .to_request()
+}